@@ -1,8 +1,6 @@
 use crate::app::{
-    audio::{AudioDecoder, FilterChain},
-    config::Config,
+    config::{Config, Keybind, midi::MidiAction},
     gui::{KeybindTarget, Theme},
-    keybind_listener::KeybindListener,
 };
 use cpal::{
     Device,
@@ -11,24 +9,26 @@
 use iced::widget::svg;
 use serde::{Deserialize, Serialize};
 use std::{
-    sync::{Arc, Mutex, atomic::AtomicU64},
+    sync::mpsc::Receiver,
     time::{Duration, Instant},
 };
 
+pub use self::soundboard::Soundboard;
+
 pub mod audio;
 pub mod config;
+pub mod control;
 pub mod gui;
 pub mod keybind_listener;
+mod limits;
+pub mod macro_runner;
+pub mod midi_listener;
+pub mod scripting;
+mod soundboard;
+mod variants;
 
 pub struct App {
-    _keep_alive: audio::KeepAlive,
-    keybind_listener: KeybindListener,
-    playing_sound: Option<PlayingSound>,
-    target_sample_rate: u32,
-    decoder: Arc<Mutex<Option<AudioDecoder>>>,
-    decoder_pos: Arc<AtomicU64>,
-    config: Config,
-    filter_chain: Arc<Mutex<FilterChain>>,
+    soundboard: Soundboard,
 
     // GUI - Audio Settings
     input_devices: Vec<DeviceOption>,
@@ -43,7 +43,20 @@ pub struct App {
     page: Page,
     search: String,
     selected_preset: usize,
+    selected_macro: usize,
     recording_keybind: Option<KeybindTarget>,
+    /// Shows the MIDI mappings of sounds, presets and filter properties.
+    midi_map_mode: bool,
+    learning_midi: Option<MidiAction>,
+}
+
+/// Audio devices present on the system and the ones selected in the config.
+pub struct Devices {
+    pub input_devices: Vec<DeviceOption>,
+    pub output_devices: Vec<DeviceOption>,
+    pub mic: DeviceOption,
+    pub out: DeviceOption,
+    pub virtual_out: DeviceOption,
 }
 
 #[derive(Clone, Debug)]
@@ -84,21 +97,152 @@ pub struct Svgs {
 pub enum Page {
     Sounds,
     FilterChain,
+    Macros,
     Settings,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Sound {
-    name: String,
-    path: String,
+    pub name: String,
+    /// Empty for groups, which play one of their `variants` instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
     #[serde(
         default = "Sound::default_volume",
         skip_serializing_if = "Sound::is_default_volume"
     )]
-    volume: f32,
+    pub volume: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keybind: Option<Keybind>,
+    #[serde(default, skip_serializing_if = "TriggerMode::is_default")]
+    pub trigger_mode: TriggerMode,
+    /// Starting a sound stops the other sounds with the same choke group, like all music beds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub choke_group: Option<String>,
+    /// Seconds after a trigger in which the sound can't be triggered again.
+    #[serde(default, skip_serializing_if = "Sound::is_zero")]
+    pub cooldown: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_minute: Option<u32>,
+    /// How much faster the sound plays, which also makes it higher.
+    #[serde(default = "Sound::one", skip_serializing_if = "Sound::is_one")]
+    pub speed: f32,
+    /// Semitones to shift the pitch by, without changing the speed.
+    #[serde(default, skip_serializing_if = "Sound::is_zero")]
+    pub pitch: f32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reverse: bool,
+    /// The filter preset the sound goes through before the one of all sounds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter_preset: Option<String>,
+    #[serde(default, skip_serializing_if = "Route::is_default")]
+    pub route: Route,
+    /// Makes the sound a group of files, every trigger plays one of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<Variant>,
+    #[serde(default, skip_serializing_if = "Selection::is_default")]
+    pub selection: Selection,
+    /// How many semitones every trigger may randomly shift the pitch up or down.
+    #[serde(default, skip_serializing_if = "Sound::is_zero")]
+    pub pitch_variance: f32,
+    /// How much every trigger may randomly change the volume, `0.1` is up to 10% either way.
+    #[serde(default, skip_serializing_if = "Sound::is_zero")]
+    pub volume_variance: f32,
+}
+
+/// One of the files of a sound group.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Variant {
+    pub path: String,
+    /// How likely the variant is compared to the others, with `selection = "weighted"`.
+    #[serde(default = "Sound::one", skip_serializing_if = "Sound::is_one")]
+    pub weight: f32,
+}
+
+/// How a sound group picks the variant to play.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    #[default]
+    Uniform,
+    /// Following the weights of the variants.
+    Weighted,
+    /// Every variant once in a random order, then again, never the same one twice in a row.
+    Shuffle,
+}
+
+/// Changes to how a sound plays for a single trigger, from a macro or a held modifier key.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Playback {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    /// Semitones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverse: Option<bool>,
+}
+
+/// What triggering a sound does, depending on what is playing.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMode {
+    /// Replaces whatever is playing, starting the sound from the beginning.
+    #[default]
+    Restart,
+    /// Stops the sound if it is playing, plays it otherwise.
+    Toggle,
+    /// Plays the sound until its keybind is released.
+    Hold,
+    /// Plays the sound on top of whatever is playing.
+    Overlap,
+    /// Does nothing while the sound is playing.
+    IgnoreIfPlaying,
+}
+
+/// Which outputs a sound plays on.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Route {
+    #[default]
+    Both,
+    /// Only on the local output, to audition a sound without anyone else hearing it.
+    Local,
+    /// Only on the virtual output.
+    Virtual,
 }
 
 impl Sound {
+    pub fn new(name: String, path: String) -> Self {
+        Self {
+            name,
+            path,
+            volume: Self::default_volume(),
+            keybind: None,
+            trigger_mode: TriggerMode::default(),
+            choke_group: None,
+            cooldown: 0.0,
+            max_per_minute: None,
+            speed: 1.0,
+            pitch: 0.0,
+            reverse: false,
+            filter_preset: None,
+            route: Route::default(),
+            variants: Vec::new(),
+            selection: Selection::default(),
+            pitch_variance: 0.0,
+            volume_variance: 0.0,
+        }
+    }
+
+    /// The files the sound may play, a group ignores its `path`.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        let path = self.variants.is_empty().then_some(self.path.as_str());
+        path.into_iter()
+            .filter(|path| !path.is_empty())
+            .chain(self.variants.iter().map(|variant| variant.path.as_str()))
+    }
+
     #[inline]
     const fn default_volume() -> f32 {
         1.0
@@ -108,56 +252,156 @@ const fn default_volume() -> f32 {
     const fn is_default_volume(volume: &f32) -> bool {
         *volume == Self::default_volume()
     }
+
+    #[inline]
+    const fn one() -> f32 {
+        1.0
+    }
+
+    #[inline]
+    const fn is_one(value: &f32) -> bool {
+        *value == 1.0
+    }
+
+    #[inline]
+    const fn is_zero(value: &f32) -> bool {
+        *value == 0.0
+    }
+}
+
+impl Selection {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Playback {
+    pub fn apply(&self, sound: &mut Sound) {
+        if let Some(speed) = self.speed {
+            sound.speed = speed;
+        }
+        if let Some(pitch) = self.pitch {
+            sound.pitch = pitch;
+        }
+        if let Some(reverse) = self.reverse {
+            sound.reverse = reverse;
+        }
+    }
+
+    /// These changes, with the ones `other` makes on top.
+    pub fn and(self, other: Self) -> Self {
+        Self {
+            speed: other.speed.or(self.speed),
+            pitch: other.pitch.or(self.pitch),
+            reverse: other.reverse.or(self.reverse),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl TriggerMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Route {
+    pub const ALL: [Self; 3] = [Self::Both, Self::Local, Self::Virtual];
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    #[inline]
+    pub fn plays_locally(self) -> bool {
+        self != Self::Virtual
+    }
+
+    #[inline]
+    pub fn plays_virtually(self) -> bool {
+        self != Self::Local
+    }
+
+    /// The outputs both routes play on, `None` if they have none in common.
+    pub fn shared(self, other: Self) -> Option<Self> {
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (Self::Both, route) | (route, Self::Both) => Some(route),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Both => "Both Outputs",
+            Self::Local => "Local Only",
+            Self::Virtual => "Virtual Only",
+        })
+    }
 }
 
 struct PlayingSound {
+    /// Matches the id the audio threads report the sound under.
+    id: u64,
     randomly_triggered: bool,
-    duration: Duration,
+    /// `None` while the duration of a file without a frame count in its header is being scanned.
+    duration: Option<Duration>,
+    /// Gets the duration once, when the scan is done.
+    duration_scan: Option<Receiver<Option<Duration>>>,
     sound: Sound,
+    /// The playback rate the sound was varied by, kept so seeking doesn't change it.
+    pitch: f32,
+    /// The sounds mixed by a macro or by overlapping, `sound` only names them. Empty for a single
+    /// sound.
+    layers: Vec<String>,
 }
 
-impl App {
-    fn is_monitor_source(device: &Device) -> bool {
-        device.id().is_ok_and(|id| id.id().ends_with(".monitor"))
+impl PlayingSound {
+    #[inline]
+    fn is_layered(&self) -> bool {
+        !self.layers.is_empty()
     }
 
-    fn device_label(device: &Device) -> String {
-        device
-            .description()
-            .map(|desc| desc.name().to_string())
-            .unwrap_or_else(|_| device.to_string())
+    /// Whether the sound named `name` is playing, on its own or in the mix.
+    fn plays(&self, name: &str) -> bool {
+        self.sound.name == name || self.layers.iter().any(|layer| layer == name)
     }
+}
 
-    fn resolve_device(devices: &[DeviceOption], config_name: &str, kind: &str) -> DeviceOption {
-        let fallback = || devices.first().cloned().expect("No devices present");
-        if config_name == "default" {
-            return fallback();
-        }
+impl Devices {
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let (input_devices, output_devices) = Self::available();
 
-        devices
-            .iter()
-            .find(|option| {
-                option.device.id().is_ok_and(|id| id.id() == config_name)
-                    || option
-                        .device
-                        .description()
-                        .is_ok_and(|desc| desc.name() == config_name)
-            })
+        let mic_device = Self::resolve_device(&input_devices, &config.input_device, "Input")?;
+        let out_device = Self::resolve_device(&output_devices, &config.output_device, "Output")?;
+
+        let virtual_out_device = Self::find(&output_devices, &config.virtual_output_device)
             .cloned()
-            .unwrap_or_else(|| {
-                tracing::warn!(
-                    "{kind} device \"{config_name}\" not found, using the system default"
-                );
-                fallback()
-            })
+            .ok_or_else(|| {
+                format!(
+                    "Could not find output device \"{}\", set virtual_output_device in {} to one \
+                     of the devices `keysounds devices` lists",
+                    config.virtual_output_device,
+                    App::config_file().display()
+                )
+            })?;
+
+        Ok(Self {
+            input_devices,
+            output_devices,
+            mic: mic_device,
+            out: out_device,
+            virtual_out: virtual_out_device,
+        })
     }
 
-    #[expect(clippy::new_without_default)]
-    pub fn new() -> App {
-        let start_instant = Instant::now();
-        let config = Self::load_config_result();
-        let decoder = Arc::new(Mutex::new(None));
-        let decoder_pos = Arc::new(AtomicU64::new(u64::MAX));
+    /// Input and output devices of the default host, each list starting with the system default.
+    pub fn available() -> (Vec<DeviceOption>, Vec<DeviceOption>) {
         let host = cpal::default_host();
 
         let default_input = host.default_input_device();
@@ -192,39 +436,64 @@ pub fn new() -> App {
                 .map(|device| DeviceOption::new(device.clone(), Self::device_label(&device))),
         );
 
-        let mic_device = Self::resolve_device(&input_devices, &config.input_device, "Input");
-        let out_device = Self::resolve_device(&output_devices, &config.output_device, "Output");
+        (input_devices, output_devices)
+    }
 
-        let virtual_out_device = output_devices
-            .iter()
-            .find(|option| {
-                option
+    /// Finds the device with the id or name `config_name`.
+    pub fn find<'a>(devices: &'a [DeviceOption], config_name: &str) -> Option<&'a DeviceOption> {
+        devices.iter().find(|option| {
+            option.device.id().is_ok_and(|id| id.id() == config_name)
+                || option
                     .device
-                    .id()
-                    .is_ok_and(|id| id.id() == config.virtual_output_device)
-                    || option
-                        .device
-                        .description()
-                        .is_ok_and(|desc| desc.name() == config.virtual_output_device)
-            })
+                    .description()
+                    .is_ok_and(|desc| desc.name() == config_name)
+        })
+    }
+
+    /// Whether `config_name` picks the system default device, an empty name does too.
+    pub fn is_default(config_name: &str) -> bool {
+        config_name.is_empty() || config_name == "default"
+    }
+
+    fn is_monitor_source(device: &Device) -> bool {
+        device.id().is_ok_and(|id| id.id().ends_with(".monitor"))
+    }
+
+    fn device_label(device: &Device) -> String {
+        device
+            .description()
+            .map(|desc| desc.name().to_string())
+            .unwrap_or_else(|_| device.to_string())
+    }
+
+    fn resolve_device(
+        devices: &[DeviceOption],
+        config_name: &str,
+        kind: &str,
+    ) -> Result<DeviceOption, String> {
+        if !Self::is_default(config_name) {
+            if let Some(device) = Self::find(devices, config_name) {
+                return Ok(device.clone());
+            }
+            tracing::warn!("{kind} device \"{config_name}\" not found, using the system default");
+        }
+        devices
+            .first()
             .cloned()
-            .unwrap_or_else(|| {
-                panic!(
-                    "Could not find output device '{}' in list:\n{:?}",
-                    config.virtual_output_device,
-                    output_devices
-                        .iter()
-                        .map(|option| format!("{} ({})", option.label, option.device.id().unwrap()))
-                        .collect::<Vec<_>>()
-                );
-            });
-
-        let (filter_chain, sample_rate, keep_alive) = Self::create_streams(
-            &mic_device.device,
-            &out_device.device,
-            &virtual_out_device.device,
-            Arc::clone(&decoder),
-            Arc::clone(&decoder_pos),
+            .ok_or_else(|| format!("There is no {} device", kind.to_lowercase()))
+    }
+}
+
+impl App {
+    pub fn new() -> Result<App, String> {
+        let start_instant = Instant::now();
+        let config = Self::load_config_result();
+        let devices = Devices::load(&config)?;
+        let soundboard = Soundboard::new(
+            config,
+            &devices.mic.device,
+            &devices.out.device,
+            &devices.virtual_out.device,
         );
 
         macro_rules! include_svg {
@@ -234,20 +503,13 @@ macro_rules! include_svg {
         }
 
         let app = App {
-            _keep_alive: keep_alive,
-            keybind_listener: KeybindListener::new(),
-            playing_sound: None,
-            target_sample_rate: sample_rate,
-            decoder,
-            decoder_pos,
-            config,
-            filter_chain,
+            soundboard,
 
-            input_devices,
-            output_devices,
-            mic_device,
-            out_device,
-            virtual_out_device,
+            input_devices: devices.input_devices,
+            output_devices: devices.output_devices,
+            mic_device: devices.mic,
+            out_device: devices.out,
+            virtual_out_device: devices.virtual_out,
 
             theme: Theme::default(),
             svgs: Svgs {
@@ -259,10 +521,13 @@ macro_rules! include_svg {
             page: Page::Sounds,
             search: String::new(),
             selected_preset: 0,
+            selected_macro: 0,
             recording_keybind: None,
+            midi_map_mode: false,
+            learning_midi: None,
         };
         tracing::info!("App startup time: {:?}", start_instant.elapsed());
-        app
+        Ok(app)
     }
 
     pub fn theme(&self) -> Theme {
@@ -270,6 +535,6 @@ pub fn theme(&self) -> Theme {
     }
 
     pub fn gui_scale(&self) -> f32 {
-        self.config.gui_scale
+        self.soundboard.config.gui_scale
     }
 }
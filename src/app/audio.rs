@@ -1,51 +1,340 @@
-use crate::app::{App, PlayingSound, Sound};
+use crate::app::{
+    PlayingSound, Route, Sound, Soundboard, TriggerMode,
+    config::{Latency, macros::Layer},
+    scripting,
+    variants::Variation,
+};
 use cpal::traits::{DeviceTrait, StreamTrait};
 use cpal::{Device, Stream};
 use ringbuf::HeapRb;
-use ringbuf::traits::{Consumer, Observer, Producer, Split};
-use std::sync::{
-    Arc, Mutex,
-    atomic::{AtomicU64, Ordering},
-};
-use std::thread;
-use std::time::Duration;
+use ringbuf::traits::{Producer, Split};
+use std::mem;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
 
+pub use cache::SoundCache;
 pub use decoder::AudioDecoder;
+pub use diagnostics::Diagnostics;
+pub use engine::{EngineConfig, OfflineEngine};
 pub use filter::{AudioProcessor, FilterChain, ProcessContext};
+pub use mix::{Levels, MixControl};
+pub use playhead::{Playhead, QueuedSound};
+pub use source::{Filtered, Layers, Pitched, Reversed, SoundSource};
+pub use stretch::Stretched;
+pub use wav::WavWriter;
 
+mod cache;
 mod decoder;
+mod diagnostics;
+mod downmix;
+mod drift;
+mod engine;
 mod filter;
+mod mix;
+mod playhead;
+mod sample_format;
+mod source;
+mod stretch;
+mod wav;
+
+use self::{cache::CachedSoundPlayer, drift::DriftCompensator};
 
 pub(super) type KeepAlive = (Stream, Stream, Stream);
 
-const CHANNELS: usize = 2;
-const BLOCK_FRAMES: usize = 512;
-const BLOCK_SAMPLES: usize = BLOCK_FRAMES * CHANNELS;
-const RING_CAPACITY: usize = BLOCK_SAMPLES * 4;
+/// The engine works in interleaved stereo.
+pub const CHANNELS: usize = 2;
+
+/// The range of the `speed` of a sound.
+pub const MIN_SPEED: f32 = 0.1;
+pub const MAX_SPEED: f32 = 10.0;
+/// The most semitones the `pitch` of a sound shifts it by, either way.
+pub const MAX_SHIFT: f32 = 24.0;
+
+impl Soundboard {
+    /// Plays `sound`, or one of its variants when it is a group, as its [`TriggerMode`] says.
+    /// Returns the id the sound was queued under, `None` if it wasn't queued on its own.
+    pub(super) fn play_sound(&mut self, sound: Sound, randomly_triggered: bool) -> Option<u64> {
+        let playing = self
+            .playing_sound
+            .as_ref()
+            .is_some_and(|playing| playing.plays(&sound.name));
+        match sound.trigger_mode {
+            TriggerMode::Toggle if playing => {
+                self.stop_sound();
+                return None;
+            }
+            TriggerMode::IgnoreIfPlaying if playing => return None,
+            _ => {}
+        }
+        let now = Instant::now();
+        if !self.limits.allows(&sound, now) {
+            tracing::info!(
+                name = sound.name,
+                "Sound is held back by its cooldown or rate limit"
+            );
+            return None;
+        }
+
+        let trigger_mode = sound.trigger_mode;
+        let Variation {
+            sound: variant,
+            pitch,
+        } = self.variants.pick(&sound, &mut scripting::random);
+        let mut source = self.open_sound(&variant, pitch)?;
+        // a file that is missing or can't be decoded doesn't use up the limits
+        self.limits.record(&sound, now);
+        let sound = variant;
+        let name = sound.name.clone();
+        if trigger_mode == TriggerMode::Overlap {
+            source = match self.overlap_sound(&sound, source) {
+                Some(source) => source,
+                None => {
+                    self.notify_scripts(scripting::Event::SoundStarted(name));
+                    return None;
+                }
+            };
+        }
+        let id = self.queue_sound(sound, source, pitch, Vec::new(), randomly_triggered);
+        self.notify_scripts(scripting::Event::SoundStarted(name));
+        Some(id)
+    }
+
+    /// Mixes `source` into the sound that is still being decoded, stopping the sounds in the mix
+    /// that `sound` chokes. Gives `source` back if there is nothing to mix it into, or if the
+    /// playing sound is routed elsewhere.
+    fn overlap_sound(&mut self, sound: &Sound, source: SoundSource) -> Option<SoundSource> {
+        let Some(playing) = &mut self.playing_sound else {
+            return Some(source);
+        };
+        let mut decoder = self.decoder.lock().unwrap();
+        let Some(queued) = decoder.as_mut().filter(|queued| queued.id == playing.id) else {
+            return Some(source);
+        };
+        let chokes = |name: &str| {
+            sound.choke_group.is_some()
+                && self
+                    .config
+                    .sounds
+                    .iter()
+                    .any(|other| other.name == name && other.choke_group == sound.choke_group)
+        };
+        // a sound on other outputs can't share the mix, it would play on all of them
+        if queued.route != sound.route || (!playing.is_layered() && chokes(&playing.sound.name)) {
+            return Some(source);
+        }
+
+        let pos = Duration::from_nanos(self.playhead.pos_nanos(playing.id));
+        playing.duration = playing
+            .duration
+            .zip(source.total_duration())
+            .map(|(duration, overlapping)| duration.max(pos + overlapping));
+        let layered = playing.is_layered();
+        let empty = SoundSource::Layered(Layers::new(self.target_sample_rate));
+        let mut layers = match mem::replace(&mut queued.source, empty) {
+            SoundSource::Layered(layers) => layers,
+            current => {
+                let mut layers = Layers::new(self.target_sample_rate);
+                layers.push(current, Duration::ZERO);
+                layers
+            }
+        };
+        // the names of the layers are in the order they were pushed in
+        for idx in (0..playing.layers.len()).rev() {
+            if chokes(&playing.layers[idx]) {
+                layers.remove(idx);
+                playing.layers.remove(idx);
+            }
+        }
+        layers.push_now(source);
+        queued.source = SoundSource::Layered(layers);
+
+        if playing.layers.is_empty() && !layered {
+            playing.layers.push(playing.sound.name.clone());
+        }
+        playing.layers.push(sound.name.clone());
+        playing.sound.name = playing.layers.join(" + ");
+        None
+    }
+
+    /// Plays the sounds of `layers` mixed together, each with its own offset and volume.
+    pub(super) fn play_layers(&mut self, layers: &[Layer]) -> Result<(), String> {
+        let sounds = layers
+            .iter()
+            .map(|layer| {
+                let mut sound = self.find_sound(&layer.sound)?.clone();
+                if let Some(volume) = layer.volume {
+                    sound.volume = volume;
+                }
+                layer.playback.apply(&mut sound);
+                Ok(sound)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if let ([sound], [layer]) = (&sounds[..], layers)
+            && layer.offset_ms == 0
+        {
+            self.play_sound(sound.clone(), false);
+            return Ok(());
+        }
+
+        // the layers are mixed into one sound, which only plays where all of them may
+        let route = sounds
+            .iter()
+            .try_fold(Route::Both, |route, sound| route.shared(sound.route))
+            .ok_or(
+                "Layers routed only to the local and only to the virtual output can't be mixed",
+            )?;
+
+        let mut mixed = Layers::new(self.target_sample_rate);
+        let mut paths = Vec::new();
+        let mut names = Vec::new();
+        let now = Instant::now();
+        for (sound, layer) in sounds.iter().zip(layers) {
+            if !self.limits.allows(sound, now) {
+                tracing::info!(
+                    name = sound.name,
+                    "Layer is held back by its cooldown or rate limit"
+                );
+                continue;
+            }
+            let Variation {
+                sound: variant,
+                pitch,
+            } = self.variants.pick(sound, &mut scripting::random);
+            if let Some(source) = self.open_sound(&variant, pitch) {
+                self.limits.record(sound, now);
+                let sound = variant;
+                mixed.push(source, layer.offset());
+                names.push(sound.name);
+                paths.push(sound.path);
+            }
+        }
+        let path = paths
+            .into_iter()
+            .next()
+            .ok_or("None of the layers could be played")?;
+        let mut sound = Sound::new(names.join(" + "), path);
+        sound.route = route;
+        let name = sound.name.clone();
+        self.queue_sound(sound, SoundSource::Layered(mixed), 1.0, names, false);
+        self.notify_scripts(scripting::Event::SoundStarted(name));
+        Ok(())
+    }
+
+    /// Plays the playing sound again from `pos`.
+    pub(super) fn seek(&mut self, pos: Duration) -> Result<(), String> {
+        let playing = self.playing_sound.as_ref().ok_or("Nothing is playing")?;
+        if playing.is_layered() {
+            return Err("Layered or overlapping sounds can't seek".to_string());
+        }
+        let (sound, pitch, randomly_triggered) = (
+            playing.sound.clone(),
+            playing.pitch,
+            playing.randomly_triggered,
+        );
+        let mut source = self
+            .open_sound(&sound, pitch)
+            .ok_or_else(|| format!("Couldn't open {}", sound.path))?;
+        source.seek(pos)?;
+        self.queue_sound(sound, source, pitch, Vec::new(), randomly_triggered);
+        Ok(())
+    }
+
+    /// Opens the file of a sound that was already picked from its group, `pitch` is the playback
+    /// rate it was varied by.
+    fn open_sound(&self, sound: &Sound, pitch: f32) -> Option<SoundSource> {
+        let cached = self.sound_cache.lock().unwrap().get(&sound.path);
+        let source = match cached {
+            Some(cached) => SoundSource::Cached(CachedSoundPlayer::new(cached, sound.volume)),
+            None => {
+                let decoder =
+                    match AudioDecoder::new(&sound.path, self.target_sample_rate, sound.volume) {
+                        Ok(decoder) => decoder,
+                        Err(err) => {
+                            tracing::error!(path = sound.path, %err, "Couldn't play sound");
+                            return None;
+                        }
+                    };
+                SoundCache::load_in_background(&self.sound_cache, [sound.path.clone()]);
+                SoundSource::Stream(decoder)
+            }
+        };
+        Some(self.shape_sound(sound, source, pitch))
+    }
+
+    /// Applies the speed, pitch, direction and filters of `sound` to its `source`.
+    fn shape_sound(&self, sound: &Sound, mut source: SoundSource, pitch: f32) -> SoundSource {
+        if sound.reverse {
+            source =
+                SoundSource::Reversed(Box::new(Reversed::new(source, self.target_sample_rate)));
+        }
+        // stretching by the pitch shift and then playing that much faster keeps the speed
+        let shift = 2f32.powf(sound.pitch.clamp(-MAX_SHIFT, MAX_SHIFT) / 12.0);
+        if shift != 1.0 {
+            source = SoundSource::Stretched(Box::new(Stretched::new(
+                source,
+                shift,
+                self.target_sample_rate,
+            )));
+        }
+        let rate = sound.speed.clamp(MIN_SPEED, MAX_SPEED) * shift * pitch;
+        if rate != 1.0 {
+            source = SoundSource::Pitched(Box::new(Pitched::new(source, rate)));
+        }
+        if let Some(name) = &sound.filter_preset {
+            match self.config.filter_preset(name) {
+                Some(preset) => {
+                    source = SoundSource::Filtered(Box::new(Filtered::new(
+                        source,
+                        preset.enabled_filters(),
+                        self.target_sample_rate,
+                    )));
+                }
+                None => tracing::warn!(sound = sound.name, preset = name, "No such filter preset"),
+            }
+        }
+        source
+    }
 
-impl App {
-    pub(super) fn play_sound(&mut self, sound: Sound, randomly_triggered: bool) {
-        let decoder = AudioDecoder::new(&sound.path, self.target_sample_rate, sound.volume);
-        let duration = decoder.total_duration().unwrap_or_default();
+    fn queue_sound(
+        &mut self,
+        sound: Sound,
+        source: SoundSource,
+        pitch: f32,
+        layers: Vec<String>,
+        randomly_triggered: bool,
+    ) -> u64 {
+        let duration = source.total_duration();
+        let duration_scan = (duration.is_none() && layers.is_empty()).then(|| {
+            let (tx, rx) = mpsc::sync_channel(1);
+            let path = sound.path.clone();
+            thread::spawn(move || tx.send(AudioDecoder::scan_duration(&path)));
+            rx
+        });
 
-        *self.decoder.lock().unwrap() = Some(decoder);
-        self.decoder_pos.store(0, Ordering::Relaxed);
+        self.next_sound_id += 1;
+        let id = self.next_sound_id;
+        *self.decoder.lock().unwrap() = Some(QueuedSound {
+            id,
+            source,
+            route: sound.route,
+        });
+        self.decoder_thread.unpark();
         self.playing_sound = Some(PlayingSound {
+            id,
             duration,
+            duration_scan,
             sound,
+            pitch,
             randomly_triggered,
+            layers,
         });
+        id
     }
 
     pub(super) fn play_sound_from_path(&mut self, path: String) {
-        self.play_sound(
-            Sound {
-                name: "Sound from path".to_string(),
-                path,
-                volume: 1.0,
-            },
-            false,
-        );
+        self.play_sound(Sound::new("Sound from path".to_string(), path), false);
     }
 
     fn try_config_48khz(device: &Device) -> Option<cpal::SupportedStreamConfig> {
@@ -57,13 +346,23 @@ fn try_config_48khz(device: &Device) -> Option<cpal::SupportedStreamConfig> {
     }
 
     #[inline]
+    #[expect(clippy::type_complexity)]
     pub(super) fn create_streams(
         mic_device: &Device,
         out_device: &Device,
         virtual_out_device: &Device,
-        decoder: Arc<Mutex<Option<AudioDecoder>>>,
-        decoder_pos: Arc<AtomicU64>,
-    ) -> (Arc<Mutex<FilterChain>>, u32, KeepAlive) {
+        decoder: Arc<Mutex<Option<QueuedSound>>>,
+        playhead: Arc<Playhead>,
+        mix: Arc<MixControl>,
+        latency: Latency,
+    ) -> (
+        Arc<Mutex<FilterChain>>,
+        Arc<Mutex<FilterChain>>,
+        u32,
+        Arc<Diagnostics>,
+        Thread,
+        KeepAlive,
+    ) {
         let mic_config = mic_device.default_input_config().unwrap();
         let out_config = Self::try_config_48khz(out_device)
             .unwrap_or_else(|| out_device.default_output_config().unwrap());
@@ -72,107 +371,103 @@ pub(super) fn create_streams(
 
         let sample_rate = out_config.sample_rate();
         let filter_chain = Arc::new(Mutex::new(FilterChain::new(sample_rate, CHANNELS)));
+        let sound_filter_chain = Arc::new(Mutex::new(FilterChain::new(sample_rate, CHANNELS)));
+        let diagnostics = Arc::new(Diagnostics::default());
+
+        let block_frames = latency.block_frames();
+        let config = EngineConfig {
+            sample_rate,
+            block_frames,
+        };
+        let (mut sound_bus, mut output_mixer, mut virtual_mixer) = engine::engine(
+            config,
+            decoder,
+            playhead,
+            Arc::clone(&filter_chain),
+            Arc::clone(&sound_filter_chain),
+            mix,
+            Arc::clone(&diagnostics),
+        );
+
+        // the mic runs on its own clock, the drift compensation bridges it to the virtual output
+        let (mut mic_prod, mut mic_cons) = HeapRb::<f32>::new(config.ring_capacity()).split();
+        let mic_target_fill = config.ring_capacity() / CHANNELS / 2;
 
-        let mic_rb = HeapRb::<f32>::new(RING_CAPACITY);
-        let decoder_rb = HeapRb::<f32>::new(RING_CAPACITY);
-        let decoder_too_rb = HeapRb::<f32>::new(RING_CAPACITY);
-
-        let (mut mic_prod, mut mic_cons) = mic_rb.split();
-        let (mut decoder_prod, mut decoder_cons) = decoder_rb.split();
-        let (mut decoder_too_prod, mut decoder_too_cons) = decoder_too_rb.split();
-
-        let mic_stream = mic_device
-            .build_input_stream(
-                mic_config.into(),
-                move |data: &[f32], _| {
-                    mic_prod.push_slice(data);
-                },
-                |err| eprintln!("Input stream error: {err}"),
-                None,
-            )
-            .unwrap();
-
-        let out_stream = out_device
-            .build_output_stream(
-                out_config.into(),
-                move |data: &mut [f32], _| {
-                    decoder_cons.pop_slice(data);
-                },
-                |err| eprintln!("Default output stream error: {err}"),
-                None,
-            )
-            .unwrap();
-
-        let filter_chain_too = Arc::clone(&filter_chain);
-        let virtual_out_stream = virtual_out_device
-            .build_output_stream(
-                virtual_out_config.into(),
-                move |data: &mut [f32], _| {
-                    // if there are not enough elements in mic_cons, rest of data should be zeroes
-                    mic_cons.pop_slice(data);
-
-                    filter_chain_too.lock().unwrap().process(data);
-
-                    let mut decoder_iter = decoder_too_cons.pop_iter();
-                    for item in data {
-                        *item += decoder_iter.next().unwrap_or_default();
-                    }
-                },
-                |err| eprintln!("Output stream error: {err}"),
-                None,
-            )
-            .unwrap();
-
-        thread::spawn(move || {
-            let mut buf = [0.0f32; BLOCK_SAMPLES];
+        let decoder_thread = thread::spawn(move || {
             loop {
-                if decoder_prod.vacant_len() < RING_CAPACITY / 2 {
-                    thread::sleep(Duration::from_millis(10));
-                    continue;
-                }
+                sound_bus.fill();
+                // woken up by play_sound and by the output once it drained half of the ring
+                thread::park();
+            }
+        })
+        .thread()
+        .clone();
 
-                let mut guard = decoder.lock().unwrap();
-                let Some(decoder) = guard.as_mut() else {
-                    std::mem::drop(guard);
-
-                    for item in buf.iter_mut() {
-                        *item = 0.0;
-                    }
-
-                    decoder_prod.push_slice(&buf);
-                    decoder_too_prod.push_slice(&buf);
-
-                    thread::sleep(Duration::from_millis(10));
-                    continue;
-                };
-
-                let mut eof = false;
-                for frame in 0..BLOCK_FRAMES {
-                    for ch in 0..CHANNELS {
-                        match decoder.next_sample() {
-                            Some(sample) => buf[frame * CHANNELS + ch] = sample,
-                            None => {
-                                eof = true;
-                                buf[frame * CHANNELS + ch] = 0.0;
-                            }
-                        }
-                    }
+        let mic_diagnostics = Arc::clone(&diagnostics);
+        let mic_stream = sample_format::build_input_stream(
+            mic_device,
+            mic_config,
+            block_frames,
+            "Input",
+            &diagnostics,
+            move |data, info| {
+                let timestamp = info.timestamp();
+                Diagnostics::store_latency(
+                    &mic_diagnostics.input_latency_us,
+                    timestamp.callback - timestamp.capture,
+                );
+
+                if mic_prod.push_slice(data) < data.len() {
+                    Diagnostics::count(&mic_diagnostics.mic_overruns);
                 }
+            },
+        )
+        .unwrap();
 
-                decoder_pos.store(decoder.pos_nanos(), Ordering::Relaxed);
+        let out_diagnostics = Arc::clone(&diagnostics);
+        let out_decoder_thread = decoder_thread.clone();
+        let out_stream = sample_format::build_output_stream(
+            out_device,
+            out_config,
+            block_frames,
+            "Default output",
+            &diagnostics,
+            move |data, info| {
+                let timestamp = info.timestamp();
+                Diagnostics::store_latency(
+                    &out_diagnostics.output_latency_us,
+                    timestamp.playback - timestamp.callback,
+                );
 
-                if eof {
-                    // delete decoder
-                    *guard = None;
-                    decoder_pos.store(u64::MAX, Ordering::Relaxed);
+                if output_mixer.process(data) {
+                    out_decoder_thread.unpark();
                 }
-                std::mem::drop(guard);
+            },
+        )
+        .unwrap();
 
-                decoder_prod.push_slice(&buf);
-                decoder_too_prod.push_slice(&buf);
-                thread::sleep(Duration::from_millis(5));
-            }
-        });
+        let virtual_out_diagnostics = Arc::clone(&diagnostics);
+        let mut drift_compensator = DriftCompensator::new(mic_target_fill);
+        let virtual_out_stream = sample_format::build_output_stream(
+            virtual_out_device,
+            virtual_out_config,
+            block_frames,
+            "Output",
+            &diagnostics,
+            move |data, info| {
+                let timestamp = info.timestamp();
+                Diagnostics::store_latency(
+                    &virtual_out_diagnostics.virtual_output_latency_us,
+                    timestamp.playback - timestamp.callback,
+                );
+
+                // if there are not enough elements in mic_cons, rest of data stays zeroed
+                drift_compensator.process(&mut mic_cons, data, &virtual_out_diagnostics);
+
+                virtual_mixer.process(data);
+            },
+        )
+        .unwrap();
 
         mic_stream.play().unwrap();
         out_stream.play().unwrap();
@@ -180,7 +475,10 @@ pub(super) fn create_streams(
 
         (
             filter_chain,
+            sound_filter_chain,
             sample_rate,
+            diagnostics,
+            decoder_thread,
             (mic_stream, out_stream, virtual_out_stream),
         )
     }
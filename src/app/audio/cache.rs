@@ -0,0 +1,226 @@
+use crate::app::audio::{AudioDecoder, CHANNELS};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Fully decoded sound, already resampled to the output sample rate and stored as interleaved stereo.
+pub struct CachedSound {
+    samples: Box<[f32]>,
+    sample_rate: u32,
+}
+
+impl CachedSound {
+    pub(super) fn new(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self {
+            samples: samples.into_boxed_slice(),
+            sample_rate,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64((self.samples.len() / CHANNELS) as f64 / self.sample_rate as f64)
+    }
+
+    fn byte_len(&self) -> usize {
+        self.samples.len() * size_of::<f32>()
+    }
+}
+
+struct CacheEntry {
+    sound: Arc<CachedSound>,
+    last_used: u64,
+}
+
+/// In-memory cache of short sounds with a memory budget and LRU eviction.
+pub struct SoundCache {
+    entries: HashMap<String, CacheEntry>,
+    loading: HashSet<String>,
+    sample_rate: u32,
+    budget_bytes: usize,
+    used_bytes: usize,
+    max_duration: Duration,
+    clock: u64,
+}
+
+impl SoundCache {
+    pub fn new(sample_rate: u32, budget_mb: u32, max_duration: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            loading: HashSet::new(),
+            sample_rate,
+            budget_bytes: budget_mb as usize * 1024 * 1024,
+            used_bytes: 0,
+            max_duration,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, path: &str) -> Option<Arc<CachedSound>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(path)?;
+        entry.last_used = self.clock;
+        Some(Arc::clone(&entry.sound))
+    }
+
+    fn insert(&mut self, path: String, sound: CachedSound) {
+        let byte_len = sound.byte_len();
+        if byte_len > self.budget_bytes {
+            return;
+        }
+
+        while self.used_bytes + byte_len > self.budget_bytes {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used_bytes -= entry.sound.byte_len();
+                tracing::debug!(path = oldest, "Evicted sound from cache");
+            }
+        }
+
+        self.clock += 1;
+        self.used_bytes += byte_len;
+        self.entries.insert(
+            path,
+            CacheEntry {
+                sound: Arc::new(sound),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Decodes the given files on a background thread and caches the ones that are short enough.
+    pub fn load_in_background(cache: &Arc<Mutex<Self>>, paths: impl IntoIterator<Item = String>) {
+        let (sample_rate, max_duration, paths) = {
+            let mut guard = cache.lock().unwrap();
+            let SoundCache {
+                entries, loading, ..
+            } = &mut *guard;
+            let paths = paths
+                .into_iter()
+                .filter(|path| !entries.contains_key(path) && loading.insert(path.clone()))
+                .collect::<Vec<_>>();
+            (guard.sample_rate, guard.max_duration, paths)
+        };
+
+        if paths.is_empty() {
+            return;
+        }
+
+        let cache = Arc::clone(cache);
+        thread::spawn(move || {
+            for path in paths {
+                let decoded = Self::decode(&path, sample_rate, max_duration);
+                let mut guard = cache.lock().unwrap();
+                guard.loading.remove(&path);
+                if let Some(sound) = decoded {
+                    tracing::debug!(path, duration = ?sound.duration(), "Cached sound");
+                    guard.insert(path, sound);
+                }
+            }
+        });
+    }
+
+    fn decode(path: &str, sample_rate: u32, max_duration: Duration) -> Option<CachedSound> {
+        let mut decoder = AudioDecoder::new(path, sample_rate, 1.0)
+            .inspect_err(|err| tracing::warn!(path, %err, "Couldn't cache sound"))
+            .ok()?;
+
+        if decoder.total_duration().is_some_and(|d| d > max_duration) {
+            return None;
+        }
+
+        let max_samples = (max_duration.as_secs_f64() * sample_rate as f64) as usize * CHANNELS;
+        let mut samples = Vec::new();
+        while let Some(sample) = decoder.next_sample() {
+            if samples.len() >= max_samples {
+                return None;
+            }
+            samples.push(sample);
+        }
+
+        Some(CachedSound::new(samples, sample_rate))
+    }
+}
+
+/// Plays back a [`CachedSound`] without touching the file system.
+pub struct CachedSoundPlayer {
+    sound: Arc<CachedSound>,
+    idx: usize,
+    volume: f32,
+}
+
+impl CachedSoundPlayer {
+    pub fn new(sound: Arc<CachedSound>, volume: f32) -> Self {
+        Self {
+            sound,
+            idx: 0,
+            volume,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> Option<f32> {
+        let sample = *self.sound.samples.get(self.idx)?;
+        self.idx += 1;
+        Some(sample * self.volume)
+    }
+
+    pub fn seek(&mut self, pos: Duration) {
+        let frame = (pos.as_secs_f64() * self.sound.sample_rate as f64).round() as usize;
+        self.idx = (frame * CHANNELS).min(self.sound.samples.len());
+    }
+
+    pub fn pos_nanos(&self) -> u64 {
+        let secs = self.idx as f64 / self.sound.sample_rate as f64 / CHANNELS as f64;
+        (secs * 1_000_000_000.0) as u64
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.sound.duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sound taking up `kb` KiB.
+    fn sound(kb: usize) -> CachedSound {
+        CachedSound::new(vec![0.0; kb * 1024 / size_of::<f32>()], 48_000)
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_sounds() {
+        let mut cache = SoundCache::new(48_000, 1, Duration::from_secs(10));
+        cache.insert("a".to_string(), sound(400));
+        cache.insert("b".to_string(), sound(400));
+        assert!(cache.get("a").is_some());
+
+        // only two fit, "b" was used longer ago than "a"
+        cache.insert("c".to_string(), sound(400));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.used_bytes, 800 * 1024);
+    }
+
+    #[test]
+    fn skips_sounds_over_the_budget() {
+        let mut cache = SoundCache::new(48_000, 1, Duration::from_secs(10));
+        cache.insert("a".to_string(), sound(400));
+        cache.insert("huge".to_string(), sound(1025));
+
+        assert!(cache.get("huge").is_none());
+        assert!(cache.get("a").is_some());
+        assert_eq!(cache.used_bytes, 400 * 1024);
+    }
+}
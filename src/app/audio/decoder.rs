@@ -1,23 +1,29 @@
+use super::{CHANNELS, downmix::Downmix};
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fs::File,
     io::{self, BufReader, Read, Seek, SeekFrom},
-    path::Path,
-    time::Duration,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime},
 };
 use symphonia::{
     core::{
         audio::AudioSpec,
         codecs::{self, CodecParameters, audio::CODEC_ID_NULL_AUDIO},
-        errors::Error,
-        formats::{FormatReader, SeekMode, SeekTo, TrackType, probe::Hint},
+        errors::{Error, SeekErrorKind},
+        formats::{FormatReader, SeekMode, SeekTo, Track, probe::Hint},
         io::{MediaSource, MediaSourceStream},
-        units,
+        units::{Time, TimeBase, Timestamp},
     },
     default::{get_codecs, get_probe},
 };
 
-// TODO: replace unwraps in this file with actual error handling
+/// Durations of files without a frame count in their header, keyed by path and modification time.
+static SCANNED_DURATIONS: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, Duration)>>> =
+    LazyLock::new(Default::default);
 
 pub struct AudioDecoder {
     decoder: Box<dyn codecs::audio::AudioDecoder>,
@@ -28,27 +34,28 @@ pub struct AudioDecoder {
     spec: AudioSpec,
     counted_samples: usize,
     volume: f32,
-    previous_mono_sample: Option<f32>,
+    downmix: Downmix,
+    track_id: u32,
+    time_base: Option<TimeBase>,
 
     // linear resampling
     target_sr: u32,
     step: f32,
     pos: f32,
     a: [f32; 2],
-    b: [f32; 2],
+    /// `None` once `a` is the last frame of the file.
+    b: Option<[f32; 2]>,
     a_idx: usize,
     out_ch: usize,
     out_pair: [f32; 2],
 }
 
 impl AudioDecoder {
-    pub fn new(audio_path: &str, sample_rate: u32, volume: f32) -> Self {
+    pub fn new(audio_path: &str, sample_rate: u32, volume: f32) -> Result<Self, Error> {
         let path = Path::new(audio_path);
-        let file = File::open(path).unwrap();
-        let byte_len = file.metadata().unwrap().len();
-        let buf_reader = BufReader::new(file);
+        let format = Self::open_format(path)?;
 
-        Self::create_decoder(sample_rate, volume, buf_reader, byte_len, path.extension())
+        Self::create_decoder(sample_rate, volume, format, path)
     }
 
     pub fn next_sample(&mut self) -> Option<f32> {
@@ -61,14 +68,15 @@ pub fn next_sample(&mut self) -> Option<f32> {
             }
 
             if self.a_idx < target {
-                self.a = self.b;
-                self.b = self.read_raw_frame()?;
+                self.a = self.b?;
+                self.b = self.read_raw_frame();
                 self.a_idx += 1;
             }
 
             let frac = self.pos.fract();
-            for i in 0..2 {
-                self.out_pair[i] = self.a[i] + (self.b[i] - self.a[i]) * frac;
+            let b = self.b.unwrap_or(self.a);
+            for ((out, a), b) in self.out_pair.iter_mut().zip(self.a).zip(b) {
+                *out = a + (b - a) * frac;
             }
             self.pos += self.step;
         }
@@ -80,24 +88,17 @@ pub fn next_sample(&mut self) -> Option<f32> {
     }
 
     fn read_raw_frame(&mut self) -> Option<[f32; 2]> {
-        let l = self.next_raw_sample()?;
-        let r = if self.spec.channels().count() == 1 {
-            l
-        } else {
-            self.next_raw_sample()?
-        };
-        Some([l, r])
+        let frame = self.next_raw_frame()?;
+        Some(self.downmix.apply(&self.buffer[frame]))
     }
 
     fn skip_raw_frame(&mut self) -> Option<()> {
-        self.next_raw_sample()?;
-        if self.spec.channels().count() == 2 {
-            self.next_raw_sample()?;
-        }
+        self.next_raw_frame()?;
         Some(())
     }
 
-    pub fn seek(&mut self, pos: Duration) {
+    /// Seeks to the exact frame at `pos`, decoding and discarding audio if the demuxer lands earlier.
+    pub fn seek(&mut self, pos: Duration) -> Result<(), Error> {
         let mut target = pos;
         if let Some(total_duration) = self.total_duration
             && target > total_duration
@@ -105,37 +106,61 @@ pub fn seek(&mut self, pos: Duration) {
             target = total_duration;
         }
 
-        let active_channel = self.current_packet_offset % self.spec.channels().count();
-
-        let _ = self.format.seek(
-            SeekMode::Coarse,
-            SeekTo::Time {
-                time: symphonia::core::units::Time::try_new(
-                    target.as_secs() as i64,
-                    target.subsec_nanos(),
-                )
-                .unwrap(),
-                track_id: None,
+        let active_channel = self.out_ch;
+
+        // round to the nearest timestamp ourselves, going through `Time` can land one frame early
+        let seek_to = match self.time_base {
+            Some(time_base) => SeekTo::Timestamp {
+                ts: Timestamp::new(
+                    (target.as_secs_f64() * time_base.denom.get() as f64
+                        / time_base.numer.get() as f64)
+                        .round() as i64,
+                ),
+                track_id: self.track_id,
             },
-        );
+            None => SeekTo::Time {
+                time: Time::try_new(target.as_secs() as i64, target.subsec_nanos())
+                    .ok_or(Error::SeekError(SeekErrorKind::OutOfRange))?,
+                track_id: Some(self.track_id),
+            },
+        };
+        let seeked = self.format.seek(SeekMode::Accurate, seek_to)?;
 
         self.decoder.reset();
-        self.current_packet_offset = usize::MAX;
+        self.buffer.clear();
+        self.current_packet_offset = 0;
+
+        // the packet we landed on starts at or before the target, so throw away the difference
+        let skip = Timestamp::new((seeked.required_ts.get() - seeked.actual_ts.get()).max(0));
+        let skip_frames = match self.time_base {
+            Some(time_base) => time_base.calc_time(skip).map_or(0, |time| {
+                (time.as_secs_f64() * self.spec.rate() as f64).round() as u64
+            }),
+            None => skip.get() as u64,
+        };
+        for _ in 0..skip_frames {
+            if self.skip_raw_frame().is_none() {
+                break;
+            }
+        }
+
         self.pos = 0.0;
         self.a_idx = 0;
         self.out_ch = 0;
-        self.previous_mono_sample = None;
+        self.counted_samples =
+            (target.as_secs_f64() * self.target_sr as f64).round() as usize * CHANNELS;
 
         self.a = self.read_raw_frame().unwrap_or([0.0; 2]);
-        self.b = self.read_raw_frame().unwrap_or([0.0; 2]);
+        self.b = self.read_raw_frame();
 
         for _ in 0..active_channel {
             self.next_sample();
         }
+        Ok(())
     }
 
     pub fn pos_nanos(&self) -> u64 {
-        let secs = self.counted_samples as f64 / self.target_sr as f64 / 2.0;
+        let secs = self.counted_samples as f64 / self.target_sr as f64 / CHANNELS as f64;
         (secs * 1_000_000_000.0) as u64
     }
 
@@ -143,13 +168,51 @@ pub(super) fn total_duration(&self) -> Option<Duration> {
         self.total_duration
     }
 
-    fn next_raw_sample(&mut self) -> Option<f32> {
-        if self.spec.channels().count() == 1
-            && let Some(previous) = self.previous_mono_sample.take()
-        {
-            return Some(previous);
+    /// Returns the duration found by an earlier [`Self::scan_duration`] if the file hasn't changed.
+    pub fn scanned_duration(audio_path: &str) -> Option<Duration> {
+        let modified = std::fs::metadata(audio_path)
+            .and_then(|m| m.modified())
+            .ok()?;
+        let durations = SCANNED_DURATIONS.lock().unwrap();
+        match durations.get(Path::new(audio_path)) {
+            Some(&(scanned_modified, duration)) if scanned_modified == modified => Some(duration),
+            _ => None,
+        }
+    }
+
+    /// Estimates the duration of a file whose header has no frame count by walking its packets
+    /// without decoding them. The result is cached per file.
+    pub fn scan_duration(audio_path: &str) -> Option<Duration> {
+        if let Some(duration) = Self::scanned_duration(audio_path) {
+            return Some(duration);
+        }
+
+        let path = Path::new(audio_path);
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+        let mut format = Self::open_format(path).ok()?;
+        let track = Self::audio_track(&*format).ok()?;
+        let track_id = track.id;
+        let start_ts = track.start_ts.get();
+        let time_base = Self::track_time_base(track)?;
+
+        let mut end_ts = start_ts;
+        while let Ok(Some(packet)) = format.next_packet() {
+            if packet.track_id == track_id {
+                end_ts = end_ts.max(packet.pts.get() + packet.dur.get() as i64);
+            }
         }
 
+        let duration =
+            Self::time_to_duration(time_base.calc_time(Timestamp::new(end_ts - start_ts))?);
+        SCANNED_DURATIONS
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, duration));
+        Some(duration)
+    }
+
+    /// Returns the range of the next interleaved frame with all of the source channels in `buffer`.
+    fn next_raw_frame(&mut self) -> Option<Range<usize>> {
         if self.current_packet_offset >= self.buffer.len() {
             let decoded = loop {
                 let packet = match self.format.next_packet() {
@@ -157,6 +220,9 @@ fn next_raw_sample(&mut self) -> Option<f32> {
                     Ok(None) => return None,
                     Err(_) => return None,
                 };
+                if packet.track_id != self.track_id {
+                    continue;
+                }
                 let decoded = match self.decoder.decode(&packet) {
                     Ok(decoded) => decoded,
                     Err(Error::DecodeError(_)) => continue,
@@ -168,94 +234,110 @@ fn next_raw_sample(&mut self) -> Option<f32> {
                 }
             };
 
-            self.spec = decoded.spec().clone();
+            if decoded.spec() != &self.spec {
+                self.spec = decoded.spec().clone();
+                self.downmix = Downmix::new(self.spec.channels());
+            }
             let mut buffer = Vec::new();
             decoded.copy_to_vec_interleaved(&mut buffer);
             self.buffer = buffer;
             self.current_packet_offset = 0;
         }
 
-        let sample = *self.buffer.get(self.current_packet_offset)?;
-        self.current_packet_offset += 1;
-        self.previous_mono_sample = Some(sample);
+        let frame =
+            self.current_packet_offset..self.current_packet_offset + self.downmix.channels();
+        if frame.end > self.buffer.len() {
+            return None;
+        }
+        self.current_packet_offset = frame.end;
 
-        Some(sample)
+        Some(frame)
     }
 
-    fn create_decoder(
-        target_sample_rate: u32,
-        volume: f32,
-        buf: BufReader<File>,
-        byte_len: u64,
-        file_extension: Option<&OsStr>,
-    ) -> AudioDecoder {
+    fn open_format(path: &Path) -> Result<Box<dyn FormatReader>, Error> {
+        let file = File::open(path)?;
+        let byte_len = file.metadata()?.len();
+
         let mut hint = Hint::new();
-        if let Some(extension) = file_extension {
-            hint.with_extension(extension.to_str().unwrap());
+        if let Some(extension) = path.extension().and_then(OsStr::to_str) {
+            hint.with_extension(extension);
         }
 
         let mss = MediaSourceStream::new(
             Box::new(AudioSource {
                 byte_len,
-                inner: buf,
+                inner: BufReader::new(file),
             }),
             Default::default(),
         );
 
-        let mut format = get_probe()
-            .probe(&hint, mss, Default::default(), Default::default())
-            .unwrap();
+        get_probe().probe(&hint, mss, Default::default(), Default::default())
+    }
 
-        let default_track = match format.default_track(TrackType::Audio) {
-            Some(track) => track,
-            None => panic!("no audio tracks found"),
-        };
+    fn audio_track(format: &dyn FormatReader) -> Result<&Track, Error> {
+        format
+            .tracks()
+            .iter()
+            .find(|track| {
+                matches!(
+                    &track.codec_params,
+                    Some(CodecParameters::Audio(audio_params)) if audio_params.codec != CODEC_ID_NULL_AUDIO
+                )
+            })
+            .ok_or(Error::Unsupported("no supported audio track found"))
+    }
 
-        let mut track_id = u32::MAX;
-        let track = match format.tracks().iter().find(|track| {
-            if let Some(CodecParameters::Audio(audio_params)) = &track.codec_params
-                && audio_params.codec != CODEC_ID_NULL_AUDIO
-            {
-                track_id = track.id;
-                return true;
-            }
-            false
-        }) {
-            Some(track) => track,
-            None => panic!(),
-        };
+    fn track_time_base(track: &Track) -> Option<TimeBase> {
+        track.time_base.or_else(|| {
+            let sample_rate = track.codec_params.as_ref()?.audio()?.sample_rate?;
+            TimeBase::try_from_recip(sample_rate)
+        })
+    }
+
+    fn time_to_duration(time: Time) -> Duration {
+        let nanos = time.as_nanos().max(0);
+        Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        )
+    }
+
+    fn create_decoder(
+        target_sample_rate: u32,
+        volume: f32,
+        mut format: Box<dyn FormatReader>,
+        path: &Path,
+    ) -> Result<AudioDecoder, Error> {
+        let track = Self::audio_track(&*format)?;
+        let track_id = track.id;
+        let time_base = Self::track_time_base(track);
 
         let audio_params = track
             .codec_params
             .as_ref()
             .and_then(|p| p.audio())
-            .expect("expected audio codec parameters");
-
-        let mut decoder = get_codecs()
-            .make_audio_decoder(audio_params, &Default::default())
-            .unwrap();
-
-        let total_duration =
-            default_track
-                .time_base
-                .zip(default_track.num_frames)
-                .map(|(time_base, n_frames)| {
-                    let time = time_base
-                        .calc_time(units::Timestamp::new(n_frames as i64))
-                        .expect("failed to calculate duration");
-                    let nanos = time.as_nanos();
-                    Duration::new(
-                        (nanos / 1_000_000_000) as u64,
-                        (nanos % 1_000_000_000) as u32,
-                    )
-                });
+            .ok_or(Error::Unsupported("expected audio codec parameters"))?;
+
+        let mut decoder = get_codecs().make_audio_decoder(audio_params, &Default::default())?;
+
+        let header_duration = time_base.and_then(|time_base| {
+            let ts = match (track.duration, track.num_frames) {
+                (Some(duration), _) => duration.get(),
+                (None, Some(num_frames)) => num_frames,
+                (None, None) => return None,
+            };
+            time_base.calc_time(Timestamp::new(ts as i64))
+        });
+        let total_duration = header_duration
+            .map(Self::time_to_duration)
+            .or_else(|| path.to_str().and_then(Self::scanned_duration));
 
         let decoded = loop {
             let packet = match format.next_packet() {
                 Ok(Some(packet)) => packet,
                 Ok(None) => break decoder.last_decoded(),
                 Err(Error::IoError(_)) => break decoder.last_decoded(),
-                Err(e) => panic!("{e}"),
+                Err(e) => return Err(e),
             };
 
             if packet.track_id != track_id {
@@ -265,7 +347,7 @@ fn create_decoder(
             match decoder.decode(&packet) {
                 Ok(decoded) => break decoded,
                 Err(Error::DecodeError(_)) => continue,
-                Err(_) => panic!(),
+                Err(e) => return Err(e),
             }
         };
 
@@ -274,6 +356,7 @@ fn create_decoder(
         decoded.copy_to_vec_interleaved(&mut buffer);
 
         let step = spec.rate() as f32 / target_sample_rate as f32;
+        let downmix = Downmix::new(spec.channels());
 
         let mut decoder_obj = AudioDecoder {
             decoder,
@@ -284,21 +367,23 @@ fn create_decoder(
             spec,
             counted_samples: 0,
             volume,
-            previous_mono_sample: None,
+            downmix,
+            track_id,
+            time_base,
             target_sr: target_sample_rate,
             step,
             pos: 0.0,
             a: [0.0; 2],
-            b: [0.0; 2],
+            b: None,
             a_idx: 0,
             out_ch: 0,
             out_pair: [0.0; 2],
         };
 
         decoder_obj.a = decoder_obj.read_raw_frame().unwrap_or([0.0; 2]);
-        decoder_obj.b = decoder_obj.read_raw_frame().unwrap_or([0.0; 2]);
+        decoder_obj.b = decoder_obj.read_raw_frame();
 
-        decoder_obj
+        Ok(decoder_obj)
     }
 }
 
@@ -332,3 +417,142 @@ fn is_seekable(&self) -> bool {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const RATE: u32 = 48_000;
+    const FRAMES: usize = 2048;
+
+    /// Writes a 32-bit float WAVE_FORMAT_EXTENSIBLE file where every frame is `frame`.
+    fn write_wav(name: &str, channel_mask: u32, frame: &[f32]) -> PathBuf {
+        write_wav_with(name, channel_mask, frame.len() as u16, |_, ch| frame[ch])
+    }
+
+    fn write_wav_with(
+        name: &str,
+        channel_mask: u32,
+        channels: u16,
+        sample: impl Fn(usize, usize) -> f32,
+    ) -> PathBuf {
+        let block_align = channels * 4;
+        let data_len = FRAMES as u32 * block_align as u32;
+
+        let mut bytes = Vec::new();
+        bytes.extend(b"RIFF");
+        bytes.extend((4 + 8 + 40 + 8 + data_len).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(40u32.to_le_bytes());
+        bytes.extend(0xFFFEu16.to_le_bytes()); // WAVE_FORMAT_EXTENSIBLE
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(RATE.to_le_bytes());
+        bytes.extend((RATE * block_align as u32).to_le_bytes());
+        bytes.extend(block_align.to_le_bytes());
+        bytes.extend(32u16.to_le_bytes());
+        bytes.extend(22u16.to_le_bytes());
+        bytes.extend(32u16.to_le_bytes());
+        bytes.extend(channel_mask.to_le_bytes());
+        // KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+        bytes.extend([
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38,
+            0x9B, 0x71,
+        ]);
+        bytes.extend(b"data");
+        bytes.extend(data_len.to_le_bytes());
+        for frame in 0..FRAMES {
+            for ch in 0..channels as usize {
+                bytes.extend(sample(frame, ch).to_le_bytes());
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("keysounds-decoder-{name}.wav"));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn decode_frames(path: &Path) -> Vec<[f32; 2]> {
+        let mut decoder = AudioDecoder::new(path.to_str().unwrap(), RATE, 1.0).unwrap();
+        let mut frames = Vec::new();
+        while let (Some(l), Some(r)) = (decoder.next_sample(), decoder.next_sample()) {
+            frames.push([l, r]);
+        }
+        frames
+    }
+
+    fn assert_all_frames(path: &Path, expected: [f32; 2]) {
+        let frames = decode_frames(path);
+        assert!(
+            frames.len() >= FRAMES - 2,
+            "only got {} frames",
+            frames.len()
+        );
+        for (i, [l, r]) in frames.into_iter().enumerate() {
+            assert!(
+                (l - expected[0]).abs() < 1e-4 && (r - expected[1]).abs() < 1e-4,
+                "frame {i}: got [{l}, {r}], expected {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn downmixes_5_1_with_itu_coefficients() {
+        // FL, FR, FC, LFE, BL, BR
+        let path = write_wav("5_1", 0x3F, &[0.1, 0.2, 0.3, 0.9, 0.4, 0.5]);
+        let c = std::f32::consts::FRAC_1_SQRT_2;
+        assert_all_frames(&path, [0.1 + 0.3 * c + 0.4 * c, 0.2 + 0.3 * c + 0.5 * c]);
+    }
+
+    #[test]
+    fn drops_lfe() {
+        let path = write_wav("lfe", 0x3F, &[0.0, 0.0, 0.0, 0.8, 0.0, 0.0]);
+        assert_all_frames(&path, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn downmixes_quad() {
+        // FL, FR, BL, BR
+        let path = write_wav("quad", 0x33, &[0.0, 0.5, 0.25, 0.0]);
+        let c = std::f32::consts::FRAC_1_SQRT_2;
+        assert_all_frames(&path, [0.25 * c, 0.5]);
+    }
+
+    #[test]
+    fn keeps_stereo_and_duplicates_mono() {
+        let path = write_wav("stereo", 0x3, &[0.3, -0.6]);
+        assert_all_frames(&path, [0.3, -0.6]);
+
+        let path = write_wav("mono", 0x4, &[0.4]);
+        assert_all_frames(&path, [0.4, 0.4]);
+    }
+
+    #[test]
+    fn seeks_to_the_exact_frame() {
+        // every frame holds its own index, scaled to stay in range
+        let path = write_wav_with("ramp", 0x3, 2, |frame, _| frame as f32 / FRAMES as f32);
+        let mut decoder = AudioDecoder::new(path.to_str().unwrap(), RATE, 1.0).unwrap();
+
+        let target_frame = 1234;
+        let target = Duration::from_secs_f64(target_frame as f64 / RATE as f64);
+        decoder.seek(target).unwrap();
+        assert!(
+            Duration::from_nanos(decoder.pos_nanos()).abs_diff(target) < Duration::from_micros(1)
+        );
+
+        let expected = target_frame as f32 / FRAMES as f32;
+        let l = decoder.next_sample().unwrap();
+        assert!((l - expected).abs() < 1e-6, "got {l}, expected {expected}");
+    }
+
+    #[test]
+    fn scans_duration() {
+        let path = write_wav("scan", 0x3, &[0.0, 0.0]);
+        let path = path.to_str().unwrap();
+        let expected = Duration::from_secs_f64(FRAMES as f64 / RATE as f64);
+
+        let scanned = AudioDecoder::scan_duration(path).unwrap();
+        assert!(scanned.abs_diff(expected) < Duration::from_micros(50));
+        assert_eq!(AudioDecoder::scanned_duration(path), Some(scanned));
+    }
+}
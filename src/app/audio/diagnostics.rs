@@ -0,0 +1,73 @@
+use std::{
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Counters written by the audio callbacks and read by the diagnostics panel.
+#[derive(Default)]
+pub struct Diagnostics {
+    pub mic_underruns: AtomicU64,
+    pub mic_overruns: AtomicU64,
+    /// Current mic resampling correction in parts per million.
+    pub mic_drift_ppm: AtomicI64,
+    /// Smoothed number of frames buffered between the mic and the virtual output.
+    pub mic_buffered_frames: AtomicU64,
+    /// Times the output ran out of decoded sound samples.
+    pub sound_underruns: AtomicU64,
+    /// Number of decoded frames queued for the output at the last callback.
+    pub sound_buffered_frames: AtomicU64,
+    /// Buffer under- and overruns reported by the audio backend itself.
+    pub device_xruns: AtomicU64,
+    /// Time between the mic capturing a block and its callback running, in microseconds.
+    pub input_latency_us: AtomicU64,
+    /// Time between the output callback running and its block being played, in microseconds.
+    pub output_latency_us: AtomicU64,
+    /// Same as `output_latency_us`, for the virtual output.
+    pub virtual_output_latency_us: AtomicU64,
+}
+
+impl Diagnostics {
+    #[inline]
+    pub(super) fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(super) fn store_latency(counter: &AtomicU64, latency: Duration) {
+        counter.store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// All glitches seen so far, whether they were caught by us or by the audio backend.
+    pub fn xruns(&self) -> u64 {
+        [
+            &self.mic_underruns,
+            &self.mic_overruns,
+            &self.sound_underruns,
+            &self.device_xruns,
+        ]
+        .iter()
+        .map(|counter| counter.load(Ordering::Relaxed))
+        .sum()
+    }
+
+    /// Measured delay from the mic picking up sound to it reaching the virtual output.
+    pub fn mic_latency(&self, sample_rate: u32) -> Duration {
+        Self::load_latency(&self.input_latency_us)
+            + Self::frames_to_duration(&self.mic_buffered_frames, sample_rate)
+            + Self::load_latency(&self.virtual_output_latency_us)
+    }
+
+    /// Measured delay from a sound being decoded to it being played on the output.
+    pub fn sound_latency(&self, sample_rate: u32) -> Duration {
+        Self::frames_to_duration(&self.sound_buffered_frames, sample_rate)
+            + Self::load_latency(&self.output_latency_us)
+    }
+
+    fn load_latency(counter: &AtomicU64) -> Duration {
+        Duration::from_micros(counter.load(Ordering::Relaxed))
+    }
+
+    fn frames_to_duration(counter: &AtomicU64, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(counter.load(Ordering::Relaxed) as f64 / sample_rate as f64)
+    }
+}
@@ -0,0 +1,111 @@
+use symphonia::core::audio::{AmbisonicBFormat, ChannelLabel, Channels, Position};
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Matrix that folds a frame with any channel layout down to stereo.
+///
+/// Positioned layouts use the ITU-R BS.775 coefficients: front channels pass through, the center
+/// and surrounds are mixed in at -3 dB and LFE is dropped. The result is not normalized, matching
+/// what most players do.
+pub(super) struct Downmix {
+    /// `[left gain, right gain]` for every input channel in interleaved order.
+    gains: Vec<[f32; 2]>,
+}
+
+impl Downmix {
+    pub(super) fn new(channels: &Channels) -> Self {
+        let count = channels.count();
+        let gains = match channels {
+            _ if count == 1 => vec![[1.0, 1.0]],
+            Channels::Positioned(positions) => positions.iter().map(Self::position_gains).collect(),
+            Channels::Discrete(_) => Self::discrete_gains(count),
+            Channels::Ambisonic(_) => (0..count as u16).map(Self::ambisonic_gains).collect(),
+            Channels::Custom(labels) => labels
+                .iter()
+                .enumerate()
+                .map(|(i, label)| match *label {
+                    ChannelLabel::Positioned(position) => Self::position_gains(position),
+                    ChannelLabel::Ambisonic(acn) => Self::ambisonic_gains(acn),
+                    ChannelLabel::AmbisonicBFormat(AmbisonicBFormat::W) => Self::ambisonic_gains(0),
+                    ChannelLabel::AmbisonicBFormat(AmbisonicBFormat::Y) => Self::ambisonic_gains(1),
+                    ChannelLabel::AmbisonicBFormat(_) => [0.0, 0.0],
+                    _ => Self::alternating_gains(i),
+                })
+                .collect(),
+            _ => Self::discrete_gains(count),
+        };
+
+        Self { gains }
+    }
+
+    /// Number of interleaved samples in one input frame.
+    #[inline]
+    pub(super) fn channels(&self) -> usize {
+        self.gains.len()
+    }
+
+    #[inline]
+    pub(super) fn apply(&self, frame: &[f32]) -> [f32; 2] {
+        let mut out = [0.0; 2];
+        for (sample, [l, r]) in frame.iter().zip(&self.gains) {
+            out[0] += sample * l;
+            out[1] += sample * r;
+        }
+        out
+    }
+
+    fn position_gains(position: Position) -> [f32; 2] {
+        match position {
+            Position::FRONT_LEFT | Position::FRONT_LEFT_WIDE | Position::FRONT_LEFT_CENTER => {
+                [1.0, 0.0]
+            }
+            Position::FRONT_RIGHT | Position::FRONT_RIGHT_WIDE | Position::FRONT_RIGHT_CENTER => {
+                [0.0, 1.0]
+            }
+            Position::FRONT_CENTER | Position::BOTTOM_FRONT_CENTER | Position::TOP_FRONT_CENTER => {
+                [MINUS_3DB, MINUS_3DB]
+            }
+            Position::REAR_LEFT
+            | Position::SIDE_LEFT
+            | Position::TOP_FRONT_LEFT
+            | Position::TOP_REAR_LEFT
+            | Position::TOP_SIDE_LEFT
+            | Position::BOTTOM_FRONT_LEFT => [MINUS_3DB, 0.0],
+            Position::REAR_RIGHT
+            | Position::SIDE_RIGHT
+            | Position::TOP_FRONT_RIGHT
+            | Position::TOP_REAR_RIGHT
+            | Position::TOP_SIDE_RIGHT
+            | Position::BOTTOM_FRONT_RIGHT => [0.0, MINUS_3DB],
+            Position::REAR_CENTER | Position::TOP_CENTER | Position::TOP_REAR_CENTER => [0.5, 0.5],
+            // LFE1, LFE2 and anything unknown
+            _ => [0.0, 0.0],
+        }
+    }
+
+    /// First order decode to a pair of virtual cardioids pointing left and right.
+    fn ambisonic_gains(acn: u16) -> [f32; 2] {
+        match acn {
+            // W
+            0 => [0.5, 0.5],
+            // Y (left-right)
+            1 => [0.5, -0.5],
+            _ => [0.0, 0.0],
+        }
+    }
+
+    fn discrete_gains(count: usize) -> Vec<[f32; 2]> {
+        match Position::from_wave_channel_count(count as u32) {
+            // assume the WAVE channel order, like most untagged multichannel files use
+            Some(positions) if count > 2 => positions.iter().map(Self::position_gains).collect(),
+            _ => (0..count).map(Self::alternating_gains).collect(),
+        }
+    }
+
+    fn alternating_gains(idx: usize) -> [f32; 2] {
+        match idx % 2 {
+            0 => [1.0, 0.0],
+            _ => [0.0, 1.0],
+        }
+    }
+}
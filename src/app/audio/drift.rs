@@ -0,0 +1,159 @@
+use crate::app::audio::{CHANNELS, Diagnostics};
+use ringbuf::traits::Consumer;
+use std::sync::atomic::Ordering;
+
+/// Largest rate correction the compensator is allowed to apply (0.5%).
+const MAX_CORRECTION: f64 = 0.005;
+const PROPORTIONAL_GAIN: f64 = 1e-5;
+const INTEGRAL_GAIN: f64 = 1e-8;
+const FILL_SMOOTHING: f64 = 0.05;
+
+/// Resamples the mic ring buffer at a slightly adjusted rate so that its fill level stays around
+/// `target_fill` frames, even though the mic and virtual output devices run on different clocks.
+pub(super) struct DriftCompensator {
+    target_fill: f64,
+    smoothed_fill: f64,
+    integral: f64,
+    ratio: f64,
+    frac: f64,
+    prev: [f32; CHANNELS],
+    next: [f32; CHANNELS],
+    /// Waiting for the ring to fill up to the target after starting or an underrun.
+    priming: bool,
+}
+
+impl DriftCompensator {
+    pub(super) fn new(target_fill: usize) -> Self {
+        Self {
+            target_fill: target_fill as f64,
+            smoothed_fill: target_fill as f64,
+            integral: 0.0,
+            ratio: 1.0,
+            frac: 0.0,
+            prev: [0.0; CHANNELS],
+            next: [0.0; CHANNELS],
+            priming: true,
+        }
+    }
+
+    /// Fills `out` with mic samples, leaving silence where the ring ran dry.
+    pub(super) fn process(
+        &mut self,
+        ring: &mut impl Consumer<Item = f32>,
+        out: &mut [f32],
+        diagnostics: &Diagnostics,
+    ) {
+        let fill = (ring.occupied_len() / CHANNELS) as f64;
+
+        if self.priming {
+            if fill < self.target_fill {
+                return;
+            }
+            self.priming = false;
+            self.smoothed_fill = fill;
+            self.frac = 0.0;
+            ring.pop_slice(&mut self.next);
+            self.prev = self.next;
+        }
+
+        self.smoothed_fill += (fill - self.smoothed_fill) * FILL_SMOOTHING;
+        let error = self.smoothed_fill - self.target_fill;
+        self.integral =
+            (self.integral + error * INTEGRAL_GAIN).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        let correction =
+            (error * PROPORTIONAL_GAIN + self.integral).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.ratio = 1.0 + correction;
+
+        diagnostics
+            .mic_drift_ppm
+            .store((correction * 1_000_000.0) as i64, Ordering::Relaxed);
+        diagnostics
+            .mic_buffered_frames
+            .store(self.smoothed_fill as u64, Ordering::Relaxed);
+
+        for frame in out.chunks_exact_mut(CHANNELS) {
+            self.frac += self.ratio;
+            while self.frac >= 1.0 {
+                self.frac -= 1.0;
+                self.prev = self.next;
+                if ring.pop_slice(&mut self.next) < CHANNELS {
+                    Diagnostics::count(&diagnostics.mic_underruns);
+                    self.priming = true;
+                    return;
+                }
+            }
+
+            let frac = self.frac as f32;
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                *sample = self.prev[ch] + (self.next[ch] - self.prev[ch]) * frac;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::{
+        HeapRb,
+        traits::{Observer, Producer, Split},
+    };
+
+    /// Runs a mic producing `mic_rate` frames per second into an output consuming 48 kHz,
+    /// returning the fill levels seen by the output over the last simulated second.
+    fn simulate(mic_rate: f64, seconds: f64) -> (Vec<usize>, Diagnostics) {
+        const MIC_BLOCK: usize = 480;
+        const OUT_BLOCK: usize = 512;
+        const TARGET: usize = 1024;
+
+        let diagnostics = Diagnostics::default();
+        let (mut prod, mut cons) = HeapRb::<f32>::new(TARGET * CHANNELS * 4).split();
+        let mut compensator = DriftCompensator::new(TARGET);
+        let mic_block = [0.5f32; MIC_BLOCK * CHANNELS];
+        let mut out = [0.0f32; OUT_BLOCK * CHANNELS];
+
+        let mic_period = MIC_BLOCK as f64 / mic_rate;
+        let out_period = OUT_BLOCK as f64 / 48_000.0;
+        let (mut next_mic, mut next_out) = (0.0, 0.0);
+        let mut fills = Vec::new();
+
+        while next_out < seconds {
+            if next_mic <= next_out {
+                if prod.push_slice(&mic_block) < mic_block.len() {
+                    Diagnostics::count(&diagnostics.mic_overruns);
+                }
+                next_mic += mic_period;
+            } else {
+                if next_out > seconds - 1.0 {
+                    fills.push(cons.occupied_len() / CHANNELS);
+                }
+                compensator.process(&mut cons, &mut out, &diagnostics);
+                next_out += out_period;
+            }
+        }
+
+        (fills, diagnostics)
+    }
+
+    fn assert_settles(mic_rate: f64) {
+        let (fills, diagnostics) = simulate(mic_rate, 120.0);
+        let average = fills.iter().sum::<usize>() as f64 / fills.len() as f64;
+
+        assert!(
+            (average - 1024.0).abs() < 256.0,
+            "fill settled at {average} frames"
+        );
+        assert_eq!(diagnostics.mic_overruns.load(Ordering::Relaxed), 0);
+        assert_eq!(diagnostics.mic_underruns.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn compensates_fast_mic() {
+        assert_settles(48_000.0 * 1.0005);
+    }
+
+    #[test]
+    fn compensates_slow_mic() {
+        assert_settles(48_000.0 * 0.9995);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::app::audio::{
+    CHANNELS, Diagnostics, FilterChain, MixControl, Playhead, QueuedSound,
+    playhead::{self, PlayheadReader, PlayheadWriter},
+};
+use ringbuf::{
+    HeapCons, HeapProd, HeapRb,
+    traits::{Consumer, Observer, Producer, Split},
+};
+use std::sync::{Arc, Mutex, atomic::Ordering};
+
+pub use self::offline::OfflineEngine;
+
+mod offline;
+
+/// Ring buffers hold this many blocks.
+const RING_BLOCKS: usize = 4;
+
+/// Format of the blocks going through the engine.
+#[derive(Clone, Copy)]
+pub struct EngineConfig {
+    pub sample_rate: u32,
+    pub block_frames: u32,
+}
+
+impl EngineConfig {
+    #[inline]
+    pub fn block_samples(self) -> usize {
+        self.block_frames as usize * CHANNELS
+    }
+
+    #[inline]
+    pub fn ring_capacity(self) -> usize {
+        self.block_samples() * RING_BLOCKS
+    }
+}
+
+/// Splits the engine into the part that decodes sounds and the parts that produce output blocks.
+///
+/// The halves only share lock-free ring buffers (apart from the [`FilterChain`]s), so a driver can
+/// run each of them on whatever thread or device callback it likes. `filter_chain` filters the mic
+/// and `sound_filter_chain` all sounds.
+pub(super) fn engine(
+    config: EngineConfig,
+    decoder: Arc<Mutex<Option<QueuedSound>>>,
+    playhead: Arc<Playhead>,
+    filter_chain: Arc<Mutex<FilterChain>>,
+    sound_filter_chain: Arc<Mutex<FilterChain>>,
+    mix: Arc<MixControl>,
+    diagnostics: Arc<Diagnostics>,
+) -> (SoundBus, OutputMixer, VirtualMixer) {
+    let (local_prod, local_cons) = HeapRb::new(config.ring_capacity()).split();
+    let (virtual_prod, virtual_cons) = HeapRb::new(config.ring_capacity()).split();
+    let (mic_prod, mic_cons) = HeapRb::new(config.ring_capacity()).split();
+    let (writer, reader) = playhead::playhead(Arc::clone(&playhead), config.sample_rate);
+
+    let bus = SoundBus {
+        decoder,
+        playhead: Arc::clone(&playhead),
+        writer,
+        filter_chain: sound_filter_chain,
+        local: local_prod,
+        virtual_out: virtual_prod,
+        buf: vec![0.0; config.block_samples()],
+        ring_capacity: config.ring_capacity(),
+    };
+    let output = OutputMixer {
+        sounds: local_cons,
+        mic: mic_cons,
+        reader,
+        playhead,
+        mix: Arc::clone(&mix),
+        diagnostics,
+        ring_capacity: config.ring_capacity(),
+    };
+    let virtual_output = VirtualMixer {
+        sounds: virtual_cons,
+        mic_monitor: mic_prod,
+        filter_chain,
+        mix,
+    };
+
+    (bus, output, virtual_output)
+}
+
+/// Decodes the queued sound block by block, filters it and pushes it into the rings of both
+/// outputs, as silence for an output it isn't routed to.
+pub(super) struct SoundBus {
+    decoder: Arc<Mutex<Option<QueuedSound>>>,
+    playhead: Arc<Playhead>,
+    writer: PlayheadWriter,
+    filter_chain: Arc<Mutex<FilterChain>>,
+    local: HeapProd<f32>,
+    virtual_out: HeapProd<f32>,
+    buf: Vec<f32>,
+    ring_capacity: usize,
+}
+
+impl SoundBus {
+    /// Decodes until the rings are at least half full or there is nothing left to decode.
+    pub(super) fn fill(&mut self) {
+        while self.local.vacant_len() >= self.ring_capacity / 2 {
+            if !self.decode_block() {
+                break;
+            }
+        }
+    }
+
+    /// Pushes one block of the queued sound, returns `false` if no sound is queued.
+    fn decode_block(&mut self) -> bool {
+        let mut guard = self.decoder.lock().unwrap();
+        let Some(queued) = guard.as_mut() else {
+            // set while locked so this can't overwrite a sound queued in the meantime
+            self.playhead.set_decoding(false);
+            std::mem::drop(guard);
+            // stopped, the sound ends after what's already in the ring
+            self.writer.end();
+            return false;
+        };
+        self.playhead.set_decoding(true);
+        if !queued.source.is_ready() {
+            // the output keeps waking the bus up while decoding is set, nothing is pushed until
+            // then so the sound still starts at its beginning
+            std::mem::drop(guard);
+            self.writer.end();
+            return false;
+        }
+        self.writer.start(queued.id, queued.source.pos_nanos());
+        let route = queued.route;
+
+        let mut decoded = self.buf.len();
+        for (i, item) in self.buf.iter_mut().enumerate() {
+            match queued.source.next_sample() {
+                Some(sample) => *item = sample,
+                None => {
+                    decoded = decoded.min(i);
+                    *item = 0.0;
+                }
+            }
+        }
+
+        let eof = decoded < self.buf.len();
+        if eof {
+            // delete decoder
+            *guard = None;
+            self.playhead.set_decoding(false);
+        }
+        std::mem::drop(guard);
+
+        self.writer.advance(decoded / CHANNELS);
+        if eof {
+            self.writer.end();
+        }
+        self.writer.advance((self.buf.len() - decoded) / CHANNELS);
+
+        self.filter_chain.lock().unwrap().process(&mut self.buf);
+        // both rings get the block either way, the local output keeps the time of the playhead
+        let routed = |on: bool| move |sample: &f32| if on { *sample } else { 0.0 };
+        self.local
+            .push_iter(self.buf.iter().map(routed(route.plays_locally())));
+        self.virtual_out
+            .push_iter(self.buf.iter().map(routed(route.plays_virtually())));
+        true
+    }
+}
+
+/// Produces the blocks for the local output, which plays the sounds and the mic if it's monitored.
+pub(super) struct OutputMixer {
+    sounds: HeapCons<f32>,
+    /// The filtered mic from the [`VirtualMixer`].
+    mic: HeapCons<f32>,
+    reader: PlayheadReader,
+    playhead: Arc<Playhead>,
+    mix: Arc<MixControl>,
+    diagnostics: Arc<Diagnostics>,
+    ring_capacity: usize,
+}
+
+impl OutputMixer {
+    /// Adds the sounds to `out`, returns whether the [`SoundBus`] should be woken up to decode more.
+    pub(super) fn process(&mut self, out: &mut [f32]) -> bool {
+        self.diagnostics.sound_buffered_frames.store(
+            (self.sounds.occupied_len() / CHANNELS) as u64,
+            Ordering::Relaxed,
+        );
+
+        let volumes = self.mix.volumes();
+        self.add_mic(out, volumes.mic_monitor);
+
+        if self.mix.is_paused() {
+            self.mix.meter_monitor(out);
+            // the sound waits in the ring, the bus has nothing to do until playback resumes
+            return false;
+        }
+
+        let volume = volumes.monitor;
+        let mut played = 0;
+        for (item, sample) in out.iter_mut().zip(self.sounds.pop_iter()) {
+            *item += sample * volume;
+            played += 1;
+        }
+        self.mix.meter_monitor(out);
+
+        if self.reader.is_playing() && played < out.len() {
+            Diagnostics::count(&self.diagnostics.sound_underruns);
+        }
+        self.reader.advance(played / CHANNELS);
+
+        self.playhead.is_decoding() && self.sounds.vacant_len() >= self.ring_capacity / 2
+    }
+
+    fn add_mic(&mut self, out: &mut [f32], volume: f32) {
+        // the outputs run on their own clocks, dropping what this one fell behind on keeps the
+        // delay of the mic from growing
+        let behind = self
+            .mic
+            .occupied_len()
+            .saturating_sub(self.ring_capacity / 2);
+        self.mic.skip(behind - behind % CHANNELS);
+        for (item, sample) in out.iter_mut().zip(self.mic.pop_iter()) {
+            *item += sample * volume;
+        }
+    }
+}
+
+/// Produces the blocks for the virtual output, which gets the filtered mic plus the sounds.
+pub(super) struct VirtualMixer {
+    sounds: HeapCons<f32>,
+    /// The filtered mic for the [`OutputMixer`], only while it's monitored.
+    mic_monitor: HeapProd<f32>,
+    filter_chain: Arc<Mutex<FilterChain>>,
+    mix: Arc<MixControl>,
+}
+
+impl VirtualMixer {
+    /// Filters the mic samples already in `out`, then adds the sounds on top.
+    pub(super) fn process(&mut self, out: &mut [f32]) {
+        self.mix.meter_mic(out);
+        let volumes = self.mix.volumes();
+        for item in out.iter_mut() {
+            *item *= volumes.mic;
+        }
+        self.filter_chain.lock().unwrap().process(out);
+        if volumes.mic_monitor > 0.0 {
+            self.mic_monitor.push_slice(out);
+        }
+
+        if !self.mix.is_paused() {
+            let mut sounds = self.sounds.pop_iter();
+            for item in out.iter_mut() {
+                *item += sounds.next().unwrap_or_default() * volumes.sounds;
+            }
+        }
+        self.mix.meter_output(out);
+    }
+}
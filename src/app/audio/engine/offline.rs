@@ -0,0 +1,310 @@
+use crate::app::audio::{
+    Diagnostics, FilterChain, MixControl, Playhead, QueuedSound,
+    engine::{self, EngineConfig, OutputMixer, SoundBus, VirtualMixer},
+};
+use std::sync::{Arc, Mutex};
+
+/// Drives the engine without any audio devices, one block at a time.
+///
+/// The mic input is handed in directly instead of going through a ring buffer, so the output is
+/// sample-exact and the same for every run.
+pub struct OfflineEngine {
+    config: EngineConfig,
+    bus: SoundBus,
+    output: OutputMixer,
+    virtual_output: VirtualMixer,
+    local_block: Vec<f32>,
+    virtual_block: Vec<f32>,
+}
+
+impl OfflineEngine {
+    pub fn new(
+        config: EngineConfig,
+        decoder: Arc<Mutex<Option<QueuedSound>>>,
+        playhead: Arc<Playhead>,
+        filter_chain: Arc<Mutex<FilterChain>>,
+        sound_filter_chain: Arc<Mutex<FilterChain>>,
+        mix: Arc<MixControl>,
+    ) -> Self {
+        let (bus, output, virtual_output) = engine::engine(
+            config,
+            decoder,
+            playhead,
+            filter_chain,
+            sound_filter_chain,
+            mix,
+            Arc::new(Diagnostics::default()),
+        );
+
+        Self {
+            config,
+            bus,
+            output,
+            virtual_output,
+            local_block: Vec::with_capacity(config.block_samples()),
+            virtual_block: Vec::with_capacity(config.block_samples()),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> EngineConfig {
+        self.config
+    }
+
+    /// Processes up to one block of interleaved stereo mic samples, returning the blocks for the
+    /// local and the virtual output.
+    pub fn process(&mut self, mic: &[f32]) -> (&[f32], &[f32]) {
+        assert!(
+            mic.len() <= self.config.block_samples(),
+            "mic input is longer than a block"
+        );
+
+        self.bus.fill();
+
+        self.local_block.clear();
+        self.local_block.resize(mic.len(), 0.0);
+        self.output.process(&mut self.local_block);
+
+        self.virtual_block.clear();
+        self.virtual_block.extend_from_slice(mic);
+        self.virtual_output.process(&mut self.virtual_block);
+
+        (&self.local_block, &self.virtual_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{
+        Route,
+        audio::{CHANNELS, Levels, SoundSource, cache::CachedSound, cache::CachedSoundPlayer},
+        config::{Volumes, filter::FilterType},
+    };
+
+    const CONFIG: EngineConfig = EngineConfig {
+        sample_rate: 48_000,
+        block_frames: 64,
+    };
+
+    struct Harness {
+        engine: OfflineEngine,
+        decoder: Arc<Mutex<Option<QueuedSound>>>,
+        playhead: Arc<Playhead>,
+        filter_chain: Arc<Mutex<FilterChain>>,
+        sound_filter_chain: Arc<Mutex<FilterChain>>,
+        mix: Arc<MixControl>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let decoder = Arc::new(Mutex::new(None));
+            let playhead = Arc::new(Playhead::default());
+            let filter_chain = Arc::new(Mutex::new(FilterChain::new(CONFIG.sample_rate, CHANNELS)));
+            let sound_filter_chain =
+                Arc::new(Mutex::new(FilterChain::new(CONFIG.sample_rate, CHANNELS)));
+            let mix = Arc::new(MixControl::default());
+            let engine = OfflineEngine::new(
+                CONFIG,
+                Arc::clone(&decoder),
+                Arc::clone(&playhead),
+                Arc::clone(&filter_chain),
+                Arc::clone(&sound_filter_chain),
+                Arc::clone(&mix),
+            );
+
+            Self {
+                engine,
+                decoder,
+                playhead,
+                filter_chain,
+                sound_filter_chain,
+                mix,
+            }
+        }
+
+        fn play(&self, id: u64, frames: usize, value: f32) {
+            self.play_routed(id, frames, value, Route::Both);
+        }
+
+        fn play_routed(&self, id: u64, frames: usize, value: f32, route: Route) {
+            let sound = CachedSound::new(vec![value; frames * CHANNELS], CONFIG.sample_rate);
+            *self.decoder.lock().unwrap() = Some(QueuedSound {
+                id,
+                source: SoundSource::Cached(CachedSoundPlayer::new(Arc::new(sound), 1.0)),
+                route,
+            });
+        }
+
+        /// Renders `blocks` blocks with a constant mic signal.
+        fn render(&mut self, blocks: usize, mic: f32) -> (Vec<f32>, Vec<f32>) {
+            let mic = vec![mic; CONFIG.block_samples()];
+            let (mut local, mut virtual_out) = (Vec::new(), Vec::new());
+            for _ in 0..blocks {
+                let (local_block, virtual_block) = self.engine.process(&mic);
+                local.extend_from_slice(local_block);
+                virtual_out.extend_from_slice(virtual_block);
+            }
+            (local, virtual_out)
+        }
+    }
+
+    #[test]
+    fn mixes_sounds_over_the_mic() {
+        let mut harness = Harness::new();
+        harness.play(1, 100, 0.25);
+
+        let (local, virtual_out) = harness.render(4, 0.5);
+
+        let sound_end = 100 * CHANNELS;
+        assert!(local[..sound_end].iter().all(|&s| s == 0.25));
+        assert!(local[sound_end..].iter().all(|&s| s == 0.0));
+        assert!(virtual_out[..sound_end].iter().all(|&s| s == 0.75));
+        assert!(virtual_out[sound_end..].iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn filters_the_mic_but_not_the_sounds() {
+        let mut harness = Harness::new();
+        harness
+            .filter_chain
+            .lock()
+            .unwrap()
+            .sync([FilterType::Shittify {
+                strength: 12.0,
+                cutoff: 0.5,
+            }]);
+        harness.play(1, 1000, 0.125);
+
+        let (local, virtual_out) = harness.render(4, 0.25);
+
+        // the mic gets boosted and clipped to the cutoff, the sound passes through untouched
+        let clipped = (0.5 * i16::MAX as f32) as i16 as f32 / i16::MAX as f32;
+        assert!(local.iter().all(|&s| s == 0.125));
+        assert!(virtual_out.iter().all(|&s| s == clipped + 0.125));
+    }
+
+    #[test]
+    fn filters_the_sounds_but_not_the_mic() {
+        let mut harness = Harness::new();
+        harness
+            .sound_filter_chain
+            .lock()
+            .unwrap()
+            .sync([FilterType::Shittify {
+                strength: 12.0,
+                cutoff: 0.5,
+            }]);
+        harness.play(1, 1000, 0.125);
+
+        let (local, virtual_out) = harness.render(4, 0.25);
+
+        // the sound gets clipped on both outputs, the mic passes through untouched
+        let clipped = (0.5 * i16::MAX as f32) as i16 as f32 / i16::MAX as f32;
+        assert!(local.iter().all(|&s| s == clipped));
+        assert!(virtual_out.iter().all(|&s| s == 0.25 + clipped));
+    }
+
+    #[test]
+    fn reports_sound_position_and_end() {
+        let mut harness = Harness::new();
+        harness.play(7, 200, 0.5);
+
+        harness.render(1, 0.0);
+        let pos = harness.playhead.pos_nanos(7);
+        assert_eq!(pos, 64 * 1_000_000_000 / CONFIG.sample_rate as u64);
+        assert!(!harness.playhead.has_finished(7));
+
+        harness.render(3, 0.0);
+        assert!(harness.playhead.has_finished(7));
+        assert_eq!(harness.playhead.pos_nanos(7), 0);
+    }
+
+    #[test]
+    fn applies_volumes() {
+        let mut harness = Harness::new();
+        harness.mix.set_volumes(Volumes {
+            mic: 0.5,
+            sounds: 0.25,
+            monitor: 2.0,
+            mic_monitor: 0.0,
+        });
+        harness.play(1, 1000, 0.5);
+
+        let (local, virtual_out) = harness.render(4, 0.5);
+
+        assert!(local.iter().all(|&s| s == 1.0));
+        assert!(virtual_out.iter().all(|&s| s == 0.25 + 0.125));
+        assert_eq!(
+            harness.mix.take_levels(),
+            Levels {
+                output: 0.375,
+                monitor: 1.0
+            }
+        );
+        assert_eq!(harness.mix.take_levels(), Levels::default());
+        assert_eq!(harness.mix.take_mic_level(), 0.5);
+    }
+
+    #[test]
+    fn routes_sounds_to_their_outputs() {
+        let mut harness = Harness::new();
+        harness.play_routed(1, 1000, 0.25, Route::Local);
+
+        let (local, virtual_out) = harness.render(4, 0.5);
+
+        assert!(local.iter().all(|&s| s == 0.25));
+        assert!(virtual_out.iter().all(|&s| s == 0.5));
+        // the playhead still follows the local output
+        let pos = harness.playhead.pos_nanos(1);
+        assert_eq!(pos, 4 * 64 * 1_000_000_000 / CONFIG.sample_rate as u64);
+    }
+
+    #[test]
+    fn monitors_the_mic_locally() {
+        let mut harness = Harness::new();
+        harness.mix.set_volumes(Volumes {
+            mic_monitor: 0.5,
+            ..Volumes::default()
+        });
+
+        let (local, virtual_out) = harness.render(3, 0.5);
+
+        // the local output gets the mic from the block the virtual output rendered before
+        let block = CONFIG.block_samples();
+        assert!(local[..block].iter().all(|&s| s == 0.0));
+        assert!(local[block..].iter().all(|&s| s == 0.25));
+        assert!(virtual_out.iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn pausing_holds_the_sound() {
+        let mut harness = Harness::new();
+        harness.play(3, 100, 0.5);
+        harness.render(1, 0.0);
+        let pos = harness.playhead.pos_nanos(3);
+
+        harness.mix.set_paused(true);
+        let (local, virtual_out) = harness.render(4, 0.25);
+        assert!(local.iter().all(|&s| s == 0.0));
+        assert!(virtual_out.iter().all(|&s| s == 0.25));
+        assert_eq!(harness.playhead.pos_nanos(3), pos);
+
+        harness.mix.set_paused(false);
+        let (local, _) = harness.render(1, 0.0);
+        let rest = (100 - 64) * CHANNELS;
+        assert!(local[..rest].iter().all(|&s| s == 0.5));
+        assert!(local[rest..].iter().all(|&s| s == 0.0));
+        assert!(harness.playhead.has_finished(3));
+    }
+
+    #[test]
+    fn renders_silence_without_input() {
+        let mut harness = Harness::new();
+
+        let (local, virtual_out) = harness.render(8, 0.0);
+
+        assert!(local.iter().chain(&virtual_out).all(|&s| s == 0.0));
+        assert!(!harness.playhead.has_finished(1));
+    }
+}
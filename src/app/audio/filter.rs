@@ -24,7 +24,7 @@ pub trait AudioProcessor: Send {
 }
 
 impl FilterChain {
-    pub(super) fn new(sample_rate: u32, channels: usize) -> FilterChain {
+    pub fn new(sample_rate: u32, channels: usize) -> FilterChain {
         FilterChain {
             filters: Vec::new(),
             context: ProcessContext {
@@ -0,0 +1,118 @@
+use crate::app::config::Volumes;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Volumes and the pause state, read by the mixers on every block and changed from any thread.
+/// The mixers also report their peak levels back through it.
+pub struct MixControl {
+    mic: AtomicU32,
+    sounds: AtomicU32,
+    monitor: AtomicU32,
+    mic_monitor: AtomicU32,
+    paused: AtomicBool,
+    output_peak: AtomicU32,
+    monitor_peak: AtomicU32,
+    mic_peak: AtomicU32,
+}
+
+/// Peak sample values, `1.0` is full scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Levels {
+    /// What the virtual output sends to others.
+    pub output: f32,
+    /// What the local output plays.
+    pub monitor: f32,
+}
+
+impl MixControl {
+    pub fn new(volumes: Volumes) -> Self {
+        let mix = Self {
+            mic: AtomicU32::default(),
+            sounds: AtomicU32::default(),
+            monitor: AtomicU32::default(),
+            mic_monitor: AtomicU32::default(),
+            paused: AtomicBool::new(false),
+            output_peak: AtomicU32::default(),
+            monitor_peak: AtomicU32::default(),
+            mic_peak: AtomicU32::default(),
+        };
+        mix.set_volumes(volumes);
+        mix
+    }
+
+    pub fn volumes(&self) -> Volumes {
+        let load = |volume: &AtomicU32| f32::from_bits(volume.load(Ordering::Relaxed));
+        Volumes {
+            mic: load(&self.mic),
+            sounds: load(&self.sounds),
+            monitor: load(&self.monitor),
+            mic_monitor: load(&self.mic_monitor),
+        }
+    }
+
+    pub fn set_volumes(&self, volumes: Volumes) {
+        self.mic.store(volumes.mic.to_bits(), Ordering::Relaxed);
+        self.sounds
+            .store(volumes.sounds.to_bits(), Ordering::Relaxed);
+        self.monitor
+            .store(volumes.monitor.to_bits(), Ordering::Relaxed);
+        self.mic_monitor
+            .store(volumes.mic_monitor.to_bits(), Ordering::Relaxed);
+    }
+
+    /// While paused the mixers leave the sounds in the rings, so playback resumes where it was.
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// The peaks since the last call.
+    pub fn take_levels(&self) -> Levels {
+        let take = |peak: &AtomicU32| f32::from_bits(peak.swap(0, Ordering::Relaxed));
+        Levels {
+            output: take(&self.output_peak),
+            monitor: take(&self.monitor_peak),
+        }
+    }
+
+    /// The peak of the mic before its volume and the filters since the last call. Separate from
+    /// [`Self::take_levels`] so scripts listening to the mic don't take the levels from OSC.
+    pub fn take_mic_level(&self) -> f32 {
+        f32::from_bits(self.mic_peak.swap(0, Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub(super) fn meter_mic(&self, block: &[f32]) {
+        Self::meter(&self.mic_peak, block);
+    }
+
+    #[inline]
+    pub(super) fn meter_output(&self, block: &[f32]) {
+        Self::meter(&self.output_peak, block);
+    }
+
+    #[inline]
+    pub(super) fn meter_monitor(&self, block: &[f32]) {
+        Self::meter(&self.monitor_peak, block);
+    }
+
+    fn meter(peak: &AtomicU32, block: &[f32]) {
+        let block_peak = block
+            .iter()
+            .map(|sample| sample.abs())
+            .filter(|sample| !sample.is_nan())
+            .fold(0.0, f32::max);
+        // the bits of non-negative floats order the same way as the floats
+        peak.fetch_max(block_peak.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Default for MixControl {
+    fn default() -> Self {
+        Self::new(Volumes::default())
+    }
+}
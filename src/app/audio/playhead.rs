@@ -0,0 +1,243 @@
+use crate::app::{Route, audio::SoundSource};
+use ringbuf::{
+    HeapCons, HeapProd, HeapRb,
+    traits::{Consumer, Producer, Split},
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// How many sound boundaries can be in flight between the decoder thread and the output.
+const MARKER_CAPACITY: usize = 64;
+
+/// A sound handed to the decoder thread, tagged so it can be followed through the ring buffers.
+pub struct QueuedSound {
+    pub id: u64,
+    pub source: SoundSource,
+    pub route: Route,
+}
+
+/// Playback state published by the audio threads.
+#[derive(Default)]
+pub struct Playhead {
+    /// Id of the sound currently heard on the output, 0 if none.
+    audible: AtomicU64,
+    pos_nanos: AtomicU64,
+    /// The highest id of the sounds that ended on the output, ids only go up.
+    finished: AtomicU64,
+    /// Whether the decoder thread has a sound to decode, so the output knows to wake it up.
+    decoding: AtomicBool,
+}
+
+impl Playhead {
+    /// Position of the sound with the given id, as far as it has been played by the output.
+    pub fn pos_nanos(&self, id: u64) -> u64 {
+        match self.audible.load(Ordering::Relaxed) == id {
+            true => self.pos_nanos.load(Ordering::Relaxed),
+            false => 0,
+        }
+    }
+
+    /// Whether the sound with the given id ended on the output.
+    pub fn has_finished(&self, id: u64) -> bool {
+        self.finished.load(Ordering::Relaxed) >= id
+    }
+
+    #[inline]
+    pub(super) fn is_decoding(&self) -> bool {
+        self.decoding.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_decoding(&self, decoding: bool) {
+        self.decoding.store(decoding, Ordering::Relaxed);
+    }
+}
+
+/// Boundary of a sound in the stream of frames going through the ring buffer.
+#[derive(Clone, Copy)]
+enum Marker {
+    Start { id: u64, at: u64, offset_nanos: u64 },
+    End { id: u64, at: u64 },
+}
+
+impl Marker {
+    fn at(self) -> u64 {
+        match self {
+            Self::Start { at, .. } | Self::End { at, .. } => at,
+        }
+    }
+}
+
+/// Creates the decoder thread and output callback halves of a playhead.
+pub(super) fn playhead(
+    playhead: Arc<Playhead>,
+    sample_rate: u32,
+) -> (PlayheadWriter, PlayheadReader) {
+    let (markers_prod, markers_cons) = HeapRb::new(MARKER_CAPACITY).split();
+    let writer = PlayheadWriter {
+        markers: markers_prod,
+        produced: 0,
+        current: None,
+    };
+    let reader = PlayheadReader {
+        playhead,
+        markers: markers_cons,
+        sample_rate,
+        consumed: 0,
+        current: None,
+    };
+    (writer, reader)
+}
+
+/// Decoder thread half, records where sounds start and end as frames are pushed.
+pub(super) struct PlayheadWriter {
+    markers: HeapProd<Marker>,
+    /// Frames pushed into the ring since the streams started.
+    produced: u64,
+    current: Option<u64>,
+}
+
+impl PlayheadWriter {
+    /// Marks the next frames as belonging to the sound `id`, ending the previous one if it differs.
+    pub(super) fn start(&mut self, id: u64, offset_nanos: u64) {
+        if self.current == Some(id) {
+            return;
+        }
+        self.end();
+        self.current = Some(id);
+        self.push(Marker::Start {
+            id,
+            at: self.produced,
+            offset_nanos,
+        });
+    }
+
+    /// Marks the current sound as ending after the frames pushed so far.
+    pub(super) fn end(&mut self) {
+        if let Some(id) = self.current.take() {
+            self.push(Marker::End {
+                id,
+                at: self.produced,
+            });
+        }
+    }
+
+    #[inline]
+    pub(super) fn advance(&mut self, frames: usize) {
+        self.produced += frames as u64;
+    }
+
+    fn push(&mut self, marker: Marker) {
+        if self.markers.try_push(marker).is_err() {
+            tracing::warn!("Playhead markers are full, the playback position will be off");
+        }
+    }
+}
+
+/// Output callback half, follows the markers as frames are played and reports finished sounds.
+pub(super) struct PlayheadReader {
+    playhead: Arc<Playhead>,
+    markers: HeapCons<Marker>,
+    sample_rate: u32,
+    /// Frames popped from the ring since the streams started.
+    consumed: u64,
+    /// Id, start frame and start offset of the sound being played.
+    current: Option<(u64, u64, u64)>,
+}
+
+impl PlayheadReader {
+    #[inline]
+    pub(super) fn is_playing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub(super) fn advance(&mut self, frames: usize) {
+        self.consumed += frames as u64;
+
+        while let Some(&marker) = self.markers.try_peek() {
+            if marker.at() > self.consumed {
+                break;
+            }
+            self.markers.try_pop();
+
+            match marker {
+                Marker::Start {
+                    id,
+                    at,
+                    offset_nanos,
+                } => {
+                    self.current = Some((id, at, offset_nanos));
+                    self.playhead.audible.store(id, Ordering::Relaxed);
+                }
+                Marker::End { id, .. } => {
+                    if self.current.is_some_and(|(current, ..)| current == id) {
+                        self.current = None;
+                        self.playhead.audible.store(0, Ordering::Relaxed);
+                    }
+                    self.playhead.finished.fetch_max(id, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if let Some((_, start, offset_nanos)) = self.current {
+            let secs = (self.consumed - start) as f64 / self.sample_rate as f64;
+            self.playhead.pos_nanos.store(
+                offset_nanos + (secs * 1_000_000_000.0) as u64,
+                Ordering::Relaxed,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn follows_the_played_frames() {
+        let shared = Arc::new(Playhead::default());
+        let (mut writer, mut reader) = playhead(Arc::clone(&shared), SAMPLE_RATE);
+
+        // 100 frames of silence, then a 250 frame sound decoded ahead of the output
+        writer.advance(100);
+        writer.start(1, 0);
+        writer.advance(250);
+        writer.end();
+
+        reader.advance(50);
+        assert!(!reader.is_playing());
+        assert_eq!(shared.pos_nanos(1), 0);
+
+        reader.advance(150);
+        assert!(reader.is_playing());
+        assert_eq!(shared.pos_nanos(1), 100_000_000);
+        assert!(!shared.has_finished(1));
+
+        reader.advance(200);
+        assert!(!reader.is_playing());
+        assert!(shared.has_finished(1));
+    }
+
+    #[test]
+    fn replaced_sound_finishes_before_the_next_starts() {
+        let shared = Arc::new(Playhead::default());
+        let (mut writer, mut reader) = playhead(Arc::clone(&shared), SAMPLE_RATE);
+
+        writer.start(1, 0);
+        writer.advance(100);
+        writer.start(2, 0);
+        writer.advance(100);
+
+        reader.advance(50);
+        assert_eq!(shared.pos_nanos(1), 50_000_000);
+        assert_eq!(shared.pos_nanos(2), 0);
+
+        reader.advance(100);
+        assert!(shared.has_finished(1));
+        assert!(!shared.has_finished(2));
+        assert_eq!(shared.pos_nanos(2), 50_000_000);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::app::audio::Diagnostics;
+use cpal::{
+    BufferSize, Device, ErrorKind, FromSample, I24, InputCallbackInfo, OutputCallbackInfo,
+    SampleFormat, SizedSample, Stream, StreamConfig, SupportedBufferSize, SupportedStreamConfig,
+    U24, traits::DeviceTrait,
+};
+use std::sync::Arc;
+
+/// Frames the conversion buffers have room for at least, hosts that ignore the requested buffer
+/// size rarely go beyond this.
+const SCRATCH_FRAMES: usize = 8192;
+
+/// Dispatches on the device sample format, handing `$build::<T>` the concrete sample type.
+macro_rules! with_sample_type {
+    ($format:expr, $build:ident($($arg:expr),* $(,)?)) => {
+        match $format {
+            SampleFormat::F32 => $build::<f32>($($arg),*),
+            SampleFormat::F64 => $build::<f64>($($arg),*),
+            SampleFormat::I8 => $build::<i8>($($arg),*),
+            SampleFormat::I16 => $build::<i16>($($arg),*),
+            SampleFormat::I24 => $build::<I24>($($arg),*),
+            SampleFormat::I32 => $build::<i32>($($arg),*),
+            SampleFormat::U8 => $build::<u8>($($arg),*),
+            SampleFormat::U16 => $build::<u16>($($arg),*),
+            SampleFormat::U24 => $build::<U24>($($arg),*),
+            SampleFormat::U32 => $build::<u32>($($arg),*),
+            format => Err(cpal::Error::with_message(
+                cpal::ErrorKind::UnsupportedConfig,
+                format!("unsupported sample format {format}"),
+            )),
+        }
+    };
+}
+
+/// Builds an input stream in the device's native sample format, handing `on_data` f32 samples.
+pub(super) fn build_input_stream(
+    device: &Device,
+    config: SupportedStreamConfig,
+    block_frames: u32,
+    name: &'static str,
+    diagnostics: &Arc<Diagnostics>,
+    on_data: impl FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+) -> Result<Stream, cpal::Error> {
+    fn build<T>(
+        device: &Device,
+        config: StreamConfig,
+        name: &'static str,
+        diagnostics: Arc<Diagnostics>,
+        mut on_data: impl FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    ) -> Result<Stream, cpal::Error>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let mut scratch = Vec::with_capacity(scratch_samples(&config));
+        device.build_input_stream(
+            config,
+            move |data: &[T], info| {
+                scratch.clear();
+                scratch.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+                on_data(&scratch, info);
+            },
+            error_callback(name, diagnostics),
+            None,
+        )
+    }
+
+    let format = config.sample_format();
+    let config = stream_config(&config, block_frames);
+    let diagnostics = Arc::clone(diagnostics);
+    with_sample_type!(format, build(device, config, name, diagnostics, on_data))
+}
+
+/// Builds an output stream in the device's native sample format, letting `on_data` fill f32 samples.
+///
+/// Integer formats are dithered before quantization.
+pub(super) fn build_output_stream(
+    device: &Device,
+    config: SupportedStreamConfig,
+    block_frames: u32,
+    name: &'static str,
+    diagnostics: &Arc<Diagnostics>,
+    on_data: impl FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+) -> Result<Stream, cpal::Error> {
+    fn build<T>(
+        device: &Device,
+        config: StreamConfig,
+        format: SampleFormat,
+        name: &'static str,
+        diagnostics: Arc<Diagnostics>,
+        mut on_data: impl FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    ) -> Result<Stream, cpal::Error>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let mut scratch = Vec::with_capacity(scratch_samples(&config));
+        let mut dither = Dither::new(format);
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], info| {
+                scratch.clear();
+                scratch.resize(data.len(), 0.0);
+                on_data(&mut scratch, info);
+                quantize(&scratch, data, &mut dither);
+            },
+            error_callback(name, diagnostics),
+            None,
+        )
+    }
+
+    let format = config.sample_format();
+    let config = stream_config(&config, block_frames);
+    let diagnostics = Arc::clone(diagnostics);
+    if format == SampleFormat::F32 {
+        // no conversion needed, let the callback write into the device buffer directly
+        let mut on_data = on_data;
+        return device.build_output_stream(
+            config,
+            move |data: &mut [f32], info| {
+                data.fill(0.0);
+                on_data(data, info);
+            },
+            error_callback(name, diagnostics),
+            None,
+        );
+    }
+
+    with_sample_type!(
+        format,
+        build(device, config, format, name, diagnostics, on_data)
+    )
+}
+
+/// Room for the samples of the largest callback expected, so converting doesn't allocate in the
+/// audio callback.
+fn scratch_samples(config: &StreamConfig) -> usize {
+    let frames = match config.buffer_size {
+        BufferSize::Fixed(frames) => (frames as usize).max(SCRATCH_FRAMES),
+        BufferSize::Default => SCRATCH_FRAMES,
+    };
+    frames * config.channels as usize
+}
+
+/// Dithers `samples` and converts them to the integer or float format of `out`.
+fn quantize<T: SizedSample + FromSample<f32>>(samples: &[f32], out: &mut [T], dither: &mut Dither) {
+    for (out, sample) in out.iter_mut().zip(samples) {
+        *out = T::from_sample(dither.apply(*sample).clamp(-1.0, 1.0));
+    }
+}
+
+/// Requests callbacks of `block_frames` frames, clamped to the range the device supports.
+fn stream_config(config: &SupportedStreamConfig, block_frames: u32) -> StreamConfig {
+    let buffer_size = match *config.buffer_size() {
+        SupportedBufferSize::Range { min, max } => BufferSize::Fixed(block_frames.clamp(min, max)),
+        // the host can't tell us what it accepts, asking for something it doesn't would fail
+        SupportedBufferSize::Unknown => BufferSize::Default,
+    };
+
+    StreamConfig {
+        buffer_size,
+        ..config.config()
+    }
+}
+
+fn error_callback(
+    name: &'static str,
+    diagnostics: Arc<Diagnostics>,
+) -> impl FnMut(cpal::Error) + Send + 'static {
+    move |err| match err.kind() {
+        ErrorKind::Xrun => Diagnostics::count(&diagnostics.device_xruns),
+        _ => eprintln!("{name} stream error: {err}"),
+    }
+}
+
+/// TPDF dither with an amplitude of one LSB of the target integer format.
+struct Dither {
+    lsb: f32,
+    state: u32,
+}
+
+impl Dither {
+    fn new(format: SampleFormat) -> Self {
+        // f32 can't represent noise below 24 bits of precision anyway
+        let lsb = match format.is_float() || format.bits_per_sample() > 24 {
+            true => 0.0,
+            false => 2.0 / (1u32 << format.bits_per_sample()) as f32,
+        };
+
+        Self {
+            lsb,
+            state: 0x9E37_79B9,
+        }
+    }
+
+    #[inline]
+    fn apply(&mut self, sample: f32) -> f32 {
+        if self.lsb == 0.0 {
+            return sample;
+        }
+        sample + (self.next_unit() - self.next_unit()) * self.lsb
+    }
+
+    /// Uniform random number in `0.0..1.0` (xorshift32).
+    #[inline]
+    fn next_unit(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::Sample;
+
+    #[test]
+    fn scales_to_the_full_integer_range() {
+        // without dither, so the results are exact
+        let mut dither = Dither::new(SampleFormat::F32);
+        let samples = [-1.0, 0.0, 1.0, 2.0];
+
+        let mut out = [0i16; 4];
+        quantize(&samples, &mut out, &mut dither);
+        assert_eq!(out, [i16::MIN, 0, i16::MAX, i16::MAX]);
+
+        let mut out = [0u8; 4];
+        quantize(&samples, &mut out, &mut dither);
+        assert_eq!(out, [0, 128, 255, 255]);
+
+        assert_eq!(i16::MIN.to_sample::<f32>(), -1.0);
+        assert_eq!(0u8.to_sample::<f32>(), -1.0);
+    }
+
+    #[test]
+    fn dithers_by_one_lsb_around_the_sample() {
+        let mut dither = Dither::new(SampleFormat::I16);
+        let lsb = 2.0 / 65536.0;
+        let noise: Vec<f32> = (0..100_000).map(|_| dither.apply(0.25) - 0.25).collect();
+
+        assert!(noise.iter().all(|noise| noise.abs() < lsb));
+        let mean = noise.iter().sum::<f32>() / noise.len() as f32;
+        assert!(mean.abs() < lsb * 0.01);
+        // triangular, so most of the noise is in the middle half
+        let middle = noise.iter().filter(|noise| noise.abs() < lsb / 2.0).count();
+        assert!((0.7..0.8).contains(&(middle as f32 / noise.len() as f32)));
+
+        assert_eq!(Dither::new(SampleFormat::I32).apply(0.25), 0.25);
+        assert_eq!(Dither::new(SampleFormat::F32).apply(0.25), 0.25);
+    }
+}
@@ -0,0 +1,567 @@
+use crate::app::{
+    audio::{AudioDecoder, CHANNELS, FilterChain, cache::CachedSoundPlayer, stretch::Stretched},
+    config::filter::FilterType,
+};
+use std::{
+    iter,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+/// How much of a sound is played backwards, all of it is held in memory.
+const MAX_REVERSED: Duration = Duration::from_secs(120);
+
+/// Where the samples of a playing sound come from.
+pub enum SoundSource {
+    /// Decoded from the file while playing.
+    Stream(AudioDecoder),
+    /// Played back from the in-memory [`super::SoundCache`].
+    Cached(CachedSoundPlayer),
+    /// Several sounds mixed together.
+    Layered(Layers),
+    /// Another source played faster or slower, which also shifts its pitch.
+    Pitched(Box<Pitched>),
+    /// Another source played faster or slower without shifting its pitch.
+    Stretched(Box<Stretched>),
+    /// Another source played backwards.
+    Reversed(Box<Reversed>),
+    /// Another source through a filter chain of its own.
+    Filtered(Box<Filtered>),
+}
+
+/// Sounds that start together or shortly after each other and play as one.
+pub struct Layers {
+    layers: Vec<Layer>,
+    sample_rate: u32,
+    /// Samples, not frames.
+    played: u64,
+}
+
+/// Resamples another source by linearly interpolating between its frames.
+pub struct Pitched {
+    source: SoundSource,
+    rate: f64,
+    /// The frame before and after the read position, `None` once the source ran out.
+    current: Option<[f32; CHANNELS]>,
+    next: Option<[f32; CHANNELS]>,
+    /// How far the read position is between `current` and `next`.
+    fraction: f64,
+    frame: [f32; CHANNELS],
+    /// The channel of `frame` returned next, `CHANNELS` when a new frame is due.
+    channel: usize,
+    /// Whether `current` and `next` were read, which waits until the first sample is needed.
+    primed: bool,
+}
+
+/// Plays another source backwards, which means reading all of it before the first sample. That
+/// happens on a thread of its own, see [`SoundSource::is_ready`].
+pub struct Reversed {
+    /// Gets the reversed samples once the source has been read.
+    decoding: Option<Receiver<Vec<f32>>>,
+    /// How long the source is, until it has been read.
+    duration: Option<Duration>,
+    /// Frames in reverse order, the channels of each frame in their usual order.
+    samples: Vec<f32>,
+    /// Samples of `samples` already returned.
+    idx: usize,
+    sample_rate: u32,
+    /// Where a seek before the source was read goes to.
+    pending_seek: Option<Duration>,
+}
+
+/// Runs another source through its own filters, a block at a time.
+pub struct Filtered {
+    source: SoundSource,
+    chain: FilterChain,
+    block: Vec<f32>,
+    /// Samples of `block` already returned.
+    idx: usize,
+    sample_rate: u32,
+}
+
+struct Layer {
+    source: SoundSource,
+    /// Samples of silence before the source starts.
+    offset: u64,
+    finished: bool,
+}
+
+impl SoundSource {
+    pub fn next_sample(&mut self) -> Option<f32> {
+        match self {
+            Self::Stream(decoder) => decoder.next_sample(),
+            Self::Cached(player) => player.next_sample(),
+            Self::Layered(layers) => layers.next_sample(),
+            Self::Pitched(pitched) => pitched.next_sample(),
+            Self::Stretched(stretched) => stretched.next_sample(),
+            Self::Reversed(reversed) => reversed.next_sample(),
+            Self::Filtered(filtered) => filtered.next_sample(),
+        }
+    }
+
+    pub fn seek(&mut self, pos: Duration) -> Result<(), String> {
+        match self {
+            Self::Stream(decoder) => decoder.seek(pos).map_err(|err| err.to_string()),
+            Self::Cached(player) => {
+                player.seek(pos);
+                Ok(())
+            }
+            Self::Layered(_) => Err("Layered sounds can't seek".to_string()),
+            Self::Pitched(pitched) => pitched.seek(pos),
+            Self::Stretched(stretched) => stretched.seek(pos),
+            Self::Reversed(reversed) => {
+                reversed.seek(pos);
+                Ok(())
+            }
+            Self::Filtered(filtered) => filtered.seek(pos),
+        }
+    }
+
+    /// Whether samples can be read without waiting, which reversed sounds have to be read in full
+    /// for first.
+    pub fn is_ready(&mut self) -> bool {
+        match self {
+            Self::Stream(_) | Self::Cached(_) => true,
+            Self::Layered(layers) => layers
+                .layers
+                .iter_mut()
+                .all(|layer| layer.source.is_ready()),
+            Self::Pitched(pitched) => pitched.source.is_ready(),
+            Self::Stretched(stretched) => stretched.is_ready(),
+            Self::Reversed(reversed) => reversed.receive(false),
+            Self::Filtered(filtered) => filtered.source.is_ready(),
+        }
+    }
+
+    pub fn pos_nanos(&self) -> u64 {
+        match self {
+            Self::Stream(decoder) => decoder.pos_nanos(),
+            Self::Cached(player) => player.pos_nanos(),
+            Self::Layered(layers) => layers.pos_nanos(),
+            Self::Pitched(pitched) => pitched.pos_nanos(),
+            Self::Stretched(stretched) => stretched.pos_nanos(),
+            Self::Reversed(reversed) => reversed.pos_nanos(),
+            Self::Filtered(filtered) => filtered.pos_nanos(),
+        }
+    }
+
+    pub fn total_duration(&self) -> Option<Duration> {
+        match self {
+            Self::Stream(decoder) => decoder.total_duration(),
+            Self::Cached(player) => Some(player.total_duration()),
+            Self::Layered(layers) => layers.total_duration(),
+            Self::Pitched(pitched) => pitched.total_duration(),
+            Self::Stretched(stretched) => stretched.total_duration(),
+            Self::Reversed(reversed) => reversed.total_duration(),
+            Self::Filtered(filtered) => filtered.total_duration(),
+        }
+    }
+}
+
+impl Layers {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            layers: Vec::new(),
+            sample_rate,
+            played: 0,
+        }
+    }
+
+    /// Adds `source` to start `offset` after the first layer.
+    pub fn push(&mut self, source: SoundSource, offset: Duration) {
+        let frames = (offset.as_secs_f64() * self.sample_rate as f64).round() as u64;
+        self.layers.push(Layer {
+            source,
+            offset: frames * CHANNELS as u64,
+            finished: false,
+        });
+    }
+
+    /// Adds `source` to start with the next sample, on top of the layers already playing.
+    pub fn push_now(&mut self, source: SoundSource) {
+        self.layers.push(Layer {
+            source,
+            offset: self.played,
+            finished: false,
+        });
+    }
+
+    /// Stops the layer that was pushed `idx`th.
+    pub fn remove(&mut self, idx: usize) {
+        self.layers.remove(idx);
+    }
+
+    fn next_sample(&mut self) -> Option<f32> {
+        let mut sample = 0.0;
+        let mut playing = false;
+        for layer in &mut self.layers {
+            if layer.finished {
+                continue;
+            }
+            if self.played < layer.offset {
+                playing = true;
+                continue;
+            }
+            match layer.source.next_sample() {
+                Some(layer_sample) => {
+                    sample += layer_sample;
+                    playing = true;
+                }
+                None => layer.finished = true,
+            }
+        }
+
+        if !playing {
+            return None;
+        }
+        self.played += 1;
+        Some(sample)
+    }
+
+    fn pos_nanos(&self) -> u64 {
+        let frames = self.played / CHANNELS as u64;
+        (frames as f64 / self.sample_rate as f64 * 1_000_000_000.0) as u64
+    }
+
+    /// When the last layer ends, `None` while any layer doesn't know its duration.
+    fn total_duration(&self) -> Option<Duration> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                let offset = layer.offset / CHANNELS as u64;
+                let offset = Duration::from_secs_f64(offset as f64 / self.sample_rate as f64);
+                Some(offset + layer.source.total_duration()?)
+            })
+            .try_fold(Duration::ZERO, |end, layer_end| Some(end.max(layer_end?)))
+    }
+}
+
+impl Pitched {
+    /// `rate` above `1.0` plays `source` faster and higher.
+    pub fn new(source: SoundSource, rate: f32) -> Self {
+        Self {
+            source,
+            rate: rate as f64,
+            current: None,
+            next: None,
+            fraction: 0.0,
+            frame: [0.0; CHANNELS],
+            channel: CHANNELS,
+            primed: false,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current = Self::read_frame(&mut self.source);
+        self.next = Self::read_frame(&mut self.source);
+        self.fraction = 0.0;
+        self.channel = CHANNELS;
+        self.primed = true;
+    }
+
+    fn read_frame(source: &mut SoundSource) -> Option<[f32; CHANNELS]> {
+        let mut frame = [0.0; CHANNELS];
+        for sample in &mut frame {
+            *sample = source.next_sample()?;
+        }
+        Some(frame)
+    }
+
+    fn next_sample(&mut self) -> Option<f32> {
+        if !self.primed {
+            self.restart();
+        }
+        if self.channel == CHANNELS {
+            let current = self.current?;
+            let next = self.next.unwrap_or(current);
+            let fraction = self.fraction as f32;
+            for (i, sample) in self.frame.iter_mut().enumerate() {
+                *sample = current[i] + (next[i] - current[i]) * fraction;
+            }
+            self.channel = 0;
+
+            self.fraction += self.rate;
+            while self.fraction >= 1.0 && self.current.is_some() {
+                self.fraction -= 1.0;
+                self.current = self.next;
+                self.next = Self::read_frame(&mut self.source);
+            }
+        }
+        let sample = self.frame[self.channel];
+        self.channel += 1;
+        Some(sample)
+    }
+
+    fn seek(&mut self, pos: Duration) -> Result<(), String> {
+        self.source.seek(pos.mul_f64(self.rate))?;
+        self.primed = false;
+        Ok(())
+    }
+
+    fn pos_nanos(&self) -> u64 {
+        (self.source.pos_nanos() as f64 / self.rate) as u64
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.source.total_duration()?.div_f64(self.rate))
+    }
+}
+
+impl Reversed {
+    pub fn new(mut source: SoundSource, sample_rate: u32) -> Self {
+        let duration = source
+            .total_duration()
+            .map(|duration| duration.min(MAX_REVERSED));
+        let (sender, decoding) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let max = (MAX_REVERSED.as_secs_f64() * sample_rate as f64) as usize * CHANNELS;
+            let samples: Vec<f32> = iter::from_fn(|| source.next_sample()).take(max).collect();
+            if samples.len() == max {
+                tracing::warn!(
+                    secs = MAX_REVERSED.as_secs(),
+                    "Only the start of the sound is played backwards"
+                );
+            }
+            let reversed = samples
+                .chunks_exact(CHANNELS)
+                .rev()
+                .flatten()
+                .copied()
+                .collect();
+            // the sound may have been stopped in the meantime
+            let _ = sender.send(reversed);
+        });
+
+        Self {
+            decoding: Some(decoding),
+            duration,
+            samples: Vec::new(),
+            idx: 0,
+            sample_rate,
+            pending_seek: None,
+        }
+    }
+
+    /// Takes the samples if the source has been read, waiting for it with `wait`.
+    fn receive(&mut self, wait: bool) -> bool {
+        let Some(decoding) = &self.decoding else {
+            return true;
+        };
+        self.samples = match decoding.try_recv() {
+            Ok(samples) => samples,
+            Err(TryRecvError::Empty) if !wait => return false,
+            Err(TryRecvError::Empty) => decoding.recv().unwrap_or_default(),
+            Err(TryRecvError::Disconnected) => Vec::new(),
+        };
+        self.decoding = None;
+        if let Some(pos) = self.pending_seek.take() {
+            self.seek(pos);
+        }
+        true
+    }
+
+    fn next_sample(&mut self) -> Option<f32> {
+        self.receive(true);
+        let sample = *self.samples.get(self.idx)?;
+        self.idx += 1;
+        Some(sample)
+    }
+
+    fn seek(&mut self, pos: Duration) {
+        if self.decoding.is_some() {
+            self.pending_seek = Some(pos);
+            return;
+        }
+        let frame = (pos.as_secs_f64() * self.sample_rate as f64).round() as usize;
+        self.idx = (frame * CHANNELS).min(self.samples.len());
+    }
+
+    fn pos_nanos(&self) -> u64 {
+        let pos = self
+            .pending_seek
+            .unwrap_or_else(|| self.frames_duration(self.idx / CHANNELS));
+        pos.as_nanos() as u64
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self.decoding {
+            Some(_) => self.duration,
+            None => Some(self.frames_duration(self.samples.len() / CHANNELS)),
+        }
+    }
+
+    fn frames_duration(&self, frames: usize) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl Filtered {
+    /// Frames filtered at once.
+    const BLOCK_FRAMES: usize = 64;
+
+    pub fn new(
+        source: SoundSource,
+        filters: impl IntoIterator<Item = FilterType>,
+        sample_rate: u32,
+    ) -> Self {
+        let mut chain = FilterChain::new(sample_rate, CHANNELS);
+        chain.sync(filters);
+        Self {
+            source,
+            chain,
+            block: Vec::with_capacity(Self::BLOCK_FRAMES * CHANNELS),
+            idx: 0,
+            sample_rate,
+        }
+    }
+
+    fn next_sample(&mut self) -> Option<f32> {
+        if self.idx == self.block.len() {
+            self.block.clear();
+            let source = &mut self.source;
+            self.block
+                .extend(iter::from_fn(|| source.next_sample()).take(Self::BLOCK_FRAMES * CHANNELS));
+            self.chain.process(&mut self.block);
+            self.idx = 0;
+        }
+        let sample = *self.block.get(self.idx)?;
+        self.idx += 1;
+        Some(sample)
+    }
+
+    /// Keeps the filter state, so a reverb tail carries over the jump.
+    fn seek(&mut self, pos: Duration) -> Result<(), String> {
+        self.source.seek(pos)?;
+        self.block.clear();
+        self.idx = 0;
+        Ok(())
+    }
+
+    fn pos_nanos(&self) -> u64 {
+        // the source is ahead by what's left of the block
+        let ahead = (self.block.len() - self.idx) / CHANNELS;
+        let ahead = ahead as u64 * 1_000_000_000 / self.sample_rate as u64;
+        self.source.pos_nanos().saturating_sub(ahead)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::audio::cache::CachedSound;
+    use std::sync::Arc;
+
+    fn constant(frames: usize, value: f32) -> SoundSource {
+        let sound = CachedSound::new(vec![value; frames * CHANNELS], 1000);
+        SoundSource::Cached(CachedSoundPlayer::new(Arc::new(sound), 1.0))
+    }
+
+    #[test]
+    fn layers_start_after_their_offset() {
+        let mut layers = Layers::new(1000);
+        layers.push(constant(3, 0.25), Duration::ZERO);
+        layers.push(constant(2, 0.5), Duration::from_millis(2));
+        let mut source = SoundSource::Layered(layers);
+
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(4)));
+        let frames: Vec<f32> = iter::from_fn(|| source.next_sample())
+            .step_by(CHANNELS)
+            .collect();
+        assert_eq!(frames, [0.25, 0.25, 0.75, 0.5]);
+        assert_eq!(source.pos_nanos(), 4_000_000);
+    }
+
+    #[test]
+    fn layers_pushed_while_playing_start_right_away() {
+        let mut layers = Layers::new(1000);
+        layers.push(constant(3, 0.25), Duration::ZERO);
+        let frames: Vec<f32> = iter::from_fn(|| layers.next_sample())
+            .take(2 * CHANNELS)
+            .step_by(CHANNELS)
+            .collect();
+        assert_eq!(frames, [0.25, 0.25]);
+
+        layers.push_now(constant(2, 0.5));
+        let frames: Vec<f32> = iter::from_fn(|| layers.next_sample())
+            .step_by(CHANNELS)
+            .collect();
+        assert_eq!(frames, [0.75, 0.5]);
+    }
+
+    #[test]
+    fn reversed_plays_frames_backwards() {
+        let samples = vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5];
+        let sound = CachedSoundPlayer::new(Arc::new(CachedSound::new(samples, 1000)), 1.0);
+        let mut source =
+            SoundSource::Reversed(Box::new(Reversed::new(SoundSource::Cached(sound), 1000)));
+
+        source.seek(Duration::from_millis(1)).unwrap();
+        assert_eq!(source.pos_nanos(), 1_000_000);
+        let samples: Vec<f32> = iter::from_fn(|| source.next_sample()).collect();
+        assert_eq!(samples, [1.0, 1.5, 0.0, 0.5]);
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(3)));
+    }
+
+    #[test]
+    fn reversed_sounds_get_ready_in_the_background() {
+        let mut source = SoundSource::Pitched(Box::new(Pitched::new(
+            SoundSource::Reversed(Box::new(Reversed::new(constant(3, 0.5), 1000))),
+            1.0,
+        )));
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(3)));
+
+        let started = std::time::Instant::now();
+        while !source.is_ready() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::yield_now();
+        }
+        let samples: Vec<f32> = iter::from_fn(|| source.next_sample()).collect();
+        assert_eq!(samples, [0.5; 3 * CHANNELS]);
+    }
+
+    #[test]
+    fn pitched_interpolates_between_frames() {
+        let ramp: Vec<f32> = (0..4).flat_map(|i| [i as f32; CHANNELS]).collect();
+        let sound = CachedSound::new(ramp, 1000);
+        let ramp = SoundSource::Cached(CachedSoundPlayer::new(Arc::new(sound), 1.0));
+        let mut source = SoundSource::Pitched(Box::new(Pitched::new(ramp, 0.5)));
+
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(8)));
+        let frames: Vec<f32> = iter::from_fn(|| source.next_sample())
+            .step_by(CHANNELS)
+            .collect();
+        assert_eq!(frames, [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn filtered_sources_keep_their_own_state() {
+        let reverb = || FilterType::Reverb {
+            room_size: 0.8,
+            damping: 0.2,
+            wet: 0.5,
+        };
+        let mut first = SoundSource::Filtered(Box::new(Filtered::new(
+            constant(200, 0.5),
+            [reverb()],
+            1000,
+        )));
+        let first_samples: Vec<f32> = iter::from_fn(|| first.next_sample()).collect();
+        assert_eq!(first_samples.len(), 200 * CHANNELS);
+        assert!(first_samples.iter().any(|sample| *sample != 0.5));
+
+        // a second voice starts with a fresh reverb instead of the tail of the first one
+        let mut second = SoundSource::Filtered(Box::new(Filtered::new(
+            constant(200, 0.5),
+            [reverb()],
+            1000,
+        )));
+        let second_samples: Vec<f32> = iter::from_fn(|| second.next_sample()).collect();
+        assert_eq!(first_samples, second_samples);
+        assert_eq!(second.pos_nanos(), 200_000_000);
+    }
+}
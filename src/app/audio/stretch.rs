@@ -0,0 +1,223 @@
+use crate::app::audio::{CHANNELS, SoundSource};
+use std::{array, collections::VecDeque, f32::consts::PI, time::Duration};
+
+/// How long the crossfade between two segments is, half of a segment.
+const OVERLAP_SECS: f64 = 0.012;
+/// How far a segment may move to line up with the one before.
+const SEARCH_SECS: f64 = 0.006;
+
+type Frame = [f32; CHANNELS];
+
+/// Changes the tempo of another source without changing its pitch, with WSOLA: the source is cut
+/// into overlapping segments that are spread out or pushed together, each one moved a little to
+/// where it lines up best with the end of the one before.
+pub struct Stretched {
+    source: SoundSource,
+    /// How much longer the sound gets, `2.0` plays it at half the tempo.
+    stretch: f64,
+    sample_rate: u32,
+    overlap: usize,
+    search: usize,
+    /// Frames read from the source, from `input_start` on.
+    input: VecDeque<Frame>,
+    input_start: usize,
+    source_finished: bool,
+    /// Where the next segment starts before it's moved to line up.
+    nominal: f64,
+    /// The rest of the last segment, faded out while the next one fades in.
+    tail: Vec<Frame>,
+    block: Vec<Frame>,
+    /// Samples of `block` already returned.
+    block_pos: usize,
+    finished: bool,
+    played_frames: u64,
+    /// Where the last seek went to.
+    offset: Duration,
+}
+
+impl Stretched {
+    pub fn new(source: SoundSource, stretch: f32, sample_rate: u32) -> Self {
+        let overlap = ((OVERLAP_SECS * sample_rate as f64) as usize).max(1);
+        let search = (SEARCH_SECS * sample_rate as f64) as usize;
+        Self {
+            source,
+            stretch: stretch as f64,
+            sample_rate,
+            overlap,
+            search,
+            input: VecDeque::new(),
+            input_start: 0,
+            source_finished: false,
+            nominal: 0.0,
+            tail: Vec::new(),
+            block: Vec::new(),
+            block_pos: 0,
+            finished: false,
+            played_frames: 0,
+            offset: Duration::ZERO,
+        }
+    }
+
+    pub(super) fn next_sample(&mut self) -> Option<f32> {
+        if self.block_pos == self.block.len() * CHANNELS {
+            if !self.next_block() {
+                return None;
+            }
+            self.block_pos = 0;
+        }
+        let sample = self.block[self.block_pos / CHANNELS][self.block_pos % CHANNELS];
+        self.block_pos += 1;
+        if self.block_pos.is_multiple_of(CHANNELS) {
+            self.played_frames += 1;
+        }
+        Some(sample)
+    }
+
+    /// Puts the next `overlap` frames into `block`, returns `false` once the source is used up.
+    fn next_block(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+        let nominal = self.nominal.round() as usize;
+        let lowest = nominal.saturating_sub(self.search).max(self.input_start);
+        let highest = nominal + self.search;
+        self.read_until(highest + 2 * self.overlap);
+
+        let input_end = self.input_start + self.input.len();
+        if nominal >= input_end && self.source_finished {
+            // the end of the last segment
+            self.finished = true;
+            self.block = std::mem::take(&mut self.tail);
+            return !self.block.is_empty();
+        }
+
+        let (start, block) = if self.tail.is_empty() {
+            let block = (0..self.overlap).map(|i| self.frame(nominal + i)).collect();
+            (nominal, block)
+        } else {
+            let start = (lowest..=highest)
+                .map(|start| (start, self.similarity(start)))
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map_or(nominal, |(start, _)| start);
+            let block = (0..self.overlap)
+                .map(|i| {
+                    let fade_in = 0.5 - 0.5 * (PI * (i as f32 + 0.5) / self.overlap as f32).cos();
+                    let new = self.frame(start + i);
+                    let old = self.tail[i];
+                    array::from_fn(|ch| old[ch] * (1.0 - fade_in) + new[ch] * fade_in)
+                })
+                .collect();
+            (start, block)
+        };
+        self.block = block;
+        self.tail = (0..self.overlap)
+            .map(|i| self.frame(start + self.overlap + i))
+            .collect();
+        self.nominal += self.overlap as f64 / self.stretch;
+
+        // nothing before the lowest start of the next segment is needed anymore
+        let needed = (self.nominal.round() as usize).saturating_sub(self.search);
+        while self.input_start < needed && !self.input.is_empty() {
+            self.input.pop_front();
+            self.input_start += 1;
+        }
+        true
+    }
+
+    /// How well the segment starting at `start` continues the tail, comparing every other frame.
+    fn similarity(&self, start: usize) -> f32 {
+        (0..self.overlap)
+            .step_by(2)
+            .map(|i| {
+                let [l, r] = self.frame(start + i);
+                let [tail_l, tail_r] = self.tail[i];
+                (l + r) * (tail_l + tail_r)
+            })
+            .sum()
+    }
+
+    /// The frame `idx` of the source, silence past its end.
+    fn frame(&self, idx: usize) -> Frame {
+        idx.checked_sub(self.input_start)
+            .and_then(|idx| self.input.get(idx))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn read_until(&mut self, end: usize) {
+        while !self.source_finished && self.input_start + self.input.len() < end {
+            match (self.source.next_sample(), self.source.next_sample()) {
+                (Some(l), Some(r)) => self.input.push_back([l, r]),
+                _ => self.source_finished = true,
+            }
+        }
+    }
+
+    pub(super) fn is_ready(&mut self) -> bool {
+        self.source.is_ready()
+    }
+
+    pub(super) fn seek(&mut self, pos: Duration) -> Result<(), String> {
+        self.source.seek(pos.div_f64(self.stretch))?;
+        self.input.clear();
+        self.input_start = 0;
+        self.source_finished = false;
+        self.nominal = 0.0;
+        self.tail.clear();
+        self.block.clear();
+        self.block_pos = 0;
+        self.finished = false;
+        self.played_frames = 0;
+        self.offset = pos;
+        Ok(())
+    }
+
+    pub(super) fn pos_nanos(&self) -> u64 {
+        let played = Duration::from_secs_f64(self.played_frames as f64 / self.sample_rate as f64);
+        (self.offset + played).as_nanos() as u64
+    }
+
+    pub(super) fn total_duration(&self) -> Option<Duration> {
+        Some(self.source.total_duration()?.mul_f64(self.stretch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::audio::cache::{CachedSound, CachedSoundPlayer};
+    use std::{iter, sync::Arc};
+
+    fn sine(frames: usize, sample_rate: u32) -> SoundSource {
+        let samples = (0..frames)
+            .flat_map(|i| [(i as f32 / sample_rate as f32 * 440.0 * 2.0 * PI).sin() * 0.5; 2])
+            .collect();
+        let sound = CachedSound::new(samples, sample_rate);
+        SoundSource::Cached(CachedSoundPlayer::new(Arc::new(sound), 1.0))
+    }
+
+    /// The number of upward zero crossings, which is the number of periods of a sine.
+    fn periods(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn keeps_the_pitch_while_stretching() {
+        const RATE: u32 = 48_000;
+        let input = sine(RATE as usize / 2, RATE);
+        let mut stretched = SoundSource::Stretched(Box::new(Stretched::new(input, 2.0, RATE)));
+        assert_eq!(stretched.total_duration(), Some(Duration::from_secs(1)));
+
+        let left: Vec<f32> = iter::from_fn(|| stretched.next_sample())
+            .step_by(CHANNELS)
+            .collect();
+        let seconds = left.len() as f32 / RATE as f32;
+        assert!((seconds - 1.0).abs() < 0.05, "got {seconds}s");
+        // still 440 Hz, twice as many periods as in the half second of input
+        let periods = periods(&left);
+        assert!(periods.abs_diff(440) < 10, "got {periods} periods");
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const BITS_PER_SAMPLE: u16 = 32;
+/// Offsets of the size fields that are only known once all samples have been written.
+const RIFF_SIZE_OFFSET: u64 = 4;
+const FACT_FRAMES_OFFSET: u64 = 46;
+const DATA_SIZE_OFFSET: u64 = 54;
+const HEADER_LEN: u32 = 58;
+
+/// Streams interleaved samples into a 32-bit float WAV file.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    samples: u64,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&18u32.to_le_bytes())?;
+        file.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        // no extension
+        file.write_all(&0u16.to_le_bytes())?;
+        // non-PCM formats need a fact chunk with the frame count
+        file.write_all(b"fact")?;
+        file.write_all(&4u32.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            file,
+            channels,
+            samples: 0,
+        })
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the chunk sizes, the file is incomplete until this is called.
+    pub fn finish(mut self) -> io::Result<()> {
+        let data_len = u32::try_from(self.samples * (BITS_PER_SAMPLE / 8) as u64)
+            .ok()
+            .filter(|len| len.checked_add(HEADER_LEN).is_some())
+            .ok_or_else(|| io::Error::other("too much audio for a WAV file"))?;
+        let frames = (self.samples / self.channels as u64) as u32;
+
+        for (offset, value) in [
+            (RIFF_SIZE_OFFSET, HEADER_LEN - 8 + data_len),
+            (FACT_FRAMES_OFFSET, frames),
+            (DATA_SIZE_OFFSET, data_len),
+        ] {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+
+        self.file.flush()
+    }
+}
@@ -1,4 +1,11 @@
-use crate::app::{App, Sound, config::filter::AudioFilter};
+use crate::app::{
+    App, Playback, Sound,
+    config::{
+        filter::{AudioFilter, FilterType},
+        macros::Macro,
+        midi::Midi,
+    },
+};
 use serde::{Deserialize, Serialize};
 use std::{
     fs::{read_to_string, write},
@@ -7,6 +14,8 @@
 };
 
 pub mod filter;
+pub mod macros;
+pub mod midi;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Keybind {
@@ -29,36 +38,274 @@ pub struct Config {
     pub output_device: String,
     pub virtual_output_device: String,
     pub gui_scale: f32,
+    #[serde(default = "Config::default_sound_cache_budget_mb")]
+    pub sound_cache_budget_mb: u32,
+    #[serde(default = "Config::default_sound_cache_max_duration")]
+    pub sound_cache_max_duration: f32,
+    #[serde(default)]
+    pub latency: Latency,
+    #[serde(default)]
+    pub volumes: Volumes,
+    #[serde(default)]
+    pub web_remote: WebRemote,
+    #[serde(default)]
+    pub osc: Osc,
+    #[serde(default)]
+    pub midi: Midi,
+    #[serde(default)]
+    pub mpris: Mpris,
+    #[serde(default)]
+    pub scripts: Scripts,
+    #[serde(default, skip_serializing_if = "PlaybackModifiers::is_empty")]
+    pub playback_modifiers: PlaybackModifiers,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub search_and_play_keybind: Option<Keybind>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stop_sound_keybind: Option<Keybind>,
+    /// The filter preset every sound goes through, independent of the one on the mic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sound_filter_preset: Option<String>,
     pub filter_presets: Vec<FilterPreset>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<Macro>,
     pub sounds: Vec<Sound>,
 }
 
+impl FilterPreset {
+    pub fn enabled_filters(&self) -> impl Iterator<Item = FilterType> + '_ {
+        self.filters
+            .iter()
+            .filter(|filter| filter.enabled)
+            .map(|filter| filter.filter_type.clone())
+    }
+}
+
+/// Audio buffer size, either one of the presets or an explicit number of frames per block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Latency {
+    Preset(LatencyPreset),
+    Frames(u32),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyPreset {
+    Low,
+    #[default]
+    Balanced,
+    Safe,
+}
+
+/// The routing matrix, how loud the mic and the sounds are on each output. The sound volumes apply
+/// on top of the per sound volume, to the sounds routed to that output.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Volumes {
+    /// The mic on the virtual output.
+    pub mic: f32,
+    /// Sounds on the virtual output.
+    pub sounds: f32,
+    /// Sounds on the local output.
+    pub monitor: f32,
+    /// The mic on the local output, filtered like on the virtual output. Off at `0.0`.
+    pub mic_monitor: f32,
+}
+
+impl Default for Volumes {
+    fn default() -> Self {
+        Self {
+            mic: 1.0,
+            sounds: 1.0,
+            monitor: 1.0,
+            mic_monitor: 0.0,
+        }
+    }
+}
+
+/// The web page that turns a phone or tablet into a remote for the soundboard.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebRemote {
+    pub enabled: bool,
+    /// `127.0.0.1` only allows this computer, `0.0.0.0` allows the whole network.
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for WebRemote {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_string(),
+            port: 7878,
+        }
+    }
+}
+
+/// Open Sound Control over UDP, for mixers, TouchOSC layouts and DAWs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Osc {
+    pub enabled: bool,
+    /// Where to listen for messages, as `address:port`.
+    pub listen: String,
+    /// Where to send the playing state and levels to, as `address:port`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<String>,
+}
+
+impl Default for Osc {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:9000".to_string(),
+            feedback: None,
+        }
+    }
+}
+
+/// The MPRIS media player on the D-Bus session bus, for media keys and status bars. Linux only.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mpris {
+    pub enabled: bool,
+}
+
+/// Rhai scripts in the `scripts` folder next to the config file.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scripts {
+    pub enabled: bool,
+    /// How loud the mic has to get for `on_mic_level`, `1.0` is full scale.
+    pub mic_level_threshold: f32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keybinds: Vec<ScriptKeybind>,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mic_level_threshold: 0.5,
+            keybinds: Vec::new(),
+        }
+    }
+}
+
+/// Modifier keys that change how a sound plays when held together with its keybind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackModifiers {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ctrl: Option<Playback>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt: Option<Playback>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift: Option<Playback>,
+}
+
+impl PlaybackModifiers {
+    /// What `keybind` changes about the sound bound to `bound`, `None` if it doesn't trigger it.
+    pub fn matching(&self, keybind: Keybind, bound: Keybind) -> Option<Playback> {
+        if keybind.key != bound.key {
+            return None;
+        }
+        [
+            (keybind.ctrl, bound.ctrl, self.ctrl),
+            (keybind.alt, bound.alt, self.alt),
+            (keybind.shift, bound.shift, self.shift),
+        ]
+        .into_iter()
+        .try_fold(
+            Playback::default(),
+            |playback, (held, needed, modifier)| match (held, needed) {
+                (true, false) => Some(playback.and(modifier?)),
+                (false, true) => None,
+                _ => Some(playback),
+            },
+        )
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScriptKeybind {
+    /// The file name without `.rhai`.
+    pub script: String,
+    pub keybind: Keybind,
+}
+
+impl Latency {
+    const MIN_FRAMES: u32 = 32;
+    const MAX_FRAMES: u32 = 8192;
+
+    /// Number of frames processed per block and requested per device callback.
+    pub fn block_frames(self) -> u32 {
+        match self {
+            Self::Preset(LatencyPreset::Low) => 128,
+            Self::Preset(LatencyPreset::Balanced) => 512,
+            Self::Preset(LatencyPreset::Safe) => 1024,
+            Self::Frames(frames) => frames.clamp(Self::MIN_FRAMES, Self::MAX_FRAMES),
+        }
+    }
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self::Preset(LatencyPreset::default())
+    }
+}
+
+impl Config {
+    #[inline]
+    const fn default_sound_cache_budget_mb() -> u32 {
+        256
+    }
+
+    #[inline]
+    const fn default_sound_cache_max_duration() -> f32 {
+        10.0
+    }
+
+    pub fn filter_preset(&self, name: &str) -> Option<&FilterPreset> {
+        self.filter_presets
+            .iter()
+            .find(|preset| preset.name == name)
+    }
+}
+
 impl App {
     pub fn load_config(&mut self) {
-        self.config = Self::load_config_result();
+        self.soundboard.config = Self::load_config_result();
     }
 
     pub fn save_config(&self) {
-        Self::save_config_result(&self.config);
+        Self::save_config_result(&self.soundboard.config);
     }
 
     pub fn load_config_result() -> Config {
-        let contents = match read_to_string(Self::config_file()) {
-            Ok(contents) => contents,
-            Err(err) => {
-                if err.kind() != ErrorKind::NotFound {
-                    panic!("Couldn't read the config file: {err}");
-                }
-
+        match Self::read_config() {
+            Ok(Some(config)) => config,
+            Ok(None) => {
                 let config = Config {
                     input_device: String::new(),
                     output_device: String::new(),
                     virtual_output_device: String::from("CABLE Input (VB-Audio Virtual Cable)"),
                     gui_scale: 1.0,
+                    sound_cache_budget_mb: Config::default_sound_cache_budget_mb(),
+                    sound_cache_max_duration: Config::default_sound_cache_max_duration(),
+                    latency: Latency::default(),
+                    volumes: Volumes::default(),
+                    web_remote: WebRemote::default(),
+                    osc: Osc::default(),
+                    midi: Midi::default(),
+                    mpris: Mpris::default(),
+                    scripts: Scripts::default(),
+                    playback_modifiers: PlaybackModifiers::default(),
                     search_and_play_keybind: Some(Keybind {
                         ctrl: true,
                         alt: true,
@@ -71,15 +318,30 @@ pub fn load_config_result() -> Config {
                         shift: false,
                         key: rdev::Key::KeyS,
                     }),
+                    sound_filter_preset: None,
                     filter_presets: Vec::new(),
+                    macros: Vec::new(),
                     sounds: Vec::new(),
                 };
 
                 Self::save_config_result(&config);
-                return config;
+                config
             }
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Reads and parses the config file, `None` if there is no config file yet.
+    pub fn read_config() -> Result<Option<Config>, String> {
+        let path = Self::config_file();
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("Couldn't read the config file: {err}")),
         };
-        toml::from_str::<Config>(&contents).unwrap()
+        toml::from_str(&contents)
+            .map(Some)
+            .map_err(|err| format!("Couldn't parse {}: {err}", path.display()))
     }
 
     pub fn save_config_result(config: &Config) {
@@ -87,11 +349,17 @@ pub fn save_config_result(config: &Config) {
         write(Self::config_file(), contents).unwrap();
     }
 
-    fn config_file() -> PathBuf {
+    pub fn config_file() -> PathBuf {
         let mut dir = dirs::config_dir().unwrap();
         dir.push("keysounds/config.toml");
         dir
     }
+
+    pub fn scripts_dir() -> PathBuf {
+        let mut dir = dirs::config_dir().unwrap();
+        dir.push("keysounds/scripts");
+        dir
+    }
 }
 
 mod keybind_serde {
@@ -176,3 +444,34 @@ fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playback_modifiers_stack_on_sound_keybinds() {
+        let modifiers: PlaybackModifiers = toml::from_str(
+            r#"
+            shift = { reverse = true }
+            alt = { pitch = 12, speed = 0.5 }
+            "#,
+        )
+        .unwrap();
+        let bound: Keybind = "Ctrl+M".parse().unwrap();
+        let matching = |keybind: &str| modifiers.matching(keybind.parse().unwrap(), bound);
+
+        assert_eq!(matching("Ctrl+M"), Some(Playback::default()));
+        assert_eq!(
+            matching("Ctrl+Alt+Shift+M"),
+            Some(Playback {
+                speed: Some(0.5),
+                pitch: Some(12.0),
+                reverse: Some(true),
+            })
+        );
+        // ctrl is part of the keybind, not a modifier
+        assert_eq!(matching("Shift+M"), None);
+        assert_eq!(matching("Ctrl+Shift+N"), None);
+    }
+}
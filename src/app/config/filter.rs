@@ -99,6 +99,18 @@ pub fn set(&self, filter: &mut FilterType) {
     },
 }
 
+/// Compares filter and property names ignoring case, spaces and underscores, so both `Room Size`
+/// and `room_size` name the same property.
+pub fn same_name(a: &str, b: &str) -> bool {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    normalize(a) == normalize(b)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum PropVal {
     F32(f32),
@@ -0,0 +1,174 @@
+use crate::app::{Playback, config::Keybind};
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+
+/// Steps run one after another when the keybind is pressed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keybind: Option<Keybind>,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum MacroStep {
+    Play {
+        sound: String,
+        /// Replaces the volume of the sound.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        volume: Option<f32>,
+        #[serde(flatten)]
+        playback: Playback,
+    },
+    /// Starts several sounds together, mixed into one playing sound.
+    Layer {
+        layers: Vec<Layer>,
+    },
+    Wait {
+        ms: u64,
+    },
+    Preset {
+        preset: String,
+    },
+    Stop,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    pub sound: String,
+    /// How long after the first layer this one starts.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offset_ms: u64,
+    /// Replaces the volume of the sound.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f32>,
+    #[serde(flatten)]
+    pub playback: Playback,
+}
+
+fn is_zero(ms: &u64) -> bool {
+    *ms == 0
+}
+
+impl Macro {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            keybind: None,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl MacroStep {
+    /// The steps the editor can add, with empty sounds and presets to fill in.
+    pub const KINDS: [&str; 5] = ["Play", "Layer", "Wait", "Preset", "Stop"];
+
+    pub fn new(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "Play" => Self::Play {
+                sound: String::new(),
+                volume: None,
+                playback: Playback::default(),
+            },
+            "Layer" => Self::Layer {
+                layers: vec![Layer::new(String::new()), Layer::new(String::new())],
+            },
+            "Wait" => Self::Wait { ms: 500 },
+            "Preset" => Self::Preset {
+                preset: String::new(),
+            },
+            "Stop" => Self::Stop,
+            _ => return None,
+        })
+    }
+}
+
+impl Layer {
+    pub fn new(sound: String) -> Self {
+        Self {
+            sound,
+            offset_ms: 0,
+            volume: None,
+            playback: Playback::default(),
+        }
+    }
+
+    pub fn offset(&self) -> Duration {
+        Duration::from_millis(self.offset_ms)
+    }
+}
+
+impl fmt::Display for MacroStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Play { sound, .. } => write!(f, "Play {sound}"),
+            Self::Layer { layers } => {
+                write!(f, "Layer ")?;
+                for (i, layer) in layers.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " + ")?;
+                    }
+                    write!(f, "{}", layer.sound)?;
+                }
+                Ok(())
+            }
+            Self::Wait { ms } => write!(f, "Wait {ms} ms"),
+            Self::Preset { preset } => write!(f, "Preset {preset}"),
+            Self::Stop => write!(f, "Stop"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macros_read_like_the_readme() {
+        #[derive(Deserialize)]
+        struct Macros {
+            macros: Vec<Macro>,
+        }
+
+        let Macros { macros } = toml::from_str(
+            r#"
+            [[macros]]
+            name = "Drumroll"
+            keybind = "Ctrl+Alt+D"
+            steps = [
+                { step = "play", sound = "Drums", volume = 0.8, speed = 1.5 },
+                { step = "wait", ms = 1500 },
+                { step = "layer", layers = [{ sound = "Crash" }, { sound = "AIRHORN", offset_ms = 250, reverse = true }] },
+                { step = "preset", preset = "Church" },
+                { step = "stop" },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let steps = &macros[0].steps;
+        assert_eq!(macros[0].keybind.unwrap().to_string(), "Ctrl+Alt+D");
+        assert_eq!(
+            steps[0],
+            MacroStep::Play {
+                sound: "Drums".to_string(),
+                volume: Some(0.8),
+                playback: Playback {
+                    speed: Some(1.5),
+                    ..Playback::default()
+                },
+            }
+        );
+        assert_eq!(steps[1], MacroStep::Wait { ms: 1500 });
+        assert_eq!(steps[2].to_string(), "Layer Crash + AIRHORN");
+        let MacroStep::Layer { layers } = &steps[2] else {
+            panic!("expected layers");
+        };
+        assert_eq!(layers[1].offset(), Duration::from_millis(250));
+        assert_eq!(layers[1].playback.reverse, Some(true));
+        assert_eq!(steps[4], MacroStep::Stop);
+    }
+}
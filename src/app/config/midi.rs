@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// MIDI controllers, and what their notes and knobs are mapped to.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Midi {
+    pub enabled: bool,
+    /// Only ports whose name contains this are connected, all of them if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mappings: Vec<MidiMapping>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    #[serde(flatten)]
+    pub control: MidiControl,
+    /// 1 to 16, any channel if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    #[serde(flatten)]
+    pub action: MidiAction,
+    /// What the lowest and highest value of the control set volumes and filter properties to,
+    /// `0.0` to `1.0` if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<(f32, f32)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiControl {
+    Note(u8),
+    /// Control change, the knobs, faders and many pads.
+    Cc(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MidiAction {
+    Play {
+        sound: String,
+    },
+    Stop,
+    TogglePause,
+    Preset {
+        preset: String,
+    },
+    Volume {
+        volume: VolumeKind,
+    },
+    /// Sets the first filter of this type in `preset`, see [`MidiMapping::range`].
+    FilterProperty {
+        preset: String,
+        filter: String,
+        property: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeKind {
+    Mic,
+    Sounds,
+    Monitor,
+    MicMonitor,
+}
+
+impl MidiAction {
+    /// Whether the action follows the value of the control instead of being triggered by it.
+    pub fn is_continuous(&self) -> bool {
+        matches!(self, Self::Volume { .. } | Self::FilterProperty { .. })
+    }
+}
+
+impl MidiMapping {
+    /// Scales a 7-bit MIDI value to [`Self::range`].
+    pub fn scale(&self, value: u8) -> f32 {
+        let (min, max) = self.range.unwrap_or((0.0, 1.0));
+        min + (max - min) * f32::from(value.min(127)) / 127.0
+    }
+}
+
+impl fmt::Display for MidiMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.control {
+            MidiControl::Note(note) => write!(f, "Note {note}")?,
+            MidiControl::Cc(controller) => write!(f, "CC {controller}")?,
+        }
+        match self.channel {
+            Some(channel) => write!(f, " (Ch {channel})"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mappings_read_like_the_readme() {
+        let midi: Midi = toml::from_str(
+            r#"
+            enabled = true
+
+            [[mappings]]
+            note = 36
+            action = "play"
+            sound = "METAL PIPE"
+
+            [[mappings]]
+            cc = 21
+            channel = 2
+            action = "filter_property"
+            preset = "Church"
+            filter = "reverb"
+            property = "wet"
+            range = [0.2, 0.8]
+            "#,
+        )
+        .unwrap();
+
+        assert!(midi.enabled);
+        assert_eq!(
+            midi.mappings[0],
+            MidiMapping {
+                control: MidiControl::Note(36),
+                channel: None,
+                action: MidiAction::Play {
+                    sound: "METAL PIPE".to_string()
+                },
+                range: None,
+            }
+        );
+        assert_eq!(midi.mappings[1].control, MidiControl::Cc(21));
+        assert_eq!(midi.mappings[1].scale(127), 0.8);
+        assert_eq!(midi.mappings[1].to_string(), "CC 21 (Ch 2)");
+
+        let written = toml::to_string(&midi).unwrap();
+        let read: Midi = toml::from_str(&written).unwrap();
+        assert_eq!(read.mappings, midi.mappings);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::app::{
+    Sound, Soundboard,
+    config::{
+        Keybind, Volumes,
+        filter::{PropVal, same_name},
+        macros::Layer,
+    },
+    midi_listener::MidiEvent,
+};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+pub mod mpris;
+pub mod osc;
+pub mod rpc;
+pub mod web;
+
+/// Something for the [`Soundboard`] to act on, sent by the keybind listener and the control
+/// frontends over the same channel.
+pub enum Input {
+    Keybind(Keybind),
+    /// The key of a keybind was let go, for sounds that play while it is held.
+    KeyReleased(rdev::Key),
+    Midi(MidiEvent),
+    /// The result is sent back if there is someone waiting for it.
+    Command(Command, Option<Sender<CommandResult>>),
+    /// Sends the current [`State`] and then every change to it, until the receiver is dropped.
+    Subscribe(Sender<State>),
+}
+
+pub type CommandResult = Result<Reply, String>;
+
+/// How long [`execute`] waits for the soundboard to run a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Hands `command` to the soundboard and waits for its result. Fails if the soundboard is
+/// shutting down or doesn't respond in time.
+pub fn execute(inputs: &Sender<Input>, command: Command) -> Result<CommandResult, String> {
+    let (reply, result) = mpsc::channel();
+    inputs
+        .send(Input::Command(command, Some(reply)))
+        .map_err(|_| "keysounds is shutting down")?;
+    result.recv_timeout(REPLY_TIMEOUT).map_err(|err| {
+        match err {
+            RecvTimeoutError::Timeout => "keysounds didn't respond in time",
+            RecvTimeoutError::Disconnected => "keysounds is shutting down",
+        }
+        .to_string()
+    })
+}
+
+/// How often subscribers hear about the position of the playing sound.
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What frontends other than the GUI can ask the soundboard to do.
+#[derive(Clone, Debug)]
+pub enum Command {
+    ListSounds,
+    ListPresets,
+    Play(SoundRef),
+    /// Plays sounds from the config mixed together, see [`Layer`].
+    PlayLayers(Vec<Layer>),
+    Stop,
+    SetPaused(bool),
+    TogglePause,
+    /// Plays the playing sound from this position.
+    Seek(Duration),
+    /// Activates the filter preset with this name.
+    ActivatePreset(String),
+    /// Changes the volumes that are `Some`.
+    SetVolumes {
+        mic: Option<f32>,
+        sounds: Option<f32>,
+        monitor: Option<f32>,
+        mic_monitor: Option<f32>,
+    },
+    /// Sets a property of the first filter of this type in the preset named `preset`, or the active
+    /// one. Filter and property names are matched ignoring case, spaces and underscores, so both
+    /// `Room Size` and `room_size` work.
+    SetFilterParam {
+        preset: Option<String>,
+        filter: String,
+        property: String,
+        value: f32,
+    },
+    State,
+}
+
+#[derive(Clone, Debug)]
+pub enum SoundRef {
+    /// A sound from the config, matched case-insensitively if there is no exact match.
+    Name(String),
+    /// Any audio file.
+    Path(String),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Done,
+    Sounds(Vec<SoundInfo>),
+    Presets(Vec<String>),
+    State(State),
+}
+
+#[derive(Serialize)]
+pub struct SoundInfo {
+    pub name: String,
+    pub path: String,
+    pub volume: f32,
+}
+
+#[derive(Clone, Default, PartialEq, Serialize)]
+pub struct State {
+    pub playing: Option<PlayingInfo>,
+    pub paused: bool,
+    pub active_preset: Option<String>,
+    pub volumes: Volumes,
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct PlayingInfo {
+    pub name: String,
+    pub path: String,
+    /// Seconds.
+    pub position: f64,
+    /// Seconds, `None` while unknown.
+    pub duration: Option<f64>,
+}
+
+impl Soundboard {
+    pub fn handle_input(&mut self, input: Input) {
+        match input {
+            Input::Keybind(keybind) => {
+                self.handle_keybind(keybind);
+            }
+            Input::KeyReleased(key) => self.release_key(key),
+            Input::Midi(event) => {
+                self.handle_midi(event);
+            }
+            Input::Command(command, reply) => {
+                let result = self.execute(command);
+                if let Err(err) = &result {
+                    tracing::warn!(%err, "Command failed");
+                }
+                if let Some(reply) = reply {
+                    // the frontend may have stopped waiting already
+                    let _ = reply.send(result);
+                }
+            }
+            Input::Subscribe(subscriber) => {
+                if subscriber.send(self.state()).is_ok() {
+                    self.state_subscribers.push(subscriber);
+                }
+            }
+        }
+    }
+
+    pub fn execute(&mut self, command: Command) -> CommandResult {
+        match command {
+            Command::ListSounds => {
+                let sounds = self
+                    .config
+                    .sounds
+                    .iter()
+                    .map(|sound| SoundInfo {
+                        name: sound.name.clone(),
+                        path: sound.paths().next().unwrap_or_default().to_string(),
+                        volume: sound.volume,
+                    })
+                    .collect();
+                return Ok(Reply::Sounds(sounds));
+            }
+            Command::ListPresets => {
+                let presets = self
+                    .config
+                    .filter_presets
+                    .iter()
+                    .map(|preset| preset.name.clone())
+                    .collect();
+                return Ok(Reply::Presets(presets));
+            }
+            Command::Play(SoundRef::Name(name)) => {
+                let sound = self.find_sound(&name)?.clone();
+                self.play_sound(sound, false);
+            }
+            Command::Play(SoundRef::Path(path)) => {
+                if !Path::new(&path).is_file() {
+                    return Err(format!("{path} doesn't exist"));
+                }
+                self.play_sound_from_path(path);
+            }
+            Command::PlayLayers(layers) => self.play_layers(&layers)?,
+            Command::Stop => self.stop_sound(),
+            Command::SetPaused(paused) => self.mix.set_paused(paused),
+            Command::TogglePause => self.mix.set_paused(!self.mix.is_paused()),
+            Command::Seek(position) => self.seek(position)?,
+            Command::ActivatePreset(name) => {
+                let idx = self.find_preset(&name)?;
+                self.apply_preset(idx);
+            }
+            Command::SetVolumes {
+                mic,
+                sounds,
+                monitor,
+                mic_monitor,
+            } => {
+                let mut volumes = self.config.volumes;
+                for (volume, new) in [
+                    (&mut volumes.mic, mic),
+                    (&mut volumes.sounds, sounds),
+                    (&mut volumes.monitor, monitor),
+                    (&mut volumes.mic_monitor, mic_monitor),
+                ] {
+                    if let Some(new) = new {
+                        if !(new.is_finite() && new >= 0.0) {
+                            return Err(format!("Invalid volume {new}"));
+                        }
+                        *volume = new;
+                    }
+                }
+                self.set_volumes(volumes);
+            }
+            Command::SetFilterParam {
+                preset,
+                filter,
+                property,
+                value,
+            } => {
+                let idx = match preset {
+                    Some(name) => self.find_preset(&name)?,
+                    None => self.active_preset.ok_or("No filter preset is active")?,
+                };
+                self.set_filter_param(idx, &filter, &property, value)?;
+            }
+            Command::State => return Ok(Reply::State(self.state())),
+        }
+        Ok(Reply::Done)
+    }
+
+    pub(super) fn find_sound(&self, name: &str) -> Result<&Sound, String> {
+        let sounds = &self.config.sounds;
+        sounds
+            .iter()
+            .find(|sound| sound.name == name)
+            .or_else(|| {
+                sounds
+                    .iter()
+                    .find(|sound| sound.name.eq_ignore_ascii_case(name))
+            })
+            .ok_or_else(|| format!("No sound named \"{name}\""))
+    }
+
+    fn find_preset(&self, name: &str) -> Result<usize, String> {
+        self.config
+            .filter_presets
+            .iter()
+            .position(|preset| preset.name == name)
+            .ok_or_else(|| format!("No filter preset named \"{name}\""))
+    }
+
+    /// Changes the filter chain too if the preset is active.
+    fn set_filter_param(
+        &mut self,
+        idx: usize,
+        filter: &str,
+        property: &str,
+        value: f32,
+    ) -> Result<(), String> {
+        if !value.is_finite() {
+            return Err(format!("Invalid value {value}"));
+        }
+        let preset = &mut self.config.filter_presets[idx];
+        let audio_filter = preset
+            .filters
+            .iter_mut()
+            .find(|audio_filter| same_name(audio_filter.name(), filter))
+            .ok_or_else(|| format!("\"{}\" has no {filter} filter", preset.name))?;
+        let mut prop = audio_filter
+            .filter_type
+            .properties()
+            .into_iter()
+            .find(|prop| same_name(prop.name(), property))
+            .ok_or_else(|| format!("{} has no property {property}", audio_filter.name()))?;
+
+        prop.val = match prop.val {
+            PropVal::F32(_) => PropVal::F32(value),
+            PropVal::I32(_) => PropVal::I32(value.round() as i32),
+        };
+        prop.set(&mut audio_filter.filter_type);
+
+        if self.active_preset == Some(idx) {
+            self.filter_chain
+                .lock()
+                .unwrap()
+                .sync(preset.enabled_filters());
+        }
+        if self.config.sound_filter_preset.as_ref() == Some(&preset.name) {
+            self.sound_filter_chain
+                .lock()
+                .unwrap()
+                .sync(preset.enabled_filters());
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> State {
+        State {
+            playing: self.playing_sound.as_ref().map(|playing| PlayingInfo {
+                name: playing.sound.name.clone(),
+                path: playing.sound.path.clone(),
+                position: self.playhead.pos_nanos(playing.id) as f64 / 1e9,
+                duration: playing.duration.map(|duration| duration.as_secs_f64()),
+            }),
+            paused: self.mix.is_paused(),
+            active_preset: self
+                .active_preset
+                .and_then(|idx| self.config.filter_presets.get(idx))
+                .map(|preset| preset.name.clone()),
+            volumes: self.config.volumes,
+        }
+    }
+
+    /// Sends the state to the subscribers if anything but the position changed, or every
+    /// [`POSITION_INTERVAL`] while a sound is playing.
+    pub(super) fn publish_state(&mut self) {
+        if self.state_subscribers.is_empty() {
+            return;
+        }
+
+        let state = self.state();
+        let due = match &self.published_state {
+            None => true,
+            Some((published, at)) => {
+                let position_due =
+                    state.playing.is_some() && !state.paused && at.elapsed() >= POSITION_INTERVAL;
+                position_due || !published.same_besides_position(&state)
+            }
+        };
+        if !due {
+            return;
+        }
+
+        self.state_subscribers
+            .retain(|subscriber| subscriber.send(state.clone()).is_ok());
+        self.published_state = Some((state, Instant::now()));
+    }
+}
+
+impl State {
+    fn same_besides_position(&self, other: &State) -> bool {
+        let position = |state: &State| state.playing.as_ref().map(|playing| playing.position);
+        let mut other = other.clone();
+        if let (Some(playing), Some(position)) = (&mut other.playing, position(self)) {
+            playing.position = position;
+        }
+        *self == other
+    }
+}
+
+/// Answers commands and subscriptions like an idle soundboard without sounds, and passes the
+/// commands on as text. Anything named "Missing" doesn't exist.
+#[cfg(test)]
+pub(super) fn fake_soundboard() -> (Sender<Input>, mpsc::Receiver<String>) {
+    let (inputs, received) = mpsc::channel();
+    let (commands, executed) = mpsc::channel();
+    std::thread::spawn(move || {
+        for input in received {
+            let (command, reply) = match input {
+                Input::Command(command, reply) => (command, reply),
+                Input::Subscribe(subscriber) => {
+                    let _ = subscriber.send(State {
+                        playing: None,
+                        paused: false,
+                        active_preset: None,
+                        volumes: Volumes::default(),
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+            let result = match &command {
+                Command::Play(SoundRef::Name(name)) if name == "Missing" => {
+                    Err(format!("No sound named \"{name}\""))
+                }
+                Command::ActivatePreset(name) if name == "Missing" => {
+                    Err(format!("No filter preset named \"{name}\""))
+                }
+                Command::ListSounds => Ok(Reply::Sounds(Vec::new())),
+                Command::ListPresets => Ok(Reply::Presets(vec!["Echo".to_string()])),
+                _ => Ok(Reply::Done),
+            };
+            let _ = commands.send(format!("{command:?}"));
+            if let Some(reply) = reply {
+                let _ = reply.send(result);
+            }
+        }
+    });
+    (inputs, executed)
+}
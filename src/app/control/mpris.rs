@@ -0,0 +1,521 @@
+//! Exposes the soundboard as an MPRIS2 media player on the D-Bus session bus, so media keys,
+//! status bars and `playerctl` can show and control the playing sound.
+//!
+//! The properties are answered from the last [`State`] the soundboard pushed, the methods become
+//! [`Command`]s.
+
+use crate::app::{
+    config::Volumes,
+    control::{Command, Input, SoundRef, State},
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use zbus::{
+    blocking::{Connection, connection},
+    interface,
+    zvariant::{ObjectPath, Value},
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.keysounds";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// There is only ever one sound playing, so it always has the same id.
+const TRACK_ID: &str = "/org/keysounds/PlayingSound";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// A position further than this from where it should be means the sound was seeked.
+const SEEK_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Stays on the bus until the soundboard is dropped.
+pub struct Server {
+    _connection: Connection,
+}
+
+/// The last state the soundboard pushed and when.
+type SharedState = Arc<Mutex<(State, Instant)>>;
+
+impl Server {
+    pub fn start(inputs: Sender<Input>) -> zbus::Result<Self> {
+        Self::connect(connection::Builder::session()?, inputs)
+    }
+
+    fn connect(builder: connection::Builder<'_>, inputs: Sender<Input>) -> zbus::Result<Self> {
+        // nothing is known until the soundboard answers the subscription
+        let state = Arc::new(Mutex::new((State::default(), Instant::now())));
+        let player = Player {
+            inputs: inputs.clone(),
+            state: Arc::clone(&state),
+        };
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Root)?
+            .serve_at(OBJECT_PATH, player)?
+            .build()?;
+        tracing::info!(name = BUS_NAME, "MPRIS player registered");
+
+        let publisher = connection.clone();
+        thread::spawn(move || {
+            let (subscriber, states) = mpsc::channel();
+            if inputs.send(Input::Subscribe(subscriber)).is_ok() {
+                publish(&publisher, &states, &state);
+            }
+        });
+
+        Ok(Self {
+            _connection: connection,
+        })
+    }
+}
+
+/// Keeps the shared state up to date and tells clients what changed.
+fn publish(connection: &Connection, states: &Receiver<State>, shared: &Mutex<(State, Instant)>) {
+    for state in states {
+        let (previous, expected_position) = {
+            let mut shared = shared.lock().unwrap();
+            let expected_position = position(&shared.0, shared.1);
+            let previous = std::mem::replace(&mut *shared, (state.clone(), Instant::now())).0;
+            (previous, expected_position)
+        };
+
+        let mut changed: HashMap<&str, Value> = HashMap::new();
+        if playback_status(&previous) != playback_status(&state) {
+            changed.insert("PlaybackStatus", playback_status(&state).into());
+        }
+        if metadata(&previous) != metadata(&state) {
+            changed.insert("Metadata", metadata(&state).into());
+            changed.insert("CanSeek", can_seek(&state).into());
+        }
+        if previous.volumes.sounds != state.volumes.sounds {
+            changed.insert("Volume", f64::from(state.volumes.sounds).into());
+        }
+        if !changed.is_empty() {
+            let body = (PLAYER_INTERFACE, changed, Vec::<&str>::new());
+            emit(
+                connection,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                &body,
+            );
+        }
+
+        let new_position = state.playing.as_ref().map(|playing| playing.position);
+        if let (Some(expected), Some(position)) = (expected_position, new_position)
+            && (expected.as_secs_f64() - position).abs() > SEEK_TOLERANCE.as_secs_f64()
+        {
+            emit(connection, PLAYER_INTERFACE, "Seeked", &micros(position));
+        }
+    }
+}
+
+fn emit<B>(connection: &Connection, interface: &str, signal: &str, body: &B)
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    if let Err(err) = connection.emit_signal(None::<&str>, OBJECT_PATH, interface, signal, body) {
+        tracing::debug!(%err, signal, "Couldn't emit an MPRIS signal");
+    }
+}
+
+/// Where the playing sound should be by now, `None` if nothing is playing.
+fn position(state: &State, at: Instant) -> Option<Duration> {
+    let playing = state.playing.as_ref()?;
+    let mut position = Duration::from_secs_f64(playing.position);
+    if !state.paused {
+        position += at.elapsed();
+    }
+    Some(match playing.duration {
+        Some(duration) => position.min(Duration::from_secs_f64(duration)),
+        None => position,
+    })
+}
+
+fn playback_status(state: &State) -> &'static str {
+    match (&state.playing, state.paused) {
+        (None, _) => "Stopped",
+        (Some(_), true) => "Paused",
+        (Some(_), false) => "Playing",
+    }
+}
+
+fn can_seek(state: &State) -> bool {
+    state.playing.is_some()
+}
+
+fn metadata(state: &State) -> HashMap<String, Value<'static>> {
+    let mut metadata = HashMap::new();
+    let Some(playing) = &state.playing else {
+        metadata.insert(
+            "mpris:trackid".to_string(),
+            ObjectPath::from_static_str_unchecked(NO_TRACK).into(),
+        );
+        return metadata;
+    };
+
+    metadata.insert(
+        "mpris:trackid".to_string(),
+        ObjectPath::from_static_str_unchecked(TRACK_ID).into(),
+    );
+    metadata.insert("xesam:title".to_string(), playing.name.clone().into());
+    if let Ok(path) = std::path::absolute(&playing.path) {
+        metadata.insert("xesam:url".to_string(), file_url(&path).into());
+    }
+    if let Some(duration) = playing.duration {
+        metadata.insert("mpris:length".to_string(), micros(duration).into());
+    }
+    metadata
+}
+
+fn micros(secs: f64) -> i64 {
+    (secs * 1_000_000.0) as i64
+}
+
+fn file_url(path: &Path) -> String {
+    let mut url = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    url
+}
+
+/// Percent-decodes a `file://` URL.
+fn file_path(url: &str) -> Option<String> {
+    let encoded = url.strip_prefix("file://")?.as_bytes();
+    let mut path = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+            path.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            path.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(path).ok()
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "keysounds"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        [
+            "audio/mpeg",
+            "audio/ogg",
+            "audio/flac",
+            "audio/wav",
+            "audio/aac",
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+}
+
+struct Player {
+    inputs: Sender<Input>,
+    state: SharedState,
+}
+
+impl Player {
+    fn send(&self, command: Command) {
+        // errors like seeking while nothing plays are logged by the soundboard, MPRIS ignores them
+        let _ = self.inputs.send(Input::Command(command, None));
+    }
+
+    fn is_playing(&self) -> bool {
+        self.state.lock().unwrap().0.playing.is_some()
+    }
+
+    fn seek_to(&self, position: i64) {
+        let state = self.state.lock().unwrap();
+        let Some(playing) = &state.0.playing else {
+            return;
+        };
+        let position = Duration::from_micros(position.max(0) as u64);
+        if playing
+            .duration
+            .is_some_and(|duration| position.as_secs_f64() > duration)
+        {
+            // past the end is like going to the next track, which there isn't
+            self.send(Command::Stop);
+        } else {
+            self.send(Command::Seek(position));
+        }
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {}
+
+    fn previous(&self) {}
+
+    fn pause(&self) {
+        self.send(Command::SetPaused(true));
+    }
+
+    fn play_pause(&self) {
+        if self.is_playing() {
+            self.send(Command::TogglePause);
+        }
+    }
+
+    fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    fn play(&self) {
+        self.send(Command::SetPaused(false));
+    }
+
+    /// `offset` is in microseconds, relative to the current position.
+    fn seek(&self, offset: i64) {
+        let position = {
+            let state = self.state.lock().unwrap();
+            position(&state.0, state.1)
+        };
+        if let Some(position) = position {
+            self.seek_to(position.as_micros() as i64 + offset);
+        }
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        if track_id.as_str() == TRACK_ID && position >= 0 {
+            self.seek_to(position);
+        }
+    }
+
+    fn open_uri(&self, uri: &str) -> zbus::fdo::Result<()> {
+        let path = file_path(uri)
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("Can't open {uri}")))?;
+        self.send(Command::Play(SoundRef::Path(path)));
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &'static str {
+        playback_status(&self.state.lock().unwrap().0)
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        metadata(&self.state.lock().unwrap().0)
+    }
+
+    /// The volume of the sounds on the virtual output.
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.state.lock().unwrap().0.volumes.sounds.into()
+    }
+
+    #[zbus(property)]
+    fn set_volume(&self, volume: f64) {
+        let Volumes { sounds, .. } = self.state.lock().unwrap().0.volumes;
+        let volume = volume.max(0.0) as f32;
+        if volume != sounds {
+            self.send(Command::SetVolumes {
+                mic: None,
+                sounds: Some(volume),
+                monitor: None,
+                mic_monitor: None,
+            });
+        }
+    }
+
+    /// Microseconds.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        let state = self.state.lock().unwrap();
+        position(&state.0, state.1).map_or(0, |position| position.as_micros() as i64)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        can_seek(&self.state.lock().unwrap().0)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::control::PlayingInfo;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command as Process, Stdio},
+    };
+    use zbus::blocking::Proxy;
+
+    /// A private session bus, killed when dropped.
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+        }
+    }
+
+    fn private_bus() -> (Bus, String) {
+        let mut child = Process::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon should be installed");
+        let mut address = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        (Bus(child), address.trim().to_string())
+    }
+
+    #[test]
+    fn round_trips_file_urls() {
+        let path = Path::new("/sounds/metal pipe%.ogg");
+        let url = file_url(path);
+        assert_eq!(url, "file:///sounds/metal%20pipe%25.ogg");
+        assert_eq!(file_path(&url).as_deref(), Some("/sounds/metal pipe%.ogg"));
+        assert_eq!(file_path("https://example.com"), None);
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn plays_along_with_clients() {
+        let (_bus, address) = private_bus();
+        let (inputs, received) = mpsc::channel();
+        let soundboard = thread::spawn(move || {
+            let Input::Subscribe(subscriber) = received.recv().unwrap() else {
+                panic!("expected a subscription first");
+            };
+            subscriber
+                .send(State {
+                    playing: Some(PlayingInfo {
+                        name: "Boom".to_string(),
+                        path: "/sounds/boom.ogg".to_string(),
+                        position: 1.0,
+                        duration: Some(4.0),
+                    }),
+                    paused: true,
+                    active_preset: None,
+                    volumes: Volumes::default(),
+                })
+                .unwrap();
+            let mut commands = Vec::new();
+            while let Ok(Input::Command(command, _)) = received.recv() {
+                commands.push(format!("{command:?}"));
+                if commands.len() == 2 {
+                    break;
+                }
+            }
+            (subscriber, commands)
+        });
+
+        let builder = connection::Builder::address(address.as_str()).unwrap();
+        let _server = Server::connect(builder, inputs).unwrap();
+
+        let client = connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        let player = Proxy::new(&client, BUS_NAME, OBJECT_PATH, PLAYER_INTERFACE).unwrap();
+        // the state arrives after the player is on the bus
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while player.get_property::<String>("PlaybackStatus").unwrap() != "Paused" {
+            assert!(Instant::now() < deadline, "the state never arrived");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let position: i64 = player.get_property("Position").unwrap();
+        assert_eq!(position, 1_000_000);
+        let metadata: HashMap<String, zbus::zvariant::OwnedValue> =
+            player.get_property("Metadata").unwrap();
+        let title: String = metadata["xesam:title"].clone().try_into().unwrap();
+        assert_eq!(title, "Boom");
+
+        player.call_method("PlayPause", &()).unwrap();
+        player.call_method("Seek", &(500_000i64)).unwrap();
+
+        let (_subscriber, commands) = soundboard.join().unwrap();
+        assert_eq!(commands, ["TogglePause", "Seek(1.5s)"]);
+    }
+}
@@ -0,0 +1,568 @@
+//! Open Sound Control over UDP, for mixers, TouchOSC layouts and DAWs.
+//!
+//! Messages to `/keysounds/...` become [`Command`]s. If a feedback target is configured, it gets
+//! the [`State`] and the output levels back as OSC messages, from the same port.
+
+use crate::app::{
+    audio::{Levels, MixControl},
+    config::Osc,
+    control::{Command, Input, SoundRef, State},
+};
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the levels are sent to the feedback target.
+const LEVEL_INTERVAL: Duration = Duration::from_millis(50);
+/// Bundles nested deeper than this are dropped.
+const MAX_BUNDLE_DEPTH: usize = 8;
+/// The longest wait before receiving again while the socket keeps failing.
+const MAX_RECEIVE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Listens for OSC messages until the soundboard is dropped.
+pub struct Server {
+    address: SocketAddr,
+}
+
+impl Server {
+    pub fn start(config: &Osc, inputs: Sender<Input>, mix: Arc<MixControl>) -> io::Result<Self> {
+        let feedback = match &config.feedback {
+            Some(target) => Some(target.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, format!("Can't resolve {target}"))
+            })?),
+            None => None,
+        };
+        Self::bind(config.listen.as_str(), feedback, inputs, mix)
+    }
+
+    fn bind(
+        address: impl ToSocketAddrs,
+        feedback: Option<SocketAddr>,
+        inputs: Sender<Input>,
+        mix: Arc<MixControl>,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        let address = socket.local_addr()?;
+        tracing::info!(%address, ?feedback, "OSC listening");
+
+        if let Some(target) = feedback {
+            let socket = socket.try_clone()?;
+            let inputs = inputs.clone();
+            thread::spawn(move || send_feedback(&socket, target, &inputs, &mix));
+        }
+        thread::spawn(move || receive(&socket, &inputs));
+
+        Ok(Self { address })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+fn receive(socket: &UdpSocket, inputs: &Sender<Input>) {
+    let mut buf = vec![0; 65536];
+    let mut backoff = Duration::ZERO;
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => {
+                backoff = Duration::ZERO;
+                received
+            }
+            Err(err) => {
+                // also happens when the feedback target isn't listening on some platforms, and
+                // keeps happening on Windows until it is, so don't spin on it
+                backoff = (backoff * 2)
+                    .max(Duration::from_millis(10))
+                    .min(MAX_RECEIVE_BACKOFF);
+                tracing::debug!(%err, ?backoff, "Couldn't receive an OSC packet");
+                thread::sleep(backoff);
+                continue;
+            }
+        };
+
+        let messages = match decode(&buf[..len]) {
+            Ok(messages) => messages,
+            Err(err) => {
+                tracing::warn!(%from, err, "Invalid OSC packet");
+                continue;
+            }
+        };
+        for message in messages {
+            match parse_command(&message) {
+                Ok(Some(command)) => {
+                    if inputs.send(Input::Command(command, None)).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(%from, address = message.address, err, "Invalid OSC message")
+                }
+            }
+        }
+    }
+}
+
+/// Returns `None` for messages that don't do anything, like the release of a button.
+fn parse_command(message: &Message) -> Result<Option<Command>, String> {
+    let args = message.args.as_slice();
+    // buttons send 1 when pressed and 0 when released
+    let released = matches!(args, [arg] if arg.as_f32() == Some(0.0));
+    let Some(path) = message.address.strip_prefix("/keysounds/") else {
+        return Err("Unknown address".to_string());
+    };
+
+    let command = match (path, args) {
+        ("play", [Arg::String(name), ..]) => Command::Play(SoundRef::Name(name.clone())),
+        ("preset", [Arg::String(name), ..]) => Command::ActivatePreset(name.clone()),
+        ("param", [Arg::String(filter), Arg::String(property), value]) => Command::SetFilterParam {
+            preset: None,
+            filter: filter.clone(),
+            property: property.clone(),
+            value: value.as_f32().ok_or("The value has to be a number")?,
+        },
+        ("seek", [position]) => {
+            let position = position.as_f32().ok_or("The position has to be a number")?;
+            Command::Seek(Duration::try_from_secs_f32(position).map_err(|err| err.to_string())?)
+        }
+        ("volume/mic" | "volume/sounds" | "volume/monitor" | "volume/mic_monitor", [value]) => {
+            let value = Some(value.as_f32().ok_or("The volume has to be a number")?);
+            Command::SetVolumes {
+                mic: value.filter(|_| path == "volume/mic"),
+                sounds: value.filter(|_| path == "volume/sounds"),
+                monitor: value.filter(|_| path == "volume/monitor"),
+                mic_monitor: value.filter(|_| path == "volume/mic_monitor"),
+            }
+        }
+        (_, _) if released => return Ok(None),
+        ("stop", _) => Command::Stop,
+        ("pause", _) => Command::SetPaused(true),
+        ("resume", _) => Command::SetPaused(false),
+        ("toggle_pause", _) => Command::TogglePause,
+        (
+            "play" | "preset" | "param" | "seek" | "volume/mic" | "volume/sounds"
+            | "volume/monitor" | "volume/mic_monitor",
+            _,
+        ) => {
+            return Err("Wrong arguments".to_string());
+        }
+        _ => return Err("Unknown address".to_string()),
+    };
+    Ok(Some(command))
+}
+
+/// Sends what changed in the state as it changes, and the levels every [`LEVEL_INTERVAL`].
+fn send_feedback(socket: &UdpSocket, target: SocketAddr, inputs: &Sender<Input>, mix: &MixControl) {
+    let send = |message: Message| {
+        if let Err(err) = socket.send_to(&message.encode(), target) {
+            tracing::debug!(%err, %target, "Couldn't send OSC feedback");
+        }
+    };
+
+    let (subscriber, states) = mpsc::channel();
+    if inputs.send(Input::Subscribe(subscriber)).is_err() {
+        return;
+    }
+
+    let mut sent_state = None;
+    let mut sent_levels = Levels::default();
+    let mut next_levels = Instant::now();
+    loop {
+        match states.recv_timeout(next_levels.saturating_duration_since(Instant::now())) {
+            Ok(state) => {
+                state_messages(sent_state.as_ref(), &state)
+                    .into_iter()
+                    .for_each(send);
+                sent_state = Some(state);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        if Instant::now() >= next_levels {
+            let levels = mix.take_levels();
+            if levels != sent_levels {
+                send(Message::new(
+                    "/keysounds/level/output",
+                    [Arg::Float(levels.output)],
+                ));
+                send(Message::new(
+                    "/keysounds/level/monitor",
+                    [Arg::Float(levels.monitor)],
+                ));
+                sent_levels = levels;
+            }
+            next_levels = Instant::now() + LEVEL_INTERVAL;
+        }
+    }
+}
+
+/// The messages for the parts of `state` that differ from `sent`.
+fn state_messages(sent: Option<&State>, state: &State) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let changed = |part: fn(&State) -> Arg| sent.is_none_or(|sent| part(sent) != part(state));
+    let mut add = |address: &str, part: fn(&State) -> Arg| {
+        if changed(part) {
+            messages.push(Message::new(address, [part(state)]));
+        }
+    };
+
+    add("/keysounds/playing", |state| {
+        Arg::String(state.playing.as_ref().map_or("", |p| &p.name).to_string())
+    });
+    add("/keysounds/position", |state| {
+        Arg::Float(state.playing.as_ref().map_or(0.0, |p| p.position as f32))
+    });
+    add("/keysounds/duration", |state| {
+        Arg::Float(
+            state
+                .playing
+                .as_ref()
+                .and_then(|p| p.duration)
+                .unwrap_or_default() as f32,
+        )
+    });
+    add("/keysounds/progress", |state| {
+        let progress = state
+            .playing
+            .as_ref()
+            .and_then(|p| Some(p.position / p.duration?))
+            .unwrap_or_default();
+        Arg::Float(progress.min(1.0) as f32)
+    });
+    add("/keysounds/paused", |state| Arg::Int(state.paused.into()));
+    add("/keysounds/preset", |state| {
+        Arg::String(state.active_preset.clone().unwrap_or_default())
+    });
+    add("/keysounds/volume/mic", |state| {
+        Arg::Float(state.volumes.mic)
+    });
+    add("/keysounds/volume/sounds", |state| {
+        Arg::Float(state.volumes.sounds)
+    });
+    add("/keysounds/volume/monitor", |state| {
+        Arg::Float(state.volumes.monitor)
+    });
+    add("/keysounds/volume/mic_monitor", |state| {
+        Arg::Float(state.volumes.mic_monitor)
+    });
+    messages
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Message {
+    address: String,
+    args: Vec<Arg>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Arg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    Bool(bool),
+    Nil,
+    Impulse,
+    Long(i64),
+    Double(f64),
+}
+
+impl Arg {
+    fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::Int(val) => Some(val as f32),
+            Self::Float(val) => Some(val),
+            Self::Bool(val) => Some(val.into()),
+            Self::Long(val) => Some(val as f32),
+            Self::Double(val) => Some(val as f32),
+            _ => None,
+        }
+    }
+
+    fn type_tag(&self) -> u8 {
+        match self {
+            Self::Int(_) => b'i',
+            Self::Float(_) => b'f',
+            Self::String(_) => b's',
+            Self::Blob(_) => b'b',
+            Self::Bool(true) => b'T',
+            Self::Bool(false) => b'F',
+            Self::Nil => b'N',
+            Self::Impulse => b'I',
+            Self::Long(_) => b'h',
+            Self::Double(_) => b'd',
+        }
+    }
+}
+
+impl Message {
+    fn new(address: &str, args: impl IntoIterator<Item = Arg>) -> Self {
+        Self {
+            address: address.to_string(),
+            args: args.into_iter().collect(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_padded(&mut buf, self.address.as_bytes(), true);
+        let tags: Vec<u8> = [b',']
+            .into_iter()
+            .chain(self.args.iter().map(Arg::type_tag))
+            .collect();
+        write_padded(&mut buf, &tags, true);
+
+        for arg in &self.args {
+            match arg {
+                Arg::Int(val) => buf.extend(val.to_be_bytes()),
+                Arg::Float(val) => buf.extend(val.to_be_bytes()),
+                Arg::String(val) => write_padded(&mut buf, val.as_bytes(), true),
+                Arg::Blob(val) => {
+                    buf.extend((val.len() as i32).to_be_bytes());
+                    write_padded(&mut buf, val, false);
+                }
+                Arg::Long(val) => buf.extend(val.to_be_bytes()),
+                Arg::Double(val) => buf.extend(val.to_be_bytes()),
+                Arg::Bool(_) | Arg::Nil | Arg::Impulse => {}
+            }
+        }
+        buf
+    }
+}
+
+/// Writes `data`, a null terminator if `terminate` and then pads to a multiple of 4 bytes.
+fn write_padded(buf: &mut Vec<u8>, data: &[u8], terminate: bool) {
+    buf.extend(data);
+    if terminate {
+        buf.push(0);
+    }
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// Decodes a message or a bundle, whose messages are returned in order. Time tags are ignored,
+/// everything happens right away.
+fn decode(packet: &[u8]) -> Result<Vec<Message>, String> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages, 0)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<Message>, depth: usize) -> Result<(), String> {
+    let mut reader = Reader(packet);
+    if packet.starts_with(b"#bundle\0") {
+        if depth >= MAX_BUNDLE_DEPTH {
+            return Err("Bundles are nested too deep".to_string());
+        }
+        reader.take(16)?; // the "#bundle" string and the time tag
+        while !reader.0.is_empty() {
+            let len = reader.len()?;
+            decode_into(reader.take(len)?, messages, depth + 1)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("Invalid address \"{address}\""));
+    }
+    // very old implementations leave out the type tags
+    let tags = if reader.0.is_empty() {
+        String::new()
+    } else {
+        reader.string()?
+    };
+    let tags = tags
+        .strip_prefix(',')
+        .ok_or("The type tags don't start with ','")?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => Arg::Int(i32::from_be_bytes(reader.array()?)),
+            'f' => Arg::Float(f32::from_be_bytes(reader.array()?)),
+            's' | 'S' => Arg::String(reader.string()?),
+            'b' => {
+                let len = reader.len()?;
+                let blob = reader.take(len)?.to_vec();
+                reader.take(len.next_multiple_of(4) - len)?;
+                Arg::Blob(blob)
+            }
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' => Arg::Nil,
+            'I' => Arg::Impulse,
+            'h' => Arg::Long(i64::from_be_bytes(reader.array()?)),
+            'd' => Arg::Double(f64::from_be_bytes(reader.array()?)),
+            // only the types above can be skipped safely, they're the ones in use anyway
+            _ => return Err(format!("Unsupported type tag '{tag}'")),
+        });
+    }
+    messages.push(Message { address, args });
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err("The packet ends too early".to_string());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        usize::try_from(i32::from_be_bytes(self.array()?)).map_err(|_| "Negative size".to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self
+            .0
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or("A string isn't terminated")?;
+        let string = String::from_utf8(self.0[..len].to_vec())
+            .map_err(|_| "A string isn't valid UTF-8".to_string())?;
+        self.take((len + 1).next_multiple_of(4))?;
+        Ok(string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{config::Volumes, control::PlayingInfo};
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let message = Message::new(
+            "/keysounds/param",
+            [
+                Arg::String("Reverb".to_string()),
+                Arg::String("room".to_string()),
+                Arg::Float(0.9),
+                Arg::Int(-3),
+                Arg::Blob(vec![1, 2, 3, 4, 5]),
+                Arg::Bool(true),
+                Arg::Nil,
+                Arg::Long(1 << 40),
+                Arg::Double(0.25),
+            ],
+        );
+        let encoded = message.encode();
+        assert_eq!(encoded.len() % 4, 0);
+        assert_eq!(decode(&encoded), Ok(vec![message.clone()]));
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for _ in 0..2 {
+            bundle.extend((encoded.len() as i32).to_be_bytes());
+            bundle.extend(&encoded);
+        }
+        assert_eq!(decode(&bundle), Ok(vec![message.clone(), message]));
+
+        assert!(decode(&encoded[..encoded.len() - 4]).is_err());
+        assert!(decode(b"/no/terminator").is_err());
+    }
+
+    #[test]
+    fn maps_messages_to_commands() {
+        let command = |address, args: Vec<Arg>| {
+            parse_command(&Message::new(address, args)).map(|command| format!("{command:?}"))
+        };
+
+        assert_eq!(
+            command("/keysounds/play", vec![Arg::String("Boom".to_string())]),
+            Ok("Some(Play(Name(\"Boom\")))".to_string())
+        );
+        assert_eq!(
+            command("/keysounds/stop", vec![]),
+            Ok("Some(Stop)".to_string())
+        );
+        assert_eq!(
+            command("/keysounds/stop", vec![Arg::Float(0.0)]),
+            Ok("None".to_string())
+        );
+        assert_eq!(
+            command("/keysounds/volume/sounds", vec![Arg::Float(0.0)]),
+            Ok(
+                "Some(SetVolumes { mic: None, sounds: Some(0.0), monitor: None, mic_monitor: None })"
+                    .to_string()
+            )
+        );
+        assert!(command("/keysounds/play", vec![]).is_err());
+        assert!(command("/other/play", vec![]).is_err());
+    }
+
+    #[test]
+    fn controls_and_reports_over_loopback() {
+        let (inputs, received) = mpsc::channel();
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        remote
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let server = Server::bind(
+            "127.0.0.1:0",
+            Some(remote.local_addr().unwrap()),
+            inputs,
+            Arc::new(MixControl::default()),
+        )
+        .unwrap();
+
+        let play = Message::new("/keysounds/play", [Arg::String("Boom".to_string())]);
+        remote.send_to(&play.encode(), server.address()).unwrap();
+
+        let mut played = false;
+        let mut subscribed = false;
+        while !(played && subscribed) {
+            match received.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Input::Command(Command::Play(SoundRef::Name(name)), None) => {
+                    assert_eq!(name, "Boom");
+                    played = true;
+                }
+                Input::Subscribe(subscriber) => {
+                    let state = State {
+                        playing: Some(PlayingInfo {
+                            name: "Boom".to_string(),
+                            path: "boom.ogg".to_string(),
+                            position: 1.0,
+                            duration: Some(4.0),
+                        }),
+                        paused: false,
+                        active_preset: None,
+                        volumes: Volumes::default(),
+                    };
+                    subscriber.send(state).unwrap();
+                    subscribed = true;
+                }
+                _ => panic!("unexpected input"),
+            }
+        }
+
+        let mut buf = [0; 1024];
+        let mut feedback = Vec::new();
+        while feedback.len() < 9 {
+            let len = remote.recv(&mut buf).unwrap();
+            feedback.extend(decode(&buf[..len]).unwrap());
+        }
+        assert_eq!(
+            feedback[0],
+            Message::new("/keysounds/playing", [Arg::String("Boom".to_string())])
+        );
+        assert!(feedback.contains(&Message::new("/keysounds/progress", [Arg::Float(0.25)])));
+    }
+}
@@ -0,0 +1,432 @@
+//! JSON-RPC 2.0 over a Unix socket, or a named pipe on Windows, one message per line.
+
+use crate::app::control::{self, Command, Input, SoundRef};
+use interprocess::local_socket::{ListenerOptions, Name, Stream, prelude::*};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    sync::mpsc::Sender,
+    thread,
+    time::Duration,
+};
+
+/// Overrides where the socket is created and looked for.
+const ADDRESS_ENV: &str = "KEYSOUNDS_SOCKET";
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// The command was valid, but couldn't be carried out.
+const COMMAND_FAILED: i32 = -32000;
+/// The soundboard is shutting down or stuck.
+const NOT_RESPONDING: i32 = -32001;
+/// The web remote hasn't been paired yet.
+pub(super) const NOT_PAIRED: i32 = -32002;
+
+#[derive(Deserialize)]
+pub(super) struct Request {
+    jsonrpc: String,
+    pub(super) method: String,
+    #[serde(default)]
+    pub(super) params: Value,
+    /// Notifications don't have an id and don't get a response.
+    pub(super) id: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct Response {
+    jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    pub(super) fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl Response {
+    pub(super) fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayParams {
+    name: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeParams {
+    mic: Option<f32>,
+    sounds: Option<f32>,
+    monitor: Option<f32>,
+    mic_monitor: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeekParams {
+    /// Seconds.
+    position: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterParamParams {
+    preset: Option<String>,
+    filter: String,
+    property: String,
+    value: f32,
+}
+
+/// Accepts control connections until dropped.
+pub struct Server {
+    address: OsString,
+}
+
+impl Server {
+    pub fn start(inputs: Sender<Input>) -> io::Result<Self> {
+        Self::listen(default_address(), inputs)
+    }
+
+    fn listen(address: OsString, inputs: Sender<Input>) -> io::Result<Self> {
+        let listener = match ListenerOptions::new()
+            .name(to_name(&address)?)
+            .create_sync()
+        {
+            Err(err) if err.kind() == ErrorKind::AddrInUse => {
+                if Stream::connect(to_name(&address)?).is_ok() {
+                    return Err(io::Error::new(
+                        ErrorKind::AddrInUse,
+                        format!("{} is used by another instance", address.display()),
+                    ));
+                }
+                // left behind by an instance that didn't shut down cleanly
+                ListenerOptions::new()
+                    .name(to_name(&address)?)
+                    .try_overwrite(true)
+                    .create_sync()?
+            }
+            result => result?,
+        };
+        tracing::info!(address = %address.display(), "Control server listening");
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let inputs = inputs.clone();
+                        thread::spawn(move || serve(&stream, &inputs));
+                    }
+                    Err(err) => tracing::warn!(%err, "Control connection failed"),
+                }
+            }
+        });
+
+        Ok(Self { address })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // the listener thread never stops on its own, so the socket file is removed here instead
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.address);
+    }
+}
+
+/// Sends a request to the running instance and waits for its result.
+pub fn call(method: &str, params: Value) -> Result<Value, String> {
+    call_at(&default_address(), method, params)
+}
+
+fn call_at(address: &OsStr, method: &str, params: Value) -> Result<Value, String> {
+    let stream = to_name(address).and_then(Stream::connect).map_err(|err| {
+        format!(
+            "Couldn't connect to keysounds at {}, is it running? ({err})",
+            address.display()
+        )
+    })?;
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let mut line = request.to_string();
+    line.push('\n');
+    (&stream)
+        .write_all(line.as_bytes())
+        .map_err(|err| format!("Couldn't send the request: {err}"))?;
+
+    line.clear();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|err| format!("Couldn't read the response: {err}"))?;
+    let response: Response = serde_json::from_str(&line)
+        .map_err(|err| format!("Invalid response \"{}\": {err}", line.trim()))?;
+
+    match response.error {
+        Some(error) => Err(error.message),
+        None => Ok(response.result.unwrap_or_default()),
+    }
+}
+
+/// `$KEYSOUNDS_SOCKET`, otherwise `keysounds.sock` in the runtime directory on Unix and the
+/// `keysounds` pipe on Windows.
+fn default_address() -> OsString {
+    if let Some(address) = env::var_os(ADDRESS_ENV) {
+        return address;
+    }
+
+    #[cfg(unix)]
+    return dirs::runtime_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("keysounds.sock")
+        .into_os_string();
+    #[cfg(windows)]
+    return OsString::from("keysounds");
+}
+
+fn to_name(address: &OsStr) -> io::Result<Name<'_>> {
+    #[cfg(unix)]
+    return address.to_fs_name::<interprocess::local_socket::GenericFilePath>();
+    #[cfg(windows)]
+    return address.to_ns_name::<interprocess::local_socket::GenericNamespaced>();
+}
+
+fn serve(stream: &Stream, inputs: &Sender<Input>) {
+    let mut writer = stream;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = handle_request(&line, inputs) {
+            let mut response = serde_json::to_string(&response).unwrap();
+            response.push('\n');
+            if writer.write_all(response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs one request, returns the response unless it was a notification.
+pub(super) fn handle_request(line: &str, inputs: &Sender<Input>) -> Option<Response> {
+    match parse_request(line) {
+        Ok(request) => handle(request, inputs),
+        Err(response) => Some(response),
+    }
+}
+
+/// Parses `line` into a request, or the error response to send back instead.
+pub(super) fn parse_request(line: &str) -> Result<Request, Response> {
+    let request = serde_json::from_str::<Value>(line).map_err(|err| {
+        Response::new(
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, err.to_string())),
+        )
+    })?;
+    match serde_json::from_value::<Request>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => Ok(request),
+        Ok(_) => Err(Response::new(
+            Value::Null,
+            Err(RpcError::new(
+                INVALID_REQUEST,
+                "Only JSON-RPC 2.0 is supported",
+            )),
+        )),
+        Err(err) => Err(Response::new(
+            Value::Null,
+            Err(RpcError::new(INVALID_REQUEST, err.to_string())),
+        )),
+    }
+}
+
+/// Runs the command `request` asks for, the response is `None` for notifications.
+pub(super) fn handle(request: Request, inputs: &Sender<Input>) -> Option<Response> {
+    let command = parse_command(&request.method, request.params);
+    let Some(id) = request.id else {
+        if let Ok(command) = command {
+            let _ = inputs.send(Input::Command(command, None));
+        }
+        return None;
+    };
+
+    Some(Response::new(
+        id,
+        command.and_then(|command| run(command, inputs)),
+    ))
+}
+
+fn parse_command(method: &str, params: Value) -> Result<Command, RpcError> {
+    Ok(match method {
+        "list_sounds" => Command::ListSounds,
+        "list_presets" => Command::ListPresets,
+        "play" => match parse_params(params)? {
+            PlayParams {
+                name: Some(name),
+                path: None,
+            } => Command::Play(SoundRef::Name(name)),
+            PlayParams {
+                name: None,
+                path: Some(path),
+            } => Command::Play(SoundRef::Path(path)),
+            _ => {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    "play needs either a name or a path",
+                ));
+            }
+        },
+        "stop" => Command::Stop,
+        "pause" => Command::SetPaused(true),
+        "resume" => Command::SetPaused(false),
+        "toggle_pause" => Command::TogglePause,
+        "seek" => {
+            let SeekParams { position } = parse_params(params)?;
+            Command::Seek(
+                Duration::try_from_secs_f64(position)
+                    .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?,
+            )
+        }
+        "activate_preset" => Command::ActivatePreset(parse_params::<NameParams>(params)?.name),
+        "set_volumes" => {
+            let VolumeParams {
+                mic,
+                sounds,
+                monitor,
+                mic_monitor,
+            } = parse_params(params)?;
+            Command::SetVolumes {
+                mic,
+                sounds,
+                monitor,
+                mic_monitor,
+            }
+        }
+        "set_filter_param" => {
+            let FilterParamParams {
+                preset,
+                filter,
+                property,
+                value,
+            } = parse_params(params)?;
+            Command::SetFilterParam {
+                preset,
+                filter,
+                property,
+                value,
+            }
+        }
+        "state" => Command::State,
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method \"{method}\""),
+            ));
+        }
+    })
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
+
+/// Hands `command` to the soundboard and waits for the result.
+fn run(command: Command, inputs: &Sender<Input>) -> Result<Value, RpcError> {
+    match control::execute(inputs, command) {
+        Ok(Ok(reply)) => Ok(serde_json::to_value(reply).unwrap()),
+        Ok(Err(err)) => Err(RpcError::new(COMMAND_FAILED, err)),
+        Err(err) => Err(RpcError::new(NOT_RESPONDING, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::control::fake_soundboard;
+    use std::process;
+
+    #[test]
+    fn reports_protocol_errors() {
+        let (tx, _executed) = fake_soundboard();
+
+        let error_code = |line| {
+            handle_request(line, &tx)
+                .and_then(|response| response.error)
+                .map(|error| error.code)
+        };
+        assert_eq!(error_code("{"), Some(PARSE_ERROR));
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"1.0","method":"stop","id":1}"#),
+            Some(INVALID_REQUEST)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"nope","id":1}"#),
+            Some(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"play","params":{},"id":1}"#),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"play","params":{"name":"Missing"},"id":1}"#),
+            Some(COMMAND_FAILED)
+        );
+        assert!(handle_request(r#"{"jsonrpc":"2.0","method":"stop"}"#, &tx).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn calls_over_the_socket() {
+        let address = env::temp_dir()
+            .join(format!("keysounds-test-{}.sock", process::id()))
+            .into_os_string();
+        let (tx, _executed) = fake_soundboard();
+        let _server = Server::listen(address.clone(), tx).unwrap();
+
+        assert_eq!(call_at(&address, "stop", Value::Null), Ok(Value::Null));
+        assert_eq!(call_at(&address, "list_sounds", Value::Null), Ok(json!([])));
+        assert_eq!(
+            call_at(&address, "play", json!({ "name": "Missing" })),
+            Err("No sound named \"Missing\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,605 @@
+//! Serves the web remote page and talks JSON-RPC with it over a WebSocket.
+//!
+//! A page has to pair once with the code shown in the GUI and the log, which gets it a token it
+//! authenticates with from then on. Paired pages get the [`State`] pushed as `state`
+//! notifications.
+
+use crate::app::{
+    App,
+    config::WebRemote,
+    control::{
+        Input, State,
+        rpc::{self, NOT_PAIRED, Response, RpcError},
+    },
+};
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tungstenite::{Message, WebSocket, handshake::derive_accept_key, protocol::Role};
+
+const INDEX: &str = include_str!("web/index.html");
+/// Letters and digits that can't be mistaken for each other, 32 of them so every byte maps evenly.
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 8;
+/// Wrong codes from one address before it gets locked out.
+const PAIRING_ATTEMPTS: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+/// How long a connection waits after a wrong code before it can try again.
+const WRONG_CODE_DELAY: Duration = Duration::from_secs(1);
+/// The least time between two pairing attempts from anywhere.
+const ATTEMPT_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a WebSocket waits for messages before checking for state changes to push.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Requests with a longer request line and headers are cut off.
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+const MAX_HEADERS: usize = 64;
+/// How long a client gets to send its request.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections served at once, more are closed right away.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Serves the web remote until the process exits.
+pub struct Server {
+    address: SocketAddr,
+    pairing: Arc<Pairing>,
+}
+
+impl Server {
+    pub fn start(config: &WebRemote, inputs: Sender<Input>) -> io::Result<Self> {
+        let tokens_file = App::config_file().with_file_name("web_remote_tokens");
+        Self::bind((config.address.as_str(), config.port), tokens_file, inputs)
+    }
+
+    fn bind(
+        address: impl ToSocketAddrs,
+        tokens_file: PathBuf,
+        inputs: Sender<Input>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let pairing = Arc::new(Pairing::load(tokens_file));
+        tracing::info!(%address, code = pairing.code(), "Web remote listening");
+
+        let server_pairing = Arc::clone(&pairing);
+        let connections = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                    tracing::debug!("Too many web remote connections, closed one");
+                    continue;
+                }
+                let pairing = Arc::clone(&server_pairing);
+                let (inputs, connections) = (inputs.clone(), Arc::clone(&connections));
+                thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, &pairing, &inputs) {
+                        tracing::debug!(%err, "Web remote connection failed");
+                    }
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        Ok(Self { address, pairing })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Changes after every pairing.
+    pub fn pairing_code(&self) -> String {
+        self.pairing.code()
+    }
+}
+
+/// The pairing code and the tokens of the pages that paired with it.
+struct Pairing {
+    code: Mutex<String>,
+    attempts: Mutex<Attempts>,
+    tokens: Mutex<Vec<String>>,
+    tokens_file: PathBuf,
+}
+
+/// Slows down guessing the pairing code, without letting one client keep everyone else out.
+#[derive(Default)]
+struct Attempts {
+    /// Wrong codes in a row and when the last one came, by address.
+    failures: HashMap<IpAddr, (u32, Instant)>,
+    last: Option<Instant>,
+}
+
+impl Pairing {
+    fn load(tokens_file: PathBuf) -> Self {
+        let tokens = fs::read_to_string(&tokens_file)
+            .map(|tokens| tokens.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self {
+            code: Mutex::new(new_code()),
+            attempts: Mutex::default(),
+            tokens: Mutex::new(tokens),
+            tokens_file,
+        }
+    }
+
+    fn code(&self) -> String {
+        self.code.lock().unwrap().clone()
+    }
+
+    /// Returns a new token if `code` is right, ignoring case, spaces and dashes.
+    fn pair(&self, code: &str, from: IpAddr, now: Instant) -> Result<String, &'static str> {
+        let mut attempts = self.attempts.lock().unwrap();
+        if let Some((failed, last)) = attempts.failures.get(&from)
+            && *failed >= PAIRING_ATTEMPTS
+            && now < *last + LOCKOUT
+        {
+            return Err("Too many wrong pairing codes, try again in a minute");
+        }
+        if attempts
+            .last
+            .is_some_and(|last| now < last + ATTEMPT_INTERVAL)
+        {
+            return Err("Too many pairing attempts, try again");
+        }
+        attempts.last = Some(now);
+
+        let code: String = code
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_uppercase)
+            .collect();
+        let mut current = self.code.lock().unwrap();
+        if code != *current {
+            let (failed, last) = attempts.failures.entry(from).or_insert((0, now));
+            // a lockout that ran out starts over
+            if *failed >= PAIRING_ATTEMPTS {
+                *failed = 0;
+            }
+            *failed += 1;
+            *last = now;
+            if *failed == PAIRING_ATTEMPTS {
+                tracing::warn!(%from, "Too many wrong pairing codes, locked out for a minute");
+            }
+            return Err("Wrong pairing code");
+        }
+        attempts.failures.remove(&from);
+        *current = new_code();
+        tracing::info!(
+            code = *current,
+            "Paired a web remote, changed the pairing code"
+        );
+
+        let token = random_hex(16);
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.push(token.clone());
+        if let Err(err) = write_private(&self.tokens_file, &tokens.join("\n")) {
+            tracing::warn!(%err, "Couldn't save the web remote tokens");
+        }
+        Ok(token)
+    }
+
+    fn is_paired(&self, token: &str) -> bool {
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|paired| paired == token)
+    }
+}
+
+/// Writes `contents` to `path` so that only the current user can read it, the tokens let anyone
+/// who has them control the soundboard.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // files from before keep their permissions otherwise
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn new_code() -> String {
+    let mut bytes = [0; CODE_LEN];
+    getrandom::fill(&mut bytes).expect("no random numbers available");
+    bytes
+        .iter()
+        .map(|byte| CODE_ALPHABET[*byte as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    getrandom::fill(&mut bytes).expect("no random numbers available");
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+struct Head {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid request line",
+            ));
+        };
+        let (method, path) = (method.to_string(), path.to_string());
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(io::Error::new(ErrorKind::InvalidData, "too many headers"));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        Ok(Self {
+            method,
+            path,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    pairing: &Pairing,
+    inputs: &Sender<Input>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(HEAD_TIMEOUT))?;
+    let peer = stream.peer_addr()?.ip();
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEAD_BYTES));
+    let head = Head::read(&mut reader)?;
+    let path = head.path.split('?').next().unwrap_or_default();
+
+    match (head.method.as_str(), path) {
+        ("GET", "/") => respond(&stream, "200 OK", "text/html; charset=utf-8", INDEX),
+        ("GET", "/ws") => {
+            let key = head.header("sec-websocket-key").filter(|_| {
+                head.header("upgrade")
+                    .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+            });
+            let Some(key) = key else {
+                return respond(
+                    &stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    "Expected a WebSocket",
+                );
+            };
+            if !same_origin(&head) {
+                return respond(&stream, "403 Forbidden", "text/plain", "Wrong origin");
+            }
+
+            write!(
+                &stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            )?;
+            let buffered = reader.buffer().to_vec();
+            let socket = WebSocket::from_partially_read(stream, buffered, Role::Server, None);
+            session(socket, peer, pairing, inputs)
+        }
+        ("GET", _) => respond(&stream, "404 Not Found", "text/plain", "Not found"),
+        _ => respond(
+            &stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed",
+        ),
+    }
+}
+
+/// Whether a browser opened the WebSocket from the page of the remote itself, and not from some
+/// other site. Clients that aren't browsers don't send an origin.
+fn same_origin(head: &Head) -> bool {
+    let Some(origin) = head.header("origin") else {
+        return true;
+    };
+    let host = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"));
+    host.zip(head.header("host"))
+        .is_some_and(|(origin, host)| origin.eq_ignore_ascii_case(host))
+}
+
+fn respond(mut stream: &TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn session(
+    mut socket: WebSocket<TcpStream>,
+    peer: IpAddr,
+    pairing: &Pairing,
+    inputs: &Sender<Input>,
+) -> io::Result<()> {
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    // only set once paired
+    let mut states: Option<Receiver<State>> = None;
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Some(response) = handle_message(&text, &mut states, peer, pairing, inputs) {
+                    send(&mut socket, serde_json::to_string(&response).unwrap())?;
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(io::Error::other(err)),
+        }
+
+        if let Some(states) = &states {
+            while let Ok(state) = states.try_recv() {
+                let notification = json!({ "jsonrpc": "2.0", "method": "state", "params": state });
+                send(&mut socket, notification.to_string())?;
+            }
+        }
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, text: String) -> io::Result<()> {
+    socket.send(Message::text(text)).map_err(io::Error::other)
+}
+
+/// Pairs and authenticates the page, everything else needs a paired page and goes to
+/// [`rpc::handle_request`].
+fn handle_message(
+    text: &str,
+    states: &mut Option<Receiver<State>>,
+    peer: IpAddr,
+    pairing: &Pairing,
+    inputs: &Sender<Input>,
+) -> Option<Response> {
+    // the same request decides about pairing and gets dispatched, so the two can't disagree
+    let request = match rpc::parse_request(text) {
+        Ok(request) => request,
+        Err(response) => return Some(response),
+    };
+    let pairing_method = matches!(request.method.as_str(), "pair" | "auth");
+    if states.is_some() && !pairing_method {
+        return rpc::handle(request, inputs);
+    }
+
+    let param = |name| {
+        request
+            .params
+            .get(name)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    };
+    let result = match request.method.as_str() {
+        "pair" => match pairing.pair(param("code"), peer, Instant::now()) {
+            Ok(token) => {
+                *states = subscribe(inputs);
+                Ok(json!({ "token": token }))
+            }
+            Err(message) => {
+                // slows down guessing on this connection, without holding up anyone else
+                thread::sleep(WRONG_CODE_DELAY);
+                Err(RpcError::new(NOT_PAIRED, message))
+            }
+        },
+        "auth" if pairing.is_paired(param("token")) => {
+            *states = subscribe(inputs);
+            Ok(Value::Null)
+        }
+        "auth" => Err(RpcError::new(NOT_PAIRED, "Unknown token, pair again")),
+        _ => Err(RpcError::new(NOT_PAIRED, "Pair first")),
+    };
+    Some(Response::new(
+        request.id.clone().unwrap_or_default(),
+        result,
+    ))
+}
+
+fn subscribe(inputs: &Sender<Input>) -> Option<Receiver<State>> {
+    let (tx, rx) = mpsc::channel();
+    inputs.send(Input::Subscribe(tx)).ok()?;
+    Some(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::control::fake_soundboard;
+    use std::{env, process};
+    use tungstenite::stream::MaybeTlsStream;
+
+    fn call(
+        socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+        method: &str,
+        params: Value,
+    ) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        socket.send(Message::text(request.to_string())).unwrap();
+        loop {
+            let message: Value =
+                serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap();
+            if message["id"] == 1 {
+                return message;
+            }
+        }
+    }
+
+    #[test]
+    fn pairs_and_forwards_commands() {
+        let tokens_file = env::temp_dir().join(format!("keysounds-test-tokens-{}", process::id()));
+        let (tx, _executed) = fake_soundboard();
+        let server = Server::bind("127.0.0.1:0", tokens_file.clone(), tx).unwrap();
+
+        let url = format!("ws://{}/ws", server.address());
+        let (mut socket, _) = tungstenite::connect(&url).unwrap();
+
+        let response = call(&mut socket, "list_presets", Value::Null);
+        assert_eq!(response["error"]["code"], NOT_PAIRED);
+
+        let response = call(&mut socket, "pair", json!({ "code": "wrong" }));
+        assert_eq!(response["error"]["code"], NOT_PAIRED);
+
+        let code = server.pairing_code();
+        let response = call(&mut socket, "pair", json!({ "code": code.to_lowercase() }));
+        let token = response["result"]["token"].as_str().unwrap().to_string();
+        assert_ne!(server.pairing_code(), code);
+
+        let response = call(&mut socket, "list_presets", Value::Null);
+        assert_eq!(response["result"], json!(["Echo"]));
+
+        // a new page authenticates with the token instead of pairing again
+        let (mut socket, _) = tungstenite::connect(&url).unwrap();
+        let response = call(&mut socket, "auth", json!({ "token": token }));
+        assert_eq!(response["result"], Value::Null);
+        let state: Value = serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(state["method"], "state");
+        assert_eq!(state["params"]["paused"], false);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&tokens_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(tokens_file).unwrap();
+    }
+
+    #[test]
+    fn locks_out_after_wrong_codes() {
+        let tokens_file = env::temp_dir().join("keysounds-test-unused-tokens");
+        let pairing = Pairing::load(tokens_file);
+        let guesser = IpAddr::from([192, 168, 1, 20]);
+        let owner = IpAddr::from([192, 168, 1, 10]);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        for secs in 0..PAIRING_ATTEMPTS as u64 {
+            assert!(pairing.pair("AAAAAAAA", guesser, at(secs)).is_err());
+        }
+        // too soon after the last attempt, even from elsewhere
+        let code = pairing.code();
+        assert!(pairing.pair(&code, owner, at(4)).is_err());
+
+        // the right code doesn't help the guesser while locked out
+        assert!(pairing.pair(&code, guesser, at(10)).is_err());
+        assert_eq!(pairing.code(), code);
+        // but the owner can still pair
+        assert!(pairing.pair(&code, owner, at(11)).is_ok());
+        assert_ne!(pairing.code(), code);
+
+        let code = pairing.code();
+        assert!(pairing.pair(&code, guesser, at(70)).is_ok());
+    }
+
+    #[test]
+    fn unpaired_pages_cant_run_commands() {
+        let pairing = Pairing::load(env::temp_dir().join("keysounds-test-unused-tokens"));
+        let (inputs, executed) = fake_soundboard();
+        let mut states = None;
+        let peer = IpAddr::from([192, 168, 1, 20]);
+
+        for text in [
+            r#"{"jsonrpc":"2.0","method":"play","params":{"name":"x"},"id":1}"#,
+            // duplicate keys and the array form of a struct both parse as requests
+            r#"{"jsonrpc":"2.0","method":"play","method":"play","params":{"name":"x"},"id":1}"#,
+            r#"["2.0","play",{"name":"x"},1]"#,
+        ] {
+            let response = handle_message(text, &mut states, peer, &pairing, &inputs).unwrap();
+            let response = serde_json::to_value(response).unwrap();
+            assert_eq!(response["error"]["code"], NOT_PAIRED, "{text}");
+        }
+        assert!(executed.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn serves_the_page() {
+        let (tx, _rx) = mpsc::channel();
+        let tokens_file = env::temp_dir().join("keysounds-test-unused-tokens");
+        let server = Server::bind("127.0.0.1:0", tokens_file, tx).unwrap();
+
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(INDEX));
+    }
+
+    #[test]
+    fn refuses_sockets_from_other_sites() {
+        let (tx, _rx) = mpsc::channel();
+        let tokens_file = env::temp_dir().join("keysounds-test-unused-tokens");
+        let server = Server::bind("127.0.0.1:0", tokens_file, tx).unwrap();
+
+        let handshake = |origin: &str| {
+            let mut stream = TcpStream::connect(server.address()).unwrap();
+            write!(
+                stream,
+                "GET /ws HTTP/1.1\r\nHost: {}\r\nOrigin: {origin}\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                 Sec-WebSocket-Version: 13\r\n\r\n",
+                server.address()
+            )
+            .unwrap();
+            let mut status = String::new();
+            BufReader::new(stream).read_line(&mut status).unwrap();
+            status
+        };
+
+        assert!(handshake("https://evil.example").starts_with("HTTP/1.1 403"));
+        let own = format!("http://{}", server.address());
+        assert!(handshake(&own).starts_with("HTTP/1.1 101"));
+    }
+}
@@ -1,13 +1,19 @@
 use crate::app::{
-    App, DeviceOption, Page, Sound,
-    config::{Keybind, filter::FilterProperty},
+    App, DeviceOption, Page, Route, Sound,
+    config::{
+        Keybind, Volumes,
+        filter::FilterProperty,
+        macros::{Macro, MacroStep},
+        midi::{MidiAction, MidiMapping},
+    },
+    control::Input,
 };
 use iced::{
     Subscription, Task,
     keyboard::{self, Modifiers},
     time,
 };
-use std::{path::Path, sync::atomic::Ordering, time::Duration};
+use std::{path::Path, time::Duration};
 
 mod view;
 
@@ -22,6 +28,7 @@
 pub enum KeybindTarget {
     SearchAndPlay,
     StopSound,
+    Macro(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -38,15 +45,33 @@ pub enum Message {
     ToggleFilter(usize, bool),
     ExpandFilter(usize),
     ChangeFilterProperty(usize, FilterProperty),
+    // Macros
+    SelectMacro(usize),
+    AddMacro,
+    RemoveMacro(usize),
+    RunMacro(usize),
+    RenameMacro(String),
+    AddMacroStep(&'static str),
+    ChangeMacroStep(usize, MacroStep),
+    MoveMacroStepUp(usize),
+    RemoveMacroStep(usize),
     // Settings
     SetMicDevice(DeviceOption),
     SetOutDevice(DeviceOption),
     SetVirtualOutDevice(DeviceOption),
     SetGuiScale(f32),
+    SetSoundFilterPreset(Option<String>),
+    SetVolumes(Volumes),
+    SetSoundRoute(usize, Route),
     // Keybinds
     StartRecordingKeybind(KeybindTarget),
     CancelRecordingKeybind,
     ClearKeybind(KeybindTarget),
+    // MIDI
+    ToggleMidiMapMode,
+    StartMidiLearn(MidiAction),
+    CancelMidiLearn,
+    ClearMidiMapping(MidiAction),
 }
 
 impl App {
@@ -61,9 +86,9 @@ pub fn update(&mut self, message: Message) -> Task<Message> {
                         .iter()
                         .enumerate()
                         .min_by(|a, b| {
-                            (a.1 - self.config.gui_scale)
+                            (a.1 - self.soundboard.config.gui_scale)
                                 .abs()
-                                .total_cmp(&(b.1 - self.config.gui_scale).abs())
+                                .total_cmp(&(b.1 - self.soundboard.config.gui_scale).abs())
                         })
                         .map(|(i, _)| i)
                         .unwrap_or(0);
@@ -73,23 +98,24 @@ pub fn update(&mut self, message: Message) -> Task<Message> {
                     } else {
                         (idx + 1).min(SCALES.len() - 1)
                     };
-                    self.config.gui_scale = SCALES[new_idx];
+                    self.soundboard.config.gui_scale = SCALES[new_idx];
                 }
                 _ => {}
             },
             Message::Keyboard(_) => {}
             Message::ChangePage(page) => {
                 self.recording_keybind = None;
+                self.learning_midi = None;
                 self.page = page;
             }
             Message::PlaySound(index) => {
-                if let Some(sound) = self.config.sounds.get(index) {
-                    self.play_sound(sound.clone(), false);
+                if let Some(sound) = self.soundboard.config.sounds.get(index) {
+                    self.soundboard.play_sound(sound.clone(), false);
                 }
             }
             Message::StopSound => {
-                *self.decoder.lock().unwrap() = None;
-                self.playing_sound = None;
+                self.soundboard.stop_sound();
+                self.soundboard.stop_macros();
             }
             Message::SearchInput(input) => self.search = input,
             Message::SearchSubmit => {
@@ -98,7 +124,7 @@ pub fn update(&mut self, message: Message) -> Task<Message> {
                     let path = self.search.trim_matches('"').to_string();
                     if Path::new(&path).exists() {
                         self.search.clear();
-                        self.play_sound_from_path(path);
+                        self.soundboard.play_sound_from_path(path);
                     }
                 } else {
                     let sound = self
@@ -106,22 +132,63 @@ pub fn update(&mut self, message: Message) -> Task<Message> {
                         .next()
                         .map(|(_, sound)| sound.clone());
                     if let Some(sound) = sound {
-                        self.play_sound(sound, false);
+                        self.soundboard.play_sound(sound, false);
                     }
                 }
             }
             Message::SelectPreset(idx) => self.selected_preset = idx,
             Message::ToggleFilter(idx, v) => {
-                self.config.filter_presets[self.selected_preset].filters[idx].enabled = v;
+                self.soundboard.config.filter_presets[self.selected_preset].filters[idx].enabled =
+                    v;
             }
             Message::ExpandFilter(idx) => {
-                let filter = &mut self.config.filter_presets[self.selected_preset].filters[idx];
+                let filter =
+                    &mut self.soundboard.config.filter_presets[self.selected_preset].filters[idx];
                 filter.expanded = !filter.expanded;
             }
             Message::ChangeFilterProperty(idx, prop) => {
-                let filter = &mut self.config.filter_presets[self.selected_preset].filters[idx];
+                let filter =
+                    &mut self.soundboard.config.filter_presets[self.selected_preset].filters[idx];
                 prop.set(&mut filter.filter_type);
             }
+            Message::SelectMacro(idx) => {
+                self.recording_keybind = None;
+                self.selected_macro = idx;
+            }
+            Message::AddMacro => {
+                let macros = &mut self.soundboard.config.macros;
+                macros.push(Macro::new(format!("Macro {}", macros.len() + 1)));
+                self.selected_macro = macros.len() - 1;
+            }
+            Message::RemoveMacro(idx) => {
+                self.recording_keybind = None;
+                self.soundboard.config.macros.remove(idx);
+                self.selected_macro = idx.saturating_sub(1);
+            }
+            Message::RunMacro(idx) => self.soundboard.run_macro(idx),
+            Message::RenameMacro(name) => {
+                self.soundboard.config.macros[self.selected_macro].name = name;
+            }
+            Message::AddMacroStep(kind) => {
+                if let Some(step) = MacroStep::new(kind) {
+                    self.soundboard.config.macros[self.selected_macro]
+                        .steps
+                        .push(step);
+                }
+            }
+            Message::ChangeMacroStep(idx, step) => {
+                self.soundboard.config.macros[self.selected_macro].steps[idx] = step;
+            }
+            Message::MoveMacroStepUp(idx) => {
+                self.soundboard.config.macros[self.selected_macro]
+                    .steps
+                    .swap(idx - 1, idx);
+            }
+            Message::RemoveMacroStep(idx) => {
+                self.soundboard.config.macros[self.selected_macro]
+                    .steps
+                    .remove(idx);
+            }
             Message::SetMicDevice(device) => {
                 self.mic_device = device;
                 // TODO: actually like reconnect the audio loop and shit
@@ -135,24 +202,33 @@ pub fn update(&mut self, message: Message) -> Task<Message> {
                 // TODO: actually like reconnect the audio loop and shit
             }
             Message::SetGuiScale(scale) => {
-                self.config.gui_scale = scale;
+                self.soundboard.config.gui_scale = scale;
+            }
+            Message::SetSoundFilterPreset(preset) => {
+                self.soundboard.set_sound_filter_preset(preset)
             }
+            Message::SetVolumes(volumes) => self.soundboard.set_volumes(volumes),
+            Message::SetSoundRoute(idx, route) => self.soundboard.config.sounds[idx].route = route,
             Message::StartRecordingKeybind(target) => self.recording_keybind = Some(target),
             Message::CancelRecordingKeybind => self.recording_keybind = None,
-            Message::ClearKeybind(target) => {
-                let keybind = match target {
-                    KeybindTarget::SearchAndPlay => &mut self.config.search_and_play_keybind,
-                    KeybindTarget::StopSound => &mut self.config.stop_sound_keybind,
-                };
-                *keybind = None;
+            Message::ClearKeybind(target) => *self.keybind_slot(target) = None,
+            Message::ToggleMidiMapMode => {
+                self.midi_map_mode = !self.midi_map_mode;
+                self.learning_midi = None;
+            }
+            Message::StartMidiLearn(action) => self.learning_midi = Some(action),
+            Message::CancelMidiLearn => self.learning_midi = None,
+            Message::ClearMidiMapping(action) => {
+                self.soundboard
+                    .config
+                    .midi
+                    .mappings
+                    .retain(|mapping| mapping.action != action);
             }
         }
 
-        self.handle_keybinds();
-
-        if self.playing_sound.is_some() && self.decoder_pos.load(Ordering::Relaxed) == u64::MAX {
-            self.playing_sound = None;
-        }
+        self.handle_inputs();
+        self.soundboard.update();
 
         Task::none()
     }
@@ -177,7 +253,8 @@ pub fn subscription(_state: &App) -> Subscription<Message> {
 
     fn get_search_results(&self) -> impl Iterator<Item = (usize, &Sound)> {
         let search = self.search.to_lowercase();
-        self.config
+        self.soundboard
+            .config
             .sounds
             .iter()
             .enumerate()
@@ -188,44 +265,37 @@ fn search_matches(search: &str, sound_name: &str) -> bool {
         sound_name.to_lowercase().contains(search) // TODO: advanced search algorithm, upgrade to fzf at some point
     }
 
-    fn handle_keybinds(&mut self) {
-        #[inline]
-        fn matches_keybind(keybind: Keybind, target_keybind: impl Into<Option<Keybind>>) -> bool {
-            target_keybind.into().is_some_and(|t| t == keybind)
-        }
-
-        let Some(keybind) = self.keybind_listener.try_recv() else {
-            return;
-        };
+    fn handle_inputs(&mut self) {
+        while let Some(input) = self.soundboard.try_recv_input() {
+            if let (Some(target), Input::Keybind(keybind)) = (self.recording_keybind, &input) {
+                *self.keybind_slot(target) = Some(*keybind);
+                self.recording_keybind = None;
+                continue;
+            }
+            if let Input::Midi(event) = &input
+                && let Some(action) = self.learning_midi.take()
+            {
+                let mappings = &mut self.soundboard.config.midi.mappings;
+                mappings.retain(|mapping| mapping.action != action);
+                mappings.push(MidiMapping {
+                    control: event.control,
+                    channel: Some(event.channel),
+                    action,
+                    range: None,
+                });
+                continue;
+            }
 
-        if let Some(target) = self.recording_keybind {
-            let slot = match target {
-                KeybindTarget::SearchAndPlay => &mut self.config.search_and_play_keybind,
-                KeybindTarget::StopSound => &mut self.config.stop_sound_keybind,
-            };
-            *slot = Some(keybind);
-            self.recording_keybind = None;
-            return;
+            self.soundboard.handle_input(input);
         }
+    }
 
-        if matches_keybind(keybind, self.config.stop_sound_keybind) {
-            *self.decoder.lock().unwrap() = None;
-            self.playing_sound = None;
-        } else if matches_keybind(keybind, self.config.search_and_play_keybind) {
-            // noop for now
-        } else {
-            for preset in &self.config.filter_presets {
-                if matches_keybind(keybind, preset.keybind) {
-                    let filters = preset.filters.iter().filter_map(|f| match f.enabled {
-                        true => Some(f.filter_type.clone()),
-                        false => None,
-                    });
-
-                    let mut chain = self.filter_chain.lock().unwrap();
-                    chain.sync(filters);
-                    break;
-                }
-            }
+    fn keybind_slot(&mut self, target: KeybindTarget) -> &mut Option<Keybind> {
+        let config = &mut self.soundboard.config;
+        match target {
+            KeybindTarget::SearchAndPlay => &mut config.search_and_play_keybind,
+            KeybindTarget::StopSound => &mut config.stop_sound_keybind,
+            KeybindTarget::Macro(idx) => &mut config.macros[idx].keybind,
         }
     }
 
@@ -6,11 +6,13 @@
     Length,
     widget::{button, column, container, progress_bar, row, space, svg, text},
 };
-use std::{iter, sync::atomic::Ordering, time::Duration};
+use std::{iter, time::Duration};
 
 mod filter_presets;
 mod filter_properties;
+mod macros;
 mod max_content_column;
+mod midi;
 mod overlay;
 mod settings;
 mod sounds;
@@ -23,13 +25,18 @@ pub fn view(&self) -> Element<'_, Message> {
         let tabs = row([
             self.tab("Sounds", Page::Sounds),
             self.tab("Filter Chain", Page::FilterChain),
+            self.tab("Macros", Page::Macros),
             self.tab("Settings", Page::Settings),
+            container(self.midi_map_mode_button())
+                .align_right(Length::Fill)
+                .into(),
         ])
         .spacing(16);
 
         let page_element = match self.page {
             Page::Sounds => self.sounds_page(),
             Page::FilterChain => self.filter_chain_page(),
+            Page::Macros => self.macros_page(),
             Page::Settings => self.settings_page(),
         };
 
@@ -37,7 +44,12 @@ pub fn view(&self) -> Element<'_, Message> {
             .width(Length::Fill)
             .height(Length::Fill);
 
-        overlay::Overlay::new(base, || self.player_overlay(), self.playing_sound.is_some()).into()
+        overlay::Overlay::new(
+            base,
+            || self.player_overlay(),
+            self.soundboard.playing_sound.is_some(),
+        )
+        .into()
     }
 
     fn tab<'a>(&'a self, page_name: &'a str, page: Page) -> Element<'a> {
@@ -71,13 +83,13 @@ fn tab<'a>(&'a self, page_name: &'a str, page: Page) -> Element<'a> {
     }
 
     fn player_overlay(&self) -> Element<'_> {
-        let Some(playing_sound) = self.playing_sound.as_ref() else {
+        let Some(playing_sound) = self.soundboard.playing_sound.as_ref() else {
             panic!("Overlay shouldn't be created when a sound isn't playing");
         };
 
         let sound_name = &playing_sound.sound.name;
-        let pos = Duration::from_nanos(self.decoder_pos.load(Ordering::Relaxed));
-        let duration = playing_sound.duration;
+        let pos = Duration::from_nanos(self.soundboard.playhead.pos_nanos(playing_sound.id));
+        let duration = playing_sound.duration.unwrap_or_default();
         let time_left_str = Self::format_time_left(duration.saturating_sub(pos));
         let progress = if duration.as_secs_f32() > 0.0 {
             (pos.as_secs_f32() / duration.as_secs_f32()).clamp(0.0, 1.0)
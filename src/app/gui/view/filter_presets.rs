@@ -1,6 +1,6 @@
 use crate::app::{
     App,
-    config::filter::AudioFilter,
+    config::{filter::AudioFilter, midi::MidiAction},
     gui::{
         Message, Theme,
         view::{Element, max_content_column::max_content_column, theme},
@@ -14,11 +14,15 @@
 
 impl App {
     pub(super) fn filter_chain_page(&self) -> Element<'_> {
-        let presets =
-            scrollable(
-                max_content_column(self.config.filter_presets.iter().enumerate().map(
-                    |(i, preset)| {
-                        button(
+        let presets = scrollable(
+            max_content_column(
+                self.soundboard
+                    .config
+                    .filter_presets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, preset)| {
+                        let preset_button = button(
                             column([
                                 text(&preset.name).into(),
                                 text(Self::create_filter_summary(&preset.filters))
@@ -34,36 +38,26 @@ pub(super) fn filter_chain_page(&self) -> Element<'_> {
                         )
                         .width(Length::Fill)
                         .on_press(Message::SelectPreset(i))
-                        .style(move |theme: &Theme, status: button::Status| {
-                            let active = self.selected_preset == i;
-                            button::Style {
-                                text_color: theme.text.into(),
-                                background: match status {
-                                    _ if active => theme.filter_presets.bg_active.into(),
-                                    button::Status::Hovered | button::Status::Pressed => {
-                                        theme.filter_presets.bg_hovered.into()
-                                    }
-                                    _ => theme.filter_presets.bg.into(),
-                                },
-                                border: match status {
-                                    _ if active => theme.filter_presets.border_active.into(),
-                                    button::Status::Hovered | button::Status::Pressed => {
-                                        theme.filter_presets.border_hovered.into()
-                                    }
-                                    _ => theme.filter_presets.border.into(),
-                                },
-                                ..Default::default()
-                            }
-                        })
-                        .into()
-                    },
-                ))
-                .spacing(4),
-            );
+                        .style(theme::button_list_item(self.selected_preset == i));
+
+                        if self.midi_map_mode {
+                            let action = MidiAction::Preset {
+                                preset: preset.name.clone(),
+                            };
+                            column([preset_button.into(), self.midi_learn_button(action)])
+                                .spacing(4)
+                                .into()
+                        } else {
+                            preset_button.into()
+                        }
+                    }),
+            )
+            .spacing(4),
+        );
 
         let filters = scrollable(
             column(
-                self.config.filter_presets[self.selected_preset]
+                self.soundboard.config.filter_presets[self.selected_preset]
                     .filters
                     .iter()
                     .enumerate()
@@ -135,7 +129,7 @@ fn filter_preset<'a>(&'a self, i: usize, filter: &'a AudioFilter) -> Element<'a>
                     .into(),
                 )
                 .chain(match filter.expanded {
-                    true => Some(self.filter_properties(i, &filter.filter_type)),
+                    true => Some(self.filter_properties(i, filter)),
                     false => None,
                 }),
             )
@@ -1,6 +1,9 @@
 use crate::app::{
     App,
-    config::filter::{FilterProperty, FilterType, PropVal},
+    config::{
+        filter::{AudioFilter, FilterProperty, PropVal},
+        midi::MidiAction,
+    },
     gui::{
         Message,
         view::{Element, theme},
@@ -12,8 +15,12 @@
 };
 
 impl App {
-    pub(super) fn filter_properties<'a>(&'a self, i: usize, filter: &'a FilterType) -> Element<'a> {
-        column(filter.properties().iter().map(|prop| {
+    pub(super) fn filter_properties<'a>(
+        &'a self,
+        i: usize,
+        filter: &'a AudioFilter,
+    ) -> Element<'a> {
+        column(filter.filter_type.properties().iter().map(|prop| {
             let ctx = Ctx {
                 filter_idx: i,
                 prop: *prop,
@@ -39,7 +46,17 @@ pub(super) fn filter_properties<'a>(&'a self, i: usize, filter: &'a FilterType)
                     .into(),
             };
 
-            row([name.into(), changer, value_text])
+            let midi = self.midi_map_mode.then(|| {
+                self.midi_learn_button(MidiAction::FilterProperty {
+                    preset: self.soundboard.config.filter_presets[self.selected_preset]
+                        .name
+                        .clone(),
+                    filter: filter.name().to_string(),
+                    property: prop.name().to_string(),
+                })
+            });
+
+            row([name.into(), changer, value_text].into_iter().chain(midi))
                 .align_y(Alignment::Center)
                 .spacing(4)
                 .into()
@@ -0,0 +1,321 @@
+use crate::app::{
+    App,
+    config::macros::{Layer, Macro, MacroStep},
+    gui::{
+        KeybindTarget, Message,
+        view::{Element, max_content_column::max_content_column, theme},
+    },
+};
+use iced::{
+    Alignment, Length,
+    widget::{
+        button, column, container, pick_list, row, scrollable, slider, svg, text, text_input,
+    },
+};
+use std::iter;
+
+impl App {
+    pub(super) fn macros_page(&self) -> Element<'_> {
+        let macros = self
+            .soundboard
+            .config
+            .macros
+            .iter()
+            .enumerate()
+            .map(|(i, sound_macro)| {
+                let keybind = sound_macro
+                    .keybind
+                    .map(|keybind| keybind.to_string())
+                    .unwrap_or_else(|| "No keybind".to_string());
+                button(
+                    column([
+                        text(&sound_macro.name).into(),
+                        text(Self::create_step_summary(&sound_macro.steps))
+                            .style(theme::text_filter_preset_effects)
+                            .size(14)
+                            .into(),
+                        text(keybind)
+                            .style(theme::text_filter_preset_keybind)
+                            .size(14)
+                            .into(),
+                    ])
+                    .spacing(4),
+                )
+                .width(Length::Fill)
+                .on_press(Message::SelectMacro(i))
+                .style(theme::button_list_item(self.selected_macro == i))
+                .into()
+            });
+        let add = button(text("New Macro").size(14))
+            .style(theme::button_setting_value)
+            .on_press(Message::AddMacro)
+            .into();
+        let macros = scrollable(max_content_column(macros.chain(iter::once(add))).spacing(4));
+
+        let editor = match self.soundboard.config.macros.get(self.selected_macro) {
+            Some(sound_macro) => self.macro_editor(self.selected_macro, sound_macro),
+            None => text("Macros play sounds, wait and switch presets with a single keybind")
+                .style(theme::text_setting_name)
+                .into(),
+        };
+
+        row([macros.into(), theme::v_separator(), editor])
+            .spacing(8)
+            .into()
+    }
+
+    fn macro_editor<'a>(&'a self, idx: usize, sound_macro: &'a Macro) -> Element<'a> {
+        let header = row([
+            text_input("Name", &sound_macro.name)
+                .on_input(Message::RenameMacro)
+                .into(),
+            button(text("Run").size(14))
+                .style(theme::button_setting_value)
+                .on_press(Message::RunMacro(idx))
+                .into(),
+            button(text("Delete").size(14))
+                .style(theme::button_setting_value)
+                .on_press(Message::RemoveMacro(idx))
+                .into(),
+        ])
+        .spacing(4)
+        .align_y(Alignment::Center);
+
+        let steps = sound_macro
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| self.macro_step(i, step));
+        let add_step = row(MacroStep::KINDS.map(|kind| {
+            button(text(format!("+ {kind}")).size(14))
+                .style(theme::button_setting_value)
+                .on_press(Message::AddMacroStep(kind))
+                .into()
+        }))
+        .spacing(4);
+
+        scrollable(
+            column(
+                [
+                    header.into(),
+                    self.keybind_row("Keybind", sound_macro.keybind, KeybindTarget::Macro(idx)),
+                ]
+                .into_iter()
+                .chain(steps)
+                .chain(iter::once(add_step.into())),
+            )
+            .spacing(4),
+        )
+        .into()
+    }
+
+    fn macro_step<'a>(&'a self, i: usize, step: &'a MacroStep) -> Element<'a> {
+        let kind = match step {
+            MacroStep::Play { .. } => "Play",
+            MacroStep::Layer { .. } => "Layer",
+            MacroStep::Wait { .. } => "Wait",
+            MacroStep::Preset { .. } => "Preset",
+            MacroStep::Stop => "Stop",
+        };
+        let move_up = (i > 0).then(|| {
+            button(text("↑").size(14))
+                .style(theme::button_setting_value)
+                .on_press(Message::MoveMacroStepUp(i))
+                .into()
+        });
+        let remove = button(
+            svg(self.svgs.x.clone())
+                .width(14)
+                .style(theme::svg_keybind_x),
+        )
+        .padding(0)
+        .on_press(Message::RemoveMacroStep(i))
+        .into();
+        let header = row(iter::once(text(kind).width(Length::Fill).into())
+            .chain(move_up)
+            .chain(iter::once(remove)))
+        .spacing(4)
+        .align_y(Alignment::Center);
+
+        let body: Option<Element<'a>> = match step {
+            MacroStep::Play {
+                sound,
+                volume,
+                playback,
+            } => {
+                let (volume, playback) = (*volume, *playback);
+                let sound_picker = self.sound_pick_list(i, sound, move |sound| MacroStep::Play {
+                    sound,
+                    volume,
+                    playback,
+                });
+                let sound = sound.clone();
+                let volume_slider = slider(0.0..=2.0, volume.unwrap_or(1.0), move |volume| {
+                    Message::ChangeMacroStep(
+                        i,
+                        MacroStep::Play {
+                            sound: sound.clone(),
+                            volume: Some(volume),
+                            playback,
+                        },
+                    )
+                })
+                .step(0.05);
+                let volume_text = volume.map_or("Sound volume".to_string(), |volume| {
+                    format!("{:.0}%", volume * 100.0)
+                });
+                Some(
+                    row([
+                        sound_picker,
+                        volume_slider.into(),
+                        text(volume_text)
+                            .style(theme::text_filter_property_value)
+                            .size(14)
+                            .into(),
+                    ])
+                    .spacing(4)
+                    .align_y(Alignment::Center)
+                    .into(),
+                )
+            }
+            MacroStep::Layer { layers } => Some(self.macro_layers(i, layers)),
+            MacroStep::Wait { ms } => {
+                let ms = *ms;
+                Some(
+                    row([
+                        text_input("Milliseconds", &ms.to_string())
+                            .on_input(move |input| {
+                                let ms = if input.is_empty() {
+                                    0
+                                } else {
+                                    input.parse().unwrap_or(ms)
+                                };
+                                Message::ChangeMacroStep(i, MacroStep::Wait { ms })
+                            })
+                            .width(120)
+                            .into(),
+                        text("ms").style(theme::text_setting_name).into(),
+                    ])
+                    .spacing(4)
+                    .align_y(Alignment::Center)
+                    .into(),
+                )
+            }
+            MacroStep::Preset { preset } => {
+                let presets: Vec<String> = self
+                    .soundboard
+                    .config
+                    .filter_presets
+                    .iter()
+                    .map(|preset| preset.name.clone())
+                    .collect();
+                let selected = (!preset.is_empty()).then(|| preset.clone());
+                Some(
+                    pick_list(presets, selected, move |preset| {
+                        Message::ChangeMacroStep(i, MacroStep::Preset { preset })
+                    })
+                    .placeholder("Filter preset")
+                    .into(),
+                )
+            }
+            MacroStep::Stop => None,
+        };
+
+        container(column(iter::once(header.into()).chain(body)).spacing(4))
+            .padding(8)
+            .style(theme::container_filter_preset)
+            .width(Length::Fill)
+            .into()
+    }
+
+    fn macro_layers<'a>(&'a self, i: usize, layers: &'a [Layer]) -> Element<'a> {
+        let change =
+            move |layers: Vec<Layer>| Message::ChangeMacroStep(i, MacroStep::Layer { layers });
+
+        let rows = layers.iter().enumerate().map(move |(j, layer)| {
+            let with_layer = move |update: &dyn Fn(&mut Layer)| {
+                let mut layers = layers.to_vec();
+                update(&mut layers[j]);
+                layers
+            };
+            let without_layer = {
+                let mut layers = layers.to_vec();
+                layers.remove(j);
+                layers
+            };
+
+            row([
+                self.sound_pick_list(i, &layer.sound, move |sound| MacroStep::Layer {
+                    layers: with_layer(&|layer| layer.sound = sound.clone()),
+                }),
+                text("after").style(theme::text_setting_name).into(),
+                text_input("0", &layer.offset_ms.to_string())
+                    .on_input(move |input| {
+                        let offset_ms = if input.is_empty() {
+                            Some(0)
+                        } else {
+                            input.parse().ok()
+                        };
+                        change(with_layer(&|layer| {
+                            if let Some(offset_ms) = offset_ms {
+                                layer.offset_ms = offset_ms;
+                            }
+                        }))
+                    })
+                    .width(80)
+                    .into(),
+                text("ms").style(theme::text_setting_name).into(),
+                button(
+                    svg(self.svgs.x.clone())
+                        .width(14)
+                        .style(theme::svg_keybind_x),
+                )
+                .padding(0)
+                .on_press(change(without_layer))
+                .into(),
+            ])
+            .spacing(4)
+            .align_y(Alignment::Center)
+            .into()
+        });
+
+        let mut added = layers.to_vec();
+        added.push(Layer::new(String::new()));
+        let add = button(text("+ Sound").size(14))
+            .style(theme::button_setting_value)
+            .on_press(change(added))
+            .into();
+
+        column(rows.chain(iter::once(add))).spacing(4).into()
+    }
+
+    fn create_step_summary(steps: &[MacroStep]) -> String {
+        if steps.is_empty() {
+            return "(none)".to_string();
+        }
+        let lines: Vec<String> = steps.iter().map(|step| format!("→ {step}")).collect();
+        lines.join("\n")
+    }
+
+    /// Picks one of the sounds, `to_step` turns the picked name into the changed step `i`.
+    fn sound_pick_list<'a>(
+        &'a self,
+        i: usize,
+        sound: &str,
+        to_step: impl Fn(String) -> MacroStep + 'a,
+    ) -> Element<'a> {
+        let sounds: Vec<String> = self
+            .soundboard
+            .config
+            .sounds
+            .iter()
+            .map(|sound| sound.name.clone())
+            .collect();
+        let selected = (!sound.is_empty()).then(|| sound.to_string());
+        pick_list(sounds, selected, move |sound| {
+            Message::ChangeMacroStep(i, to_step(sound))
+        })
+        .placeholder("Sound")
+        .into()
+    }
+}
@@ -0,0 +1,75 @@
+use crate::app::{
+    App,
+    config::midi::MidiAction,
+    gui::{
+        Message,
+        view::{Element, theme},
+    },
+};
+use iced::{
+    Alignment,
+    widget::{button, row, svg, text},
+};
+use std::iter;
+
+impl App {
+    /// Shows what `action` is mapped to, starts MIDI learn for it when pressed.
+    pub(super) fn midi_learn_button(&self, action: MidiAction) -> Element<'_> {
+        let learning = self.learning_midi.as_ref() == Some(&action);
+        let mapping = self
+            .soundboard
+            .config
+            .midi
+            .mappings
+            .iter()
+            .find(|mapping| mapping.action == action);
+
+        let label = match (learning, mapping) {
+            (true, _) => "Move a control...".to_string(),
+            (false, Some(mapping)) => mapping.to_string(),
+            (false, None) => "None".to_string(),
+        };
+
+        let clear = match mapping {
+            Some(_) if !learning => Some(
+                button(
+                    svg(self.svgs.x.clone())
+                        .width(14)
+                        .style(theme::svg_keybind_x),
+                )
+                .padding(0)
+                .on_press(Message::ClearMidiMapping(action.clone()))
+                .into(),
+            ),
+            _ => None,
+        };
+
+        button(
+            row(iter::once(text(label).size(14).into()).chain(clear))
+                .spacing(4)
+                .align_y(Alignment::Center),
+        )
+        .style(if learning {
+            theme::button_setting_recording
+        } else {
+            theme::button_setting_value
+        })
+        .on_press(if learning {
+            Message::CancelMidiLearn
+        } else {
+            Message::StartMidiLearn(action)
+        })
+        .into()
+    }
+
+    pub(super) fn midi_map_mode_button(&self) -> Element<'_> {
+        button(text("MIDI Learn").size(14))
+            .style(if self.midi_map_mode {
+                theme::button_setting_recording
+            } else {
+                theme::button_setting_value
+            })
+            .on_press(Message::ToggleMidiMapMode)
+            .into()
+    }
+}
@@ -1,6 +1,9 @@
 use crate::app::{
-    App, DeviceOption,
-    config::Keybind,
+    App, DeviceOption, Route,
+    config::{
+        Keybind, Volumes,
+        midi::{MidiAction, VolumeKind},
+    },
     gui::{
         KeybindTarget, Message,
         view::{Element, theme},
@@ -8,9 +11,13 @@
 };
 use iced::{
     Alignment, Length,
-    widget::{button, column, container, pick_list, row, svg, text},
+    widget::{button, column, container, pick_list, row, slider, svg, text},
+};
+use std::{
+    iter,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
-use std::iter;
 
 impl App {
     pub(super) fn settings_page(&self) -> Element<'_> {
@@ -34,22 +41,211 @@ pub(super) fn settings_page(&self) -> Element<'_> {
                 Message::SetVirtualOutDevice,
             ),
             self.gui_scale_pick_list(),
+            self.sound_filter_preset_pick_list(),
             self.keybind_row(
                 "Search and Play Keybind",
-                self.config.search_and_play_keybind,
+                self.soundboard.config.search_and_play_keybind,
                 KeybindTarget::SearchAndPlay,
             ),
             self.keybind_row(
                 "Stop Sound Keybind",
-                self.config.stop_sound_keybind,
+                self.soundboard.config.stop_sound_keybind,
                 KeybindTarget::StopSound,
             ),
+            theme::h_separator(),
+            self.routing_panel(),
+            theme::h_separator(),
+            self.midi_panel(),
+            theme::h_separator(),
+            self.web_remote_panel(),
+            theme::h_separator(),
+            self.diagnostics_panel(),
+        ])
+        .spacing(4)
+        .into()
+    }
+
+    /// How loud the mic and the sounds are on each output, and which of them every sound plays on.
+    fn routing_panel(&self) -> Element<'_> {
+        let volumes = self.soundboard.config.volumes;
+        let cell = |volume: f32, set: fn(&mut Volumes, f32)| -> Element<'_> {
+            row([
+                slider(0.0..=2.0, volume, move |volume| {
+                    let mut volumes = volumes;
+                    set(&mut volumes, volume);
+                    Message::SetVolumes(volumes)
+                })
+                .step(0.05)
+                .width(100)
+                .into(),
+                text(format!("{:.0}%", volume * 100.0))
+                    .style(theme::text_setting_value)
+                    .width(48)
+                    .into(),
+            ])
+            .spacing(4)
+            .align_y(Alignment::Center)
+            .into()
+        };
+        let header = |name| {
+            text(name)
+                .style(theme::text_setting_name)
+                .size(14)
+                .width(152)
+                .into()
+        };
+        let volume_row = |name, virtual_out, local| {
+            row([
+                Self::setting_name(name),
+                container(row([virtual_out, local]).spacing(8))
+                    .align_right(Length::Fill)
+                    .into(),
+            ])
+            .into()
+        };
+
+        column([
+            volume_row("Routing", header("Virtual Output"), header("Local Output")),
+            volume_row(
+                "Mic",
+                cell(volumes.mic, |volumes, volume| volumes.mic = volume),
+                cell(volumes.mic_monitor, |volumes, volume| {
+                    volumes.mic_monitor = volume
+                }),
+            ),
+            volume_row(
+                "Sounds",
+                cell(volumes.sounds, |volumes, volume| volumes.sounds = volume),
+                cell(volumes.monitor, |volumes, volume| volumes.monitor = volume),
+            ),
+        ])
+        .push(
+            text("The outputs each sound plays on")
+                .style(theme::text_setting_name)
+                .size(14),
+        )
+        .extend(
+            self.soundboard
+                .config
+                .sounds
+                .iter()
+                .enumerate()
+                .map(|(idx, sound)| {
+                    row([
+                        Self::setting_name(&sound.name),
+                        container(pick_list(Route::ALL, Some(sound.route), move |route| {
+                            Message::SetSoundRoute(idx, route)
+                        }))
+                        .align_right(Length::Fill)
+                        .into(),
+                    ])
+                    .into()
+                }),
+        )
+        .spacing(4)
+        .into()
+    }
+
+    fn midi_panel(&self) -> Element<'_> {
+        let row = |name, action| {
+            row([
+                Self::setting_name(name),
+                container(self.midi_learn_button(action))
+                    .align_right(Length::Fill)
+                    .into(),
+            ])
+            .into()
+        };
+        let volume = |volume| MidiAction::Volume { volume };
+
+        column([
+            text("MIDI").into(),
+            row("Stop Sound", MidiAction::Stop),
+            row("Toggle Pause", MidiAction::TogglePause),
+            row("Mic Volume", volume(VolumeKind::Mic)),
+            row("Sounds Volume", volume(VolumeKind::Sounds)),
+            row("Monitor Volume", volume(VolumeKind::Monitor)),
+            row("Mic Monitor Volume", volume(VolumeKind::MicMonitor)),
+            text("Sounds, presets and filter properties can be mapped with MIDI Learn")
+                .style(theme::text_setting_name)
+                .size(14)
+                .into(),
+        ])
+        .spacing(4)
+        .into()
+    }
+
+    fn web_remote_panel(&self) -> Element<'_> {
+        let Some(web_remote) = self.soundboard.web_remote() else {
+            return Self::diagnostics_row("Web Remote", "Off".to_string());
+        };
+
+        column([
+            Self::diagnostics_row("Web Remote", format!("http://{}", web_remote.address())),
+            Self::diagnostics_row("Pairing Code", web_remote.pairing_code()),
         ])
         .spacing(4)
         .into()
     }
 
-    fn keybind_row<'a>(
+    fn diagnostics_panel(&self) -> Element<'_> {
+        let diagnostics = &self.soundboard.diagnostics;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+        let millis = |latency: Duration| format!("{:.1} ms", latency.as_secs_f64() * 1000.0);
+        let block_frames = self.soundboard.config.latency.block_frames();
+
+        column([
+            text("Diagnostics").into(),
+            Self::diagnostics_row(
+                "Block Size",
+                format!(
+                    "{block_frames} frames ({})",
+                    millis(Duration::from_secs_f64(
+                        block_frames as f64 / self.soundboard.target_sample_rate as f64
+                    ))
+                ),
+            ),
+            Self::diagnostics_row(
+                "Mic Latency",
+                millis(diagnostics.mic_latency(self.soundboard.target_sample_rate)),
+            ),
+            Self::diagnostics_row(
+                "Sound Latency",
+                millis(diagnostics.sound_latency(self.soundboard.target_sample_rate)),
+            ),
+            Self::diagnostics_row("Xruns", diagnostics.xruns().to_string()),
+            Self::diagnostics_row("Mic Underruns", load(&diagnostics.mic_underruns)),
+            Self::diagnostics_row("Mic Overruns", load(&diagnostics.mic_overruns)),
+            Self::diagnostics_row(
+                "Mic Buffer",
+                format!(
+                    "{} frames",
+                    diagnostics.mic_buffered_frames.load(Ordering::Relaxed)
+                ),
+            ),
+            Self::diagnostics_row(
+                "Mic Drift Correction",
+                format!(
+                    "{:+} ppm",
+                    diagnostics.mic_drift_ppm.load(Ordering::Relaxed)
+                ),
+            ),
+        ])
+        .spacing(4)
+        .into()
+    }
+
+    fn diagnostics_row(name: &str, value: String) -> Element<'_> {
+        row([
+            Self::setting_name(name),
+            container(text(value).style(theme::text_setting_value))
+                .align_right(Length::Fill)
+                .into(),
+        ])
+        .into()
+    }
+
+    pub(super) fn keybind_row<'a>(
         &self,
         name: &'a str,
         keybind: Option<Keybind>,
@@ -118,7 +314,7 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             Self::setting_name("GUI Scale"),
             container(pick_list(
                 scales,
-                Some(ScaleWrapper(self.config.gui_scale)),
+                Some(ScaleWrapper(self.soundboard.config.gui_scale)),
                 |wrapper| Message::SetGuiScale(wrapper.0),
             ))
             .align_right(Length::Fill)
@@ -127,6 +323,41 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         .into()
     }
 
+    fn sound_filter_preset_pick_list(&self) -> Element<'_> {
+        #[derive(Clone, PartialEq)]
+        struct PresetWrapper(Option<String>);
+
+        impl std::fmt::Display for PresetWrapper {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.0.as_deref().unwrap_or("None"))
+            }
+        }
+
+        let presets: Vec<PresetWrapper> = iter::once(PresetWrapper(None))
+            .chain(
+                self.soundboard
+                    .config
+                    .filter_presets
+                    .iter()
+                    .map(|preset| PresetWrapper(Some(preset.name.clone()))),
+            )
+            .collect();
+
+        row([
+            Self::setting_name("Sound Filter Preset"),
+            container(pick_list(
+                presets,
+                Some(PresetWrapper(
+                    self.soundboard.config.sound_filter_preset.clone(),
+                )),
+                |wrapper| Message::SetSoundFilterPreset(wrapper.0),
+            ))
+            .align_right(Length::Fill)
+            .into(),
+        ])
+        .into()
+    }
+
     fn device_pick_list<'a>(
         &'a self,
         name: &'a str,
@@ -1,11 +1,16 @@
 use crate::app::{
     App,
+    config::midi::MidiAction,
     gui::{
         Message,
         view::{Element, theme},
     },
 };
-use iced::widget::{Column, Row, button, column, scrollable, text, text_input};
+use iced::{
+    Length,
+    widget::{Column, Row, button, column, progress_bar, scrollable, text, text_input},
+};
+use std::time::Instant;
 
 impl App {
     pub(super) fn sounds_page(&self) -> Element<'_> {
@@ -17,15 +22,37 @@ pub(super) fn sounds_page(&self) -> Element<'_> {
             let mut content = Column::new().spacing(8);
             let mut current_row = Row::new().spacing(8);
             let mut count = 0;
+            let now = Instant::now();
 
             for (i, sound) in self.get_search_results() {
-                let btn = button(text(sound.name.as_str()).size(14))
+                let name = text(sound.name.as_str()).size(14);
+                // cooling down or over its rate limit
+                let label: Element<'_> = match self.soundboard.limits.progress(sound, now) {
+                    Some(progress) => column([
+                        name.height(Length::Fill).into(),
+                        progress_bar(0.0..=1.0, progress).girth(4).into(),
+                    ])
+                    .into(),
+                    None => name.into(),
+                };
+                let btn = button(label)
                     .width(128)
                     .height(128)
                     .on_press(Message::PlaySound(i))
                     .style(theme::button_sound);
 
-                current_row = current_row.push(btn);
+                if self.midi_map_mode {
+                    let action = MidiAction::Play {
+                        sound: sound.name.clone(),
+                    };
+                    current_row = current_row.push(
+                        column([btn.into(), self.midi_learn_button(action)])
+                            .width(128)
+                            .spacing(4),
+                    );
+                } else {
+                    current_row = current_row.push(btn);
+                }
                 count += 1;
 
                 if count % 3 == 0 {
@@ -210,7 +210,6 @@ pub fn v_separator<'a>() -> Element<'a> {
         .into()
 }
 
-#[expect(unused)] // remove if this is used at some point
 pub fn h_separator<'a>() -> Element<'a> {
     container(space::horizontal())
         .height(1)
@@ -367,6 +366,10 @@ pub fn text_setting_name(theme: &Theme) -> text::Style {
     text_color(theme.settings.names)
 }
 
+pub fn text_setting_value(theme: &Theme) -> text::Style {
+    text_color(theme.settings.values)
+}
+
 pub fn text_filter_property_name(theme: &Theme) -> text::Style {
     text_color(theme.filter_presets.property_names)
 }
@@ -472,6 +475,28 @@ pub fn button_setting_recording(theme: &Theme, _status: button::Status) -> butto
     }
 }
 
+/// An entry of a list like the filter presets, `active` when it's the selected one.
+pub fn button_list_item(active: bool) -> impl Fn(&Theme, button::Status) -> button::Style {
+    move |theme, status| button::Style {
+        text_color: theme.text.into(),
+        background: match status {
+            _ if active => theme.filter_presets.bg_active.into(),
+            button::Status::Hovered | button::Status::Pressed => {
+                theme.filter_presets.bg_hovered.into()
+            }
+            _ => theme.filter_presets.bg.into(),
+        },
+        border: match status {
+            _ if active => theme.filter_presets.border_active.into(),
+            button::Status::Hovered | button::Status::Pressed => {
+                theme.filter_presets.border_hovered.into()
+            }
+            _ => theme.filter_presets.border.into(),
+        },
+        ..Default::default()
+    }
+}
+
 pub fn button_sound(theme: &Theme, status: button::Status) -> button::Style {
     button::Style {
         text_color: theme.text.into(),
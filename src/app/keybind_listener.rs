@@ -1,47 +1,34 @@
-use crate::app::config::Keybind;
+use crate::app::{config::Keybind, control::Input};
 use rdev::{EventType, Key};
-use std::{
-    sync::mpsc::{self, Receiver, Sender},
-    thread,
-};
+use std::{collections::HashSet, sync::mpsc::Sender, thread};
 
-pub(super) struct KeybindListener {
-    rx: Receiver<Keybind>,
-}
-
-impl KeybindListener {
-    pub(super) fn new() -> Self {
-        let (tx, rx) = mpsc::channel();
-
-        let mut listener = InternalListener::new(tx);
-        thread::spawn(|| {
-            if let Err(err) = rdev::listen(move |event| listener.new_event(event)) {
-                tracing::error!(?err, "Global keybind listener error");
-            }
-        });
-
-        Self { rx }
-    }
-
-    pub fn try_recv(&self) -> Option<Keybind> {
-        self.rx.try_recv().ok()
-    }
+/// Sends every global keybind pressed from now on to `tx`, and the key of it once released.
+pub(super) fn listen(tx: Sender<Input>) {
+    let mut listener = InternalListener::new(tx);
+    thread::spawn(|| {
+        if let Err(err) = rdev::listen(move |event| listener.new_event(event)) {
+            tracing::error!(?err, "Global keybind listener error");
+        }
+    });
 }
 
 struct InternalListener {
-    tx: Sender<Keybind>,
+    tx: Sender<Input>,
     ctrl: bool,
     alt: bool,
     shift: bool,
+    /// Keys held down, so the repeated presses of a held key are only sent once.
+    held: HashSet<Key>,
 }
 
 impl InternalListener {
-    fn new(tx: Sender<Keybind>) -> Self {
+    fn new(tx: Sender<Input>) -> Self {
         Self {
             tx,
             ctrl: false,
             alt: false,
             shift: false,
+            held: HashSet::new(),
         }
     }
 
@@ -54,21 +41,29 @@ fn new_event(&mut self, event: rdev::Event) {
                 Key::Alt => self.alt = pressed,
                 Key::ShiftLeft | Key::ShiftRight => self.shift = pressed,
                 key if pressed => {
-                    let result = self.tx.send(Keybind {
-                        ctrl: self.ctrl,
-                        alt: self.alt,
-                        shift: self.shift,
-                        key,
-                    });
-
-                    if result.is_err() {
-                        panic!("Expected panic: Shutting down InternalListener");
+                    if self.held.insert(key) {
+                        self.send(Input::Keybind(Keybind {
+                            ctrl: self.ctrl,
+                            alt: self.alt,
+                            shift: self.shift,
+                            key,
+                        }));
+                    }
+                }
+                key => {
+                    if self.held.remove(&key) {
+                        self.send(Input::KeyReleased(key));
                     }
                 }
-                _ => {}
             }
         }
     }
+
+    fn send(&self, input: Input) {
+        if self.tx.send(input).is_err() {
+            panic!("Expected panic: Shutting down InternalListener");
+        }
+    }
 }
 
 pub fn write_key_str(key: Key, f: &mut impl std::fmt::Write) -> std::fmt::Result {
@@ -280,3 +275,41 @@ pub fn parse_key(str: &str) -> Result<Key, String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, time::SystemTime};
+
+    #[test]
+    fn held_keys_press_once_and_release() {
+        let (tx, rx) = mpsc::channel();
+        let mut listener = InternalListener::new(tx);
+        let mut event = |event_type| {
+            listener.new_event(rdev::Event {
+                time: SystemTime::now(),
+                name: None,
+                event_type,
+            })
+        };
+
+        event(EventType::KeyPress(Key::ControlLeft));
+        // the repeats of the held key
+        for _ in 0..3 {
+            event(EventType::KeyPress(Key::KeyH));
+        }
+        event(EventType::KeyRelease(Key::ControlLeft));
+        event(EventType::KeyRelease(Key::KeyH));
+        event(EventType::KeyRelease(Key::KeyJ));
+
+        let inputs: Vec<String> = rx
+            .try_iter()
+            .map(|input| match input {
+                Input::Keybind(keybind) => keybind.to_string(),
+                Input::KeyReleased(key) => format!("released {key:?}"),
+                _ => panic!("expected keybinds"),
+            })
+            .collect();
+        assert_eq!(inputs, ["Ctrl+H", "released KeyH"]);
+    }
+}
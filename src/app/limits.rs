@@ -0,0 +1,143 @@
+use crate::app::Sound;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// What `max_per_minute` counts the triggers in.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Keeps sounds from being triggered again too soon or too often.
+#[derive(Default)]
+pub struct Limits {
+    /// The latest triggers by sound name, as many as the limits of the sound look at.
+    triggers: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Limits {
+    /// Whether `sound` can be triggered at `now`, or its cooldown or rate limit holds it back.
+    pub fn allows(&self, sound: &Sound, now: Instant) -> bool {
+        sound.max_per_minute != Some(0) && self.wait(sound, now).is_none()
+    }
+
+    /// Records a trigger of `sound` at `now`, once it is actually playing.
+    pub fn record(&mut self, sound: &Sound, now: Instant) {
+        if sound.cooldown <= 0.0 && sound.max_per_minute.is_none() {
+            return;
+        }
+
+        let triggers = self.triggers.entry(sound.name.clone()).or_default();
+        triggers.push_back(now);
+        let kept = sound.max_per_minute.unwrap_or(1) as usize;
+        while triggers.len() > kept {
+            triggers.pop_front();
+        }
+    }
+
+    /// How far `sound` is through the wait until it can be triggered again, from `0.0` right after
+    /// the trigger to `1.0`. `None` if it can be triggered now.
+    pub fn progress(&self, sound: &Sound, now: Instant) -> Option<f32> {
+        self.wait(sound, now)
+            .map(|(left, total)| 1.0 - left.as_secs_f32() / total.as_secs_f32())
+    }
+
+    /// How long until `sound` can be triggered again, and how long the whole wait is. A sound with
+    /// a `max_per_minute` of 0 never plays, so there is nothing to wait for.
+    fn wait(&self, sound: &Sound, now: Instant) -> Option<(Duration, Duration)> {
+        let triggers = self.triggers.get(&sound.name)?;
+
+        let cooldown = Duration::try_from_secs_f32(sound.cooldown).unwrap_or_default();
+        let cooldown_wait = triggers
+            .back()
+            .map(|last| (*last + cooldown).saturating_duration_since(now))
+            .filter(|left| !left.is_zero())
+            .map(|left| (left, cooldown));
+
+        let rate_wait = sound.max_per_minute.filter(|max| *max > 0).and_then(|max| {
+            let recent: Vec<&Instant> = triggers
+                .iter()
+                .filter(|trigger| now.duration_since(**trigger) < RATE_WINDOW)
+                .collect();
+            // the trigger that frees a slot once it leaves the window
+            let oldest = recent.len().checked_sub(max as usize).map(|i| recent[i])?;
+            Some(((*oldest + RATE_WINDOW) - now, RATE_WINDOW))
+        });
+
+        match (cooldown_wait, rate_wait) {
+            (Some(cooldown), Some(rate)) => Some(cooldown.max(rate)),
+            (wait, None) | (None, wait) => wait,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(limits: &mut Limits, sound: &Sound, now: Instant) -> bool {
+        let allowed = limits.allows(sound, now);
+        if allowed {
+            limits.record(sound, now);
+        }
+        allowed
+    }
+
+    #[test]
+    fn holds_back_until_the_limits_pass() {
+        let mut sound = Sound::new("Airhorn".to_string(), "airhorn.ogg".to_string());
+        sound.cooldown = 2.0;
+        sound.max_per_minute = Some(3);
+        let mut limits = Limits::default();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert!(trigger(&mut limits, &sound, at(0)));
+        assert!(!trigger(&mut limits, &sound, at(1)));
+        assert_eq!(limits.progress(&sound, at(1)), Some(0.5));
+        assert!(trigger(&mut limits, &sound, at(2)));
+        assert!(trigger(&mut limits, &sound, at(4)));
+
+        // three in the last minute, the first one leaves it at 60s
+        assert!(!trigger(&mut limits, &sound, at(30)));
+        assert_eq!(limits.progress(&sound, at(30)), Some(0.5));
+        assert!(trigger(&mut limits, &sound, at(60)));
+        assert_eq!(limits.progress(&sound, at(63)), None);
+    }
+
+    #[test]
+    fn zero_per_minute_never_plays() {
+        let mut sound = Sound::new("Airhorn".to_string(), "airhorn.ogg".to_string());
+        sound.max_per_minute = Some(0);
+        let mut limits = Limits::default();
+        let now = Instant::now();
+
+        assert!(!trigger(&mut limits, &sound, now));
+        assert!(!trigger(&mut limits, &sound, now + RATE_WINDOW));
+        assert_eq!(limits.progress(&sound, now), None);
+    }
+
+    #[test]
+    fn unlimited_sounds_are_not_tracked() {
+        let sound = Sound::new("Bonk".to_string(), "bonk.ogg".to_string());
+        let mut limits = Limits::default();
+        let now = Instant::now();
+
+        assert!(trigger(&mut limits, &sound, now));
+        assert!(trigger(&mut limits, &sound, now));
+        assert!(limits.triggers.is_empty());
+    }
+
+    #[test]
+    fn only_recorded_triggers_count() {
+        let mut sound = Sound::new("Airhorn".to_string(), "airhorn.ogg".to_string());
+        sound.cooldown = 2.0;
+        let mut limits = Limits::default();
+        let now = Instant::now();
+
+        // a sound that failed to open is checked but never recorded
+        assert!(limits.allows(&sound, now));
+        assert!(limits.allows(&sound, now));
+        limits.record(&sound, now);
+        assert!(!limits.allows(&sound, now));
+    }
+}
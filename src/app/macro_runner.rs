@@ -0,0 +1,107 @@
+use crate::app::{
+    config::macros::{Layer, MacroStep},
+    control::{self, Command, Input},
+};
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+/// A macro running on a thread of its own. Dropping it stops the macro before its next step.
+pub struct MacroRun {
+    _cancel: Sender<()>,
+}
+
+/// Runs `steps` one after another through the soundboard's inputs, like any other frontend.
+pub(super) fn start(name: String, steps: Vec<MacroStep>, inputs: Sender<Input>) -> MacroRun {
+    let (cancel, cancelled) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(err) = run(&steps, &inputs, &cancelled) {
+            tracing::warn!(name, %err, "Macro failed");
+        }
+    });
+    MacroRun { _cancel: cancel }
+}
+
+fn run(
+    steps: &[MacroStep],
+    inputs: &Sender<Input>,
+    cancelled: &Receiver<()>,
+) -> Result<(), String> {
+    for step in steps {
+        if let Err(TryRecvError::Disconnected) = cancelled.try_recv() {
+            return Ok(());
+        }
+
+        let command = match step {
+            MacroStep::Play {
+                sound,
+                volume,
+                playback,
+            } => Command::PlayLayers(vec![Layer {
+                sound: sound.clone(),
+                offset_ms: 0,
+                volume: *volume,
+                playback: *playback,
+            }]),
+            MacroStep::Layer { layers } => Command::PlayLayers(layers.clone()),
+            MacroStep::Wait { ms } => match cancelled.recv_timeout(Duration::from_millis(*ms)) {
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                _ => continue,
+            },
+            MacroStep::Preset { preset } => Command::ActivatePreset(preset.clone()),
+            MacroStep::Stop => Command::Stop,
+        };
+        control::execute(inputs, command)??;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{Playback, control::Reply};
+    use std::time::Instant;
+
+    #[test]
+    fn runs_steps_in_order_until_dropped() {
+        let (inputs, received) = mpsc::channel();
+        let steps = vec![
+            MacroStep::Play {
+                sound: "Drums".to_string(),
+                volume: Some(0.5),
+                playback: Playback {
+                    reverse: Some(true),
+                    ..Playback::default()
+                },
+            },
+            MacroStep::Wait { ms: 50 },
+            MacroStep::Preset {
+                preset: "Church".to_string(),
+            },
+            MacroStep::Wait { ms: 10_000 },
+            MacroStep::Stop,
+        ];
+        let started = Instant::now();
+        let run = start("Drumroll".to_string(), steps, inputs);
+
+        let next = || {
+            let Ok(Input::Command(command, Some(reply))) = received.recv() else {
+                panic!("expected a command");
+            };
+            reply.send(Ok(Reply::Done)).unwrap();
+            format!("{command:?}")
+        };
+        assert_eq!(
+            next(),
+            r#"PlayLayers([Layer { sound: "Drums", offset_ms: 0, volume: Some(0.5), playback: Playback { speed: None, pitch: None, reverse: Some(true) } }])"#
+        );
+        assert_eq!(next(), r#"ActivatePreset("Church")"#);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // the stop after the long wait never comes
+        drop(run);
+        assert!(received.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::app::{
+    config::midi::{Midi, MidiControl},
+    control::Input,
+};
+use std::sync::mpsc::Sender;
+
+/// A note being pressed or a control changing on a MIDI controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MidiEvent {
+    /// 1 to 16.
+    pub channel: u8,
+    pub control: MidiControl,
+    /// The velocity of notes, the value of controls, 0 to 127.
+    pub value: u8,
+}
+
+/// Sends the notes and control changes of every MIDI controller from now on to `tx`, including
+/// the ones plugged in later.
+#[cfg(target_os = "linux")]
+pub(super) fn listen(config: &Midi, tx: Sender<Input>) {
+    let port_filter = config.port.clone();
+    std::thread::spawn(move || {
+        if let Err(err) = alsa_seq::listen(port_filter.as_deref(), &tx) {
+            tracing::warn!(%err, "MIDI input is unavailable");
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn listen(_config: &Midi, _tx: Sender<Input>) {
+    tracing::warn!("MIDI input is only supported on Linux for now");
+}
+
+/// MIDI input through the ALSA sequencer, which also sees the ports of other programs.
+#[cfg(target_os = "linux")]
+mod alsa_seq {
+    use super::MidiEvent;
+    use crate::app::{config::midi::MidiControl, control::Input};
+    use alsa::seq::{
+        Addr, ClientIter, EvCtrl, EvNote, EventType, PortCap, PortInfo, PortIter, PortSubscribe,
+        PortType, Seq,
+    };
+    use std::sync::mpsc::Sender;
+
+    pub(super) fn listen(port_filter: Option<&str>, tx: &Sender<Input>) -> alsa::Result<()> {
+        let seq = Seq::open(None, Some(alsa::Direction::Capture), false)?;
+        seq.set_client_name(c"keysounds")?;
+        let port = seq.create_simple_port(
+            c"MIDI In",
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION,
+        )?;
+        let own = Addr {
+            client: seq.client_id()?,
+            port,
+        };
+
+        // announces ports as they appear
+        connect(&seq, Addr::system_announce(), own)?;
+        for client in ClientIter::new(&seq) {
+            for port in PortIter::new(&seq, client.get_client()) {
+                connect_controller(&seq, &port, own, port_filter);
+            }
+        }
+
+        let mut input = seq.input();
+        loop {
+            let event = input.event_input()?;
+            let midi_event = match event.get_type() {
+                EventType::PortStart => {
+                    if let Some(addr) = event.get_data::<Addr>()
+                        && let Ok(port) = seq.get_any_port_info(addr)
+                    {
+                        connect_controller(&seq, &port, own, port_filter);
+                    }
+                    continue;
+                }
+                EventType::Noteon => {
+                    let Some(EvNote {
+                        channel,
+                        note,
+                        velocity,
+                        ..
+                    }) = event.get_data()
+                    else {
+                        continue;
+                    };
+                    if velocity == 0 {
+                        continue;
+                    }
+                    MidiEvent {
+                        channel: channel + 1,
+                        control: MidiControl::Note(note),
+                        value: velocity,
+                    }
+                }
+                EventType::Controller => {
+                    let Some(EvCtrl {
+                        channel,
+                        param,
+                        value,
+                    }) = event.get_data()
+                    else {
+                        continue;
+                    };
+                    MidiEvent {
+                        channel: channel + 1,
+                        control: MidiControl::Cc(param as u8),
+                        value: value.clamp(0, 127) as u8,
+                    }
+                }
+                _ => continue,
+            };
+
+            if tx.send(Input::Midi(midi_event)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Connects `port` to `own` if it's a MIDI source of another program or a device.
+    fn connect_controller(seq: &Seq, port: &PortInfo, own: Addr, port_filter: Option<&str>) {
+        let addr = port.addr();
+        let caps = port.get_capability();
+        let Ok(name) = port.get_name() else {
+            return;
+        };
+        if addr.client == own.client
+            || addr.client == Addr::system_announce().client
+            || !caps.contains(PortCap::READ | PortCap::SUBS_READ)
+            || caps.contains(PortCap::NO_EXPORT)
+            || port_filter.is_some_and(|filter| !name.contains(filter))
+        {
+            return;
+        }
+
+        match connect(seq, addr, own) {
+            Ok(()) => tracing::info!(port = name, "Connected MIDI port"),
+            Err(err) => tracing::warn!(port = name, %err, "Couldn't connect MIDI port"),
+        }
+    }
+
+    fn connect(seq: &Seq, sender: Addr, dest: Addr) -> alsa::Result<()> {
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(sender);
+        subscription.set_dest(dest);
+        seq.subscribe_port(&subscription)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// Plays a controller on a virtual sequencer port.
+    #[test]
+    #[ignore = "needs the ALSA sequencer"]
+    fn hears_a_virtual_port() {
+        use alsa::seq::{EvCtrl, Event, EventType, PortCap, PortType, Seq};
+        use std::{sync::mpsc, time::Duration};
+
+        let (tx, rx) = mpsc::channel();
+        listen(&Midi::default(), tx);
+        // the listener connects to ports that appear after it started
+        std::thread::sleep(Duration::from_millis(200));
+
+        let seq = Seq::open(None, Some(alsa::Direction::Playback), false).unwrap();
+        let port = seq
+            .create_simple_port(
+                c"Virtual Controller",
+                PortCap::READ | PortCap::SUBS_READ,
+                PortType::MIDI_GENERIC | PortType::APPLICATION,
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let control = EvCtrl {
+            channel: 0,
+            param: 21,
+            value: 64,
+        };
+        let mut event = Event::new(EventType::Controller, &control);
+        event.set_source(port);
+        event.set_subs();
+        event.set_direct();
+        seq.event_output_direct(&mut event).unwrap();
+
+        let Input::Midi(event) = rx.recv_timeout(Duration::from_secs(2)).unwrap() else {
+            panic!("expected a MIDI event");
+        };
+        assert_eq!(
+            event,
+            MidiEvent {
+                channel: 1,
+                control: MidiControl::Cc(21),
+                value: 64
+            }
+        );
+    }
+}
@@ -0,0 +1,559 @@
+//! Rhai scripts for custom actions and for reacting to what the soundboard does.
+//!
+//! Every `.rhai` file in [`App::scripts_dir`](crate::app::App::scripts_dir) is a script named
+//! after the file. Running a script runs its top level code, functions named after a [`Hook`] run
+//! when that happens. Scripts are compiled again whenever the files change, which also stops the
+//! runs still going.
+
+use crate::app::{
+    audio::MixControl,
+    config,
+    control::{self, Command, Input, SoundRef},
+};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rhai::{AST, Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Scope};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often the mic level is checked for `on_mic_level`.
+const MIC_INTERVAL: Duration = Duration::from_millis(50);
+/// Operations one run or hook call may take before it is stopped, so `loop {}` ends.
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Runs and hook calls going at once, more are dropped.
+const MAX_RUNNING: usize = 16;
+/// How often a sleeping script checks whether it was stopped.
+const SLEEP_SLICE: Duration = Duration::from_millis(50);
+
+/// Something that happened in the soundboard, passed on to the scripts' hooks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    SoundStarted(String),
+    /// The sound played to the end.
+    SoundFinished(String),
+    /// The sound was stopped before it played to the end.
+    SoundStopped(String),
+    PresetChanged(String),
+}
+
+/// The functions scripts can define to hear about [`Event`]s and the mic.
+#[derive(Clone, Copy)]
+enum Hook {
+    SoundStarted,
+    SoundFinished,
+    SoundStopped,
+    PresetChanged,
+    MicLevel,
+}
+
+impl Hook {
+    fn function(self) -> &'static str {
+        match self {
+            Self::SoundStarted => "on_sound_started",
+            Self::SoundFinished => "on_sound_finished",
+            Self::SoundStopped => "on_sound_stopped",
+            Self::PresetChanged => "on_preset_changed",
+            Self::MicLevel => "on_mic_level",
+        }
+    }
+}
+
+enum Message {
+    Run(String),
+    Event(Event),
+    FilesChanged,
+}
+
+/// Runs the scripts on a thread of its own, each run and hook call on a new thread so scripts
+/// can `sleep`.
+pub struct Scripts {
+    messages: Sender<Message>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl Scripts {
+    pub fn start(
+        dir: PathBuf,
+        config: &config::Scripts,
+        inputs: Sender<Input>,
+        mix: Arc<MixControl>,
+    ) -> Self {
+        let (messages, received) = mpsc::channel();
+        if let Err(err) = fs::create_dir_all(&dir) {
+            tracing::warn!(%err, dir = %dir.display(), "Couldn't create the scripts folder");
+        }
+        let watcher = watch(&dir, messages.clone())
+            .inspect_err(|err| tracing::warn!(%err, "Scripts won't reload when they change"))
+            .ok();
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut host = Host {
+            engine: Arc::new(engine(inputs.clone(), Arc::clone(&stopped))),
+            stopped,
+            running: Arc::default(),
+            inputs,
+            dir,
+            scripts: BTreeMap::new(),
+            mix,
+            mic_level_threshold: config.mic_level_threshold,
+            mic_over_threshold: false,
+        };
+        thread::spawn(move || {
+            host.reload();
+            host.run(&received);
+        });
+
+        Self {
+            messages,
+            _watcher: watcher,
+        }
+    }
+
+    /// Runs the top level code of the script named `name`.
+    pub fn run(&self, name: &str) {
+        let _ = self.messages.send(Message::Run(name.to_string()));
+    }
+
+    pub fn notify(&self, event: Event) {
+        let _ = self.messages.send(Message::Event(event));
+    }
+}
+
+fn watch(dir: &Path, messages: Sender<Message>) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| script_name(path).is_some())
+        {
+            let _ = messages.send(Message::FilesChanged);
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+fn script_name(path: &Path) -> Option<&str> {
+    if path.extension()? != "rhai" {
+        return None;
+    }
+    path.file_stem()?.to_str()
+}
+
+/// Compiles every script in `dir` by name.
+pub fn compile_dir(engine: &Engine, dir: &Path) -> BTreeMap<String, Result<AST, String>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = script_name(&path)?.to_string();
+            let ast = engine.compile_file(path).map_err(|err| err.to_string());
+            Some((name, ast))
+        })
+        .collect()
+}
+
+struct Host {
+    engine: Arc<Engine>,
+    /// Set to stop the runs of [`Host::engine`], a reload makes a new engine.
+    stopped: Arc<AtomicBool>,
+    running: Arc<AtomicUsize>,
+    inputs: Sender<Input>,
+    dir: PathBuf,
+    scripts: BTreeMap<String, Arc<AST>>,
+    mix: Arc<MixControl>,
+    mic_level_threshold: f32,
+    /// `on_mic_level` only runs when the level goes over the threshold, not while it stays there.
+    mic_over_threshold: bool,
+}
+
+impl Host {
+    fn run(&mut self, messages: &Receiver<Message>) {
+        loop {
+            match messages.recv_timeout(MIC_INTERVAL) {
+                Ok(Message::Run(name)) => match self.scripts.get(&name) {
+                    Some(ast) => {
+                        let (ast, script) = (Arc::clone(ast), name.clone());
+                        self.spawn(&name, move |engine| {
+                            if let Err(err) = engine.run_ast(&ast) {
+                                tracing::warn!(script, %err, "Script failed");
+                            }
+                        });
+                    }
+                    None => tracing::warn!(script = name, "There is no such script"),
+                },
+                Ok(Message::Event(event)) => match event {
+                    Event::SoundStarted(sound) => self.call(Hook::SoundStarted, (sound,)),
+                    Event::SoundFinished(sound) => self.call(Hook::SoundFinished, (sound,)),
+                    Event::SoundStopped(sound) => self.call(Hook::SoundStopped, (sound,)),
+                    Event::PresetChanged(preset) => self.call(Hook::PresetChanged, (preset,)),
+                },
+                Ok(Message::FilesChanged) => self.reload(),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            self.check_mic();
+        }
+    }
+
+    fn reload(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.stopped = Arc::new(AtomicBool::new(false));
+        self.engine = Arc::new(engine(self.inputs.clone(), Arc::clone(&self.stopped)));
+
+        self.scripts = compile_dir(&self.engine, &self.dir)
+            .into_iter()
+            .filter_map(|(name, ast)| match ast {
+                Ok(ast) => Some((name, Arc::new(ast))),
+                Err(err) => {
+                    tracing::warn!(script = name, %err, "Couldn't compile script");
+                    None
+                }
+            })
+            .collect();
+        tracing::info!(scripts = self.scripts.len(), "Loaded scripts");
+    }
+
+    fn check_mic(&mut self) {
+        if !self.has_hook(Hook::MicLevel) {
+            return;
+        }
+        let level = self.mix.take_mic_level();
+        let over = level >= self.mic_level_threshold;
+        if over && !self.mic_over_threshold {
+            self.call(Hook::MicLevel, (f64::from(level),));
+        }
+        self.mic_over_threshold = over;
+    }
+
+    fn has_hook(&self, hook: Hook) -> bool {
+        self.scripts.values().any(|ast| defines(ast, hook))
+    }
+
+    /// Runs `run` with the engine on a new thread, unless too many are running already.
+    fn spawn(&self, script: &str, run: impl FnOnce(&Engine) + Send + 'static) {
+        if self.running.fetch_add(1, Ordering::Relaxed) >= MAX_RUNNING {
+            self.running.fetch_sub(1, Ordering::Relaxed);
+            tracing::warn!(script, "Too many scripts running, didn't run it");
+            return;
+        }
+        let (engine, running) = (Arc::clone(&self.engine), Arc::clone(&self.running));
+        thread::spawn(move || {
+            run(&engine);
+            running.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+
+    /// Calls `hook` in every script that defines it.
+    fn call(&self, hook: Hook, args: impl FuncArgs + Clone + Send + 'static) {
+        for (name, ast) in &self.scripts {
+            if !defines(ast, hook) {
+                continue;
+            }
+            let (ast, args, script) = (Arc::clone(ast), args.clone(), name.clone());
+            self.spawn(name, move |engine| {
+                // the top level is the action of the script, hooks run without it
+                let options = CallFnOptions::new().eval_ast(false);
+                let result = engine.call_fn_with_options::<Dynamic>(
+                    options,
+                    &mut Scope::new(),
+                    &ast,
+                    hook.function(),
+                    args,
+                );
+                if let Err(err) = result {
+                    tracing::warn!(script, hook = hook.function(), %err, "Script failed");
+                }
+            });
+        }
+    }
+}
+
+fn defines(ast: &AST, hook: Hook) -> bool {
+    ast.iter_functions()
+        .any(|function| function.name == hook.function() && function.params.len() == 1)
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// An engine with the functions scripts use to control the soundboard, its runs end once
+/// `stopped` is set.
+fn engine(inputs: Sender<Input>, stopped: Arc<AtomicBool>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let progress = Arc::clone(&stopped);
+    engine.on_progress(move |_| {
+        progress
+            .load(Ordering::Relaxed)
+            .then(|| "the scripts were reloaded".into())
+    });
+    engine.on_print(|text| tracing::info!("{text}"));
+    engine.on_debug(|text, source, pos| tracing::debug!(?source, %pos, "{text}"));
+
+    let register = |engine: &mut Engine, name, to_command: fn(String) -> Command| {
+        let inputs = inputs.clone();
+        engine.register_fn(name, move |arg: &str| {
+            execute(&inputs, to_command(arg.to_string()))
+        });
+    };
+    register(&mut engine, "play", |name| {
+        Command::Play(SoundRef::Name(name))
+    });
+    register(&mut engine, "play_file", |path| {
+        Command::Play(SoundRef::Path(path))
+    });
+    register(&mut engine, "set_preset", Command::ActivatePreset);
+
+    for (name, command) in [
+        ("stop", Command::Stop),
+        ("pause", Command::SetPaused(true)),
+        ("resume", Command::SetPaused(false)),
+        ("toggle_pause", Command::TogglePause),
+    ] {
+        let inputs = inputs.clone();
+        engine.register_fn(name, move || execute(&inputs, command.clone()));
+    }
+
+    let set_param = inputs.clone();
+    engine.register_fn(
+        "set_param",
+        move |filter: &str, property: &str, value: f64| {
+            execute(
+                &set_param,
+                Command::SetFilterParam {
+                    preset: None,
+                    filter: filter.to_string(),
+                    property: property.to_string(),
+                    value: value as f32,
+                },
+            )
+        },
+    );
+    let set_preset_param = inputs.clone();
+    engine.register_fn(
+        "set_param",
+        move |preset: &str, filter: &str, property: &str, value: f64| {
+            execute(
+                &set_preset_param,
+                Command::SetFilterParam {
+                    preset: Some(preset.to_string()),
+                    filter: filter.to_string(),
+                    property: property.to_string(),
+                    value: value as f32,
+                },
+            )
+        },
+    );
+    engine.register_fn("set_volume", move |kind: &str, volume: f64| {
+        if !["mic", "sounds", "monitor", "mic_monitor"].contains(&kind) {
+            return Err(format!(
+                "Unknown volume \"{kind}\", use mic, sounds, monitor or mic_monitor"
+            )
+            .into());
+        }
+        let volume = Some(volume as f32);
+        let command = Command::SetVolumes {
+            mic: volume.filter(|_| kind == "mic"),
+            sounds: volume.filter(|_| kind == "sounds"),
+            monitor: volume.filter(|_| kind == "monitor"),
+            mic_monitor: volume.filter(|_| kind == "mic_monitor"),
+        };
+        execute(&inputs, command)
+    });
+
+    engine.register_fn("sleep", move |ms: i64| -> ScriptResult<()> {
+        let mut left = Duration::from_millis(ms.max(0) as u64);
+        while !left.is_zero() {
+            if stopped.load(Ordering::Relaxed) {
+                return Err("the scripts were reloaded".into());
+            }
+            let slice = left.min(SLEEP_SLICE);
+            thread::sleep(slice);
+            left -= slice;
+        }
+        Ok(())
+    });
+    engine.register_fn("random", random);
+    engine.register_fn("random", |min: i64, max: i64| -> ScriptResult<i64> {
+        if min > max {
+            return Err(format!("random({min}, {max}) has no numbers to pick from").into());
+        }
+        let span = max.abs_diff(min).saturating_add(1);
+        let offset = if span == 0 {
+            random_u64()
+        } else {
+            random_u64() % span
+        };
+        Ok(min.wrapping_add(offset as i64))
+    });
+    engine.register_fn("choose", |list: Array| -> ScriptResult<Dynamic> {
+        if list.is_empty() {
+            return Err("Can't choose from an empty list".into());
+        }
+        let idx = (random_u64() % list.len() as u64) as usize;
+        Ok(list[idx].clone())
+    });
+    engine
+}
+
+/// Sends `command` to the soundboard and waits for it to be done.
+fn execute(inputs: &Sender<Input>, command: Command) -> ScriptResult<()> {
+    control::execute(inputs, command)?
+        .map(drop)
+        .map_err(Into::into)
+}
+
+fn random_u64() -> u64 {
+    getrandom::u64().expect("no random numbers available")
+}
+
+/// From `0.0` up to but not including `1.0`.
+pub(super) fn random() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::control::fake_soundboard;
+    use std::{env, process, time::Instant};
+
+    fn scripts_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("keysounds-scripts-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn next(executed: &Receiver<String>) -> String {
+        executed.recv_timeout(Duration::from_secs(2)).unwrap()
+    }
+
+    /// Runs `script` until it executes `command`, after the file changed and got reloaded.
+    fn run_until(scripts: &Scripts, script: &str, executed: &Receiver<String>, command: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            scripts.run(script);
+            thread::sleep(Duration::from_millis(20));
+            if executed.try_iter().any(|executed| executed == command) {
+                return;
+            }
+            assert!(Instant::now() < deadline, "the script never reloaded");
+        }
+    }
+
+    #[test]
+    fn runs_actions_and_hooks() {
+        let dir = scripts_dir("hooks");
+        fs::write(
+            dir.join("combo.rhai"),
+            r#"
+            play(choose(["Boom"]));
+            sleep(1);
+            set_param("Church", "reverb", "wet", 0.5);
+            set_preset("Missing");
+            stop();
+
+            fn on_sound_finished(sound) {
+                play(sound + " again");
+            }
+
+            fn on_sound_stopped(sound) {
+                play(sound + " stopped");
+            }
+            "#,
+        )
+        .unwrap();
+        let (inputs, executed) = fake_soundboard();
+        let scripts = Scripts::start(
+            dir.clone(),
+            &config::Scripts::default(),
+            inputs,
+            Arc::default(),
+        );
+
+        scripts.run("combo");
+        assert_eq!(next(&executed), r#"Play(Name("Boom"))"#);
+        assert_eq!(
+            next(&executed),
+            r#"SetFilterParam { preset: Some("Church"), filter: "reverb", property: "wet", value: 0.5 }"#
+        );
+        // the failed command ends the script
+        assert_eq!(next(&executed), r#"ActivatePreset("Missing")"#);
+
+        scripts.notify(Event::SoundFinished("Boom".to_string()));
+        assert_eq!(next(&executed), r#"Play(Name("Boom again"))"#);
+        scripts.notify(Event::SoundStopped("Boom".to_string()));
+        assert_eq!(next(&executed), r#"Play(Name("Boom stopped"))"#);
+        assert!(executed.recv_timeout(Duration::from_millis(100)).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloads_changed_scripts() {
+        let dir = scripts_dir("reload");
+        let script = dir.join("airhorn.rhai");
+        fs::write(&script, r#"play("one");"#).unwrap();
+        let (inputs, executed) = fake_soundboard();
+        let scripts = Scripts::start(
+            dir.clone(),
+            &config::Scripts::default(),
+            inputs,
+            Arc::default(),
+        );
+
+        scripts.run("airhorn");
+        assert_eq!(next(&executed), r#"Play(Name("one"))"#);
+
+        fs::write(&script, r#"play("two");"#).unwrap();
+        run_until(&scripts, "airhorn", &executed, r#"Play(Name("two"))"#);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn endless_scripts_get_stopped() {
+        let (inputs, _executed) = fake_soundboard();
+        let engine = engine(inputs, Arc::default());
+        let err = engine.run("loop {}").unwrap_err();
+        assert!(matches!(*err, EvalAltResult::ErrorTooManyOperations(_)));
+    }
+
+    #[test]
+    fn reloading_stops_running_scripts() {
+        let dir = scripts_dir("stop");
+        let script = dir.join("spam.rhai");
+        fs::write(&script, "loop { stop(); sleep(5); }").unwrap();
+        let (inputs, executed) = fake_soundboard();
+        let scripts = Scripts::start(
+            dir.clone(),
+            &config::Scripts::default(),
+            inputs,
+            Arc::default(),
+        );
+
+        scripts.run("spam");
+        assert_eq!(next(&executed), "Stop");
+
+        fs::write(&script, r#"play("done");"#).unwrap();
+        run_until(&scripts, "spam", &executed, r#"Play(Name("done"))"#);
+        thread::sleep(Duration::from_millis(100));
+        executed.try_iter().for_each(drop);
+        assert!(executed.recv_timeout(Duration::from_millis(100)).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,510 @@
+#[cfg(target_os = "linux")]
+use crate::app::control::mpris;
+use crate::app::{
+    App, Playback, PlayingSound, TriggerMode,
+    audio::{self, Diagnostics, FilterChain, MixControl, Playhead, QueuedSound, SoundCache},
+    config::{
+        Config, Keybind, Volumes,
+        midi::{MidiAction, MidiControl, MidiMapping, VolumeKind},
+    },
+    control::{Command, Input, SoundRef, State, osc, rpc, web},
+    keybind_listener,
+    limits::Limits,
+    macro_runner::{self, MacroRun},
+    midi_listener::{self, MidiEvent},
+    scripting::{self, Scripts},
+    variants::Variants,
+};
+use cpal::Device;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender, TryRecvError},
+    },
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// Everything that keeps working without a window: the audio streams, the global keybinds and the
+/// sounds and presets they trigger. Shared by the GUI and the headless mode.
+pub struct Soundboard {
+    _keep_alive: audio::KeepAlive,
+    _control_server: Option<rpc::Server>,
+    web_remote: Option<web::Server>,
+    _osc_server: Option<osc::Server>,
+    scripts: Option<Scripts>,
+    /// By macro name, a macro triggered again while running starts over.
+    running_macros: HashMap<String, MacroRun>,
+    pub(super) variants: Variants,
+    pub(super) limits: Limits,
+    /// The key and id of a sound that plays while its keybind is held.
+    held_sound: Option<(rdev::Key, u64)>,
+    /// The last value of every MIDI control change by channel and number, so a fader sweeping
+    /// past the middle triggers once.
+    cc_values: HashMap<(u8, u8), u8>,
+    #[cfg(target_os = "linux")]
+    _mpris_server: Option<mpris::Server>,
+    pub(super) state_subscribers: Vec<Sender<State>>,
+    /// The last state sent to the subscribers and when.
+    pub(super) published_state: Option<(State, Instant)>,
+    inputs: Receiver<Input>,
+    input_sender: Sender<Input>,
+    pub(super) playing_sound: Option<PlayingSound>,
+    pub(super) target_sample_rate: u32,
+    pub(super) decoder: Arc<Mutex<Option<QueuedSound>>>,
+    pub(super) playhead: Arc<Playhead>,
+    pub(super) next_sound_id: u64,
+    /// Parked while there is nothing to decode, see [`Soundboard::create_streams`].
+    pub(super) decoder_thread: Thread,
+    pub(super) sound_cache: Arc<Mutex<SoundCache>>,
+    pub(super) config: Config,
+    pub(super) filter_chain: Arc<Mutex<FilterChain>>,
+    /// Filters every sound, after the filters of the sound itself.
+    pub(super) sound_filter_chain: Arc<Mutex<FilterChain>>,
+    /// Index of the filter preset that was applied last.
+    pub(super) active_preset: Option<usize>,
+    pub(super) mix: Arc<MixControl>,
+    pub(super) diagnostics: Arc<Diagnostics>,
+}
+
+impl Soundboard {
+    pub fn new(
+        config: Config,
+        mic_device: &Device,
+        out_device: &Device,
+        virtual_out_device: &Device,
+    ) -> Self {
+        let decoder = Arc::new(Mutex::new(None));
+        let playhead = Arc::new(Playhead::default());
+        let mix = Arc::new(MixControl::new(config.volumes));
+
+        let (
+            filter_chain,
+            sound_filter_chain,
+            sample_rate,
+            diagnostics,
+            decoder_thread,
+            keep_alive,
+        ) = Self::create_streams(
+            mic_device,
+            out_device,
+            virtual_out_device,
+            Arc::clone(&decoder),
+            Arc::clone(&playhead),
+            Arc::clone(&mix),
+            config.latency,
+        );
+
+        let sound_cache = Arc::new(Mutex::new(SoundCache::new(
+            sample_rate,
+            config.sound_cache_budget_mb,
+            Duration::try_from_secs_f32(config.sound_cache_max_duration).unwrap_or_else(|err| {
+                tracing::warn!(%err, "Invalid sound_cache_max_duration, no sounds get cached");
+                Duration::ZERO
+            }),
+        )));
+        SoundCache::load_in_background(
+            &sound_cache,
+            config
+                .sounds
+                .iter()
+                .flat_map(|sound| sound.paths().map(str::to_string)),
+        );
+
+        let (input_sender, inputs) = mpsc::channel();
+        keybind_listener::listen(input_sender.clone());
+        if config.midi.enabled {
+            midi_listener::listen(&config.midi, input_sender.clone());
+        }
+        let control_server = rpc::Server::start(input_sender.clone())
+            .inspect_err(|err| tracing::warn!(%err, "Couldn't start the control server"))
+            .ok();
+        let web_remote = config
+            .web_remote
+            .enabled
+            .then(|| web::Server::start(&config.web_remote, input_sender.clone()))
+            .and_then(|result| {
+                result
+                    .inspect_err(|err| tracing::warn!(%err, "Couldn't start the web remote"))
+                    .ok()
+            });
+        let osc_server = config
+            .osc
+            .enabled
+            .then(|| osc::Server::start(&config.osc, input_sender.clone(), Arc::clone(&mix)))
+            .and_then(|result| {
+                result
+                    .inspect_err(|err| tracing::warn!(%err, "Couldn't start OSC"))
+                    .ok()
+            });
+        let scripts = config.scripts.enabled.then(|| {
+            Scripts::start(
+                App::scripts_dir(),
+                &config.scripts,
+                input_sender.clone(),
+                Arc::clone(&mix),
+            )
+        });
+        #[cfg(target_os = "linux")]
+        let mpris_server = config
+            .mpris
+            .enabled
+            .then(|| mpris::Server::start(input_sender.clone()))
+            .and_then(|result| {
+                result
+                    .inspect_err(|err| tracing::warn!(%err, "Couldn't register the MPRIS player"))
+                    .ok()
+            });
+
+        let mut soundboard = Self {
+            _keep_alive: keep_alive,
+            _control_server: control_server,
+            web_remote,
+            _osc_server: osc_server,
+            scripts,
+            running_macros: HashMap::new(),
+            variants: Variants::default(),
+            limits: Limits::default(),
+            held_sound: None,
+            cc_values: HashMap::new(),
+            #[cfg(target_os = "linux")]
+            _mpris_server: mpris_server,
+            state_subscribers: Vec::new(),
+            published_state: None,
+            inputs,
+            input_sender,
+            playing_sound: None,
+            target_sample_rate: sample_rate,
+            decoder,
+            playhead,
+            next_sound_id: 0,
+            decoder_thread,
+            sound_cache,
+            config,
+            filter_chain,
+            sound_filter_chain,
+            active_preset: None,
+            mix,
+            diagnostics,
+        };
+        soundboard.set_sound_filter_preset(soundboard.config.sound_filter_preset.clone());
+        soundboard
+    }
+
+    pub fn try_recv_input(&self) -> Option<Input> {
+        self.inputs.try_recv().ok()
+    }
+
+    pub fn recv_input_timeout(&self, timeout: Duration) -> Option<Input> {
+        self.inputs.recv_timeout(timeout).ok()
+    }
+
+    pub fn web_remote(&self) -> Option<&web::Server> {
+        self.web_remote.as_ref()
+    }
+
+    /// For frontends to send [`Input::Command`]s with.
+    pub fn input_sender(&self) -> Sender<Input> {
+        self.input_sender.clone()
+    }
+
+    /// Runs whatever `keybind` is bound to, returns whether it was bound to anything.
+    pub fn handle_keybind(&mut self, keybind: Keybind) -> bool {
+        #[inline]
+        fn matches_keybind(keybind: Keybind, target_keybind: impl Into<Option<Keybind>>) -> bool {
+            target_keybind.into().is_some_and(|t| t == keybind)
+        }
+
+        if matches_keybind(keybind, self.config.stop_sound_keybind) {
+            self.stop_sound();
+            self.stop_macros();
+        } else if matches_keybind(keybind, self.config.search_and_play_keybind) {
+            // noop for now
+        } else if let Some(idx) = self
+            .config
+            .filter_presets
+            .iter()
+            .position(|preset| matches_keybind(keybind, preset.keybind))
+        {
+            self.apply_preset(idx);
+        } else if let Some(idx) = self
+            .config
+            .macros
+            .iter()
+            .position(|sound_macro| matches_keybind(keybind, sound_macro.keybind))
+        {
+            self.run_macro(idx);
+        } else if let Some((idx, playback)) = self.sound_for_keybind(keybind) {
+            let mut sound = self.config.sounds[idx].clone();
+            playback.apply(&mut sound);
+            let hold = sound.trigger_mode == TriggerMode::Hold;
+            let id = self.play_sound(sound, false);
+            if hold {
+                self.held_sound = id.map(|id| (keybind.key, id));
+            }
+        } else if let Some(binding) = self
+            .config
+            .scripts
+            .keybinds
+            .iter()
+            .find(|binding| matches_keybind(keybind, binding.keybind))
+        {
+            if let Some(scripts) = &self.scripts {
+                scripts.run(&binding.script);
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Runs what `event` is mapped to, returns whether it was mapped to anything.
+    pub fn handle_midi(&mut self, event: MidiEvent) -> bool {
+        let previous = match event.control {
+            MidiControl::Cc(cc) => self.cc_values.insert((event.channel, cc), event.value),
+            MidiControl::Note(_) => None,
+        };
+        let commands: Vec<Command> = self
+            .config
+            .midi
+            .mappings
+            .iter()
+            .filter(|mapping| {
+                mapping.control == event.control
+                    && mapping
+                        .channel
+                        .is_none_or(|channel| channel == event.channel)
+            })
+            .filter_map(|mapping| midi_command(mapping, event, previous))
+            .collect();
+
+        let mapped = !commands.is_empty();
+        for command in commands {
+            if let Err(err) = self.execute(command) {
+                tracing::warn!(%err, "MIDI mapping failed");
+            }
+        }
+        mapped
+    }
+
+    /// The sound bound to `keybind`, also when it is pressed with playback modifiers held.
+    fn sound_for_keybind(&self, keybind: Keybind) -> Option<(usize, Playback)> {
+        let sounds = &self.config.sounds;
+        let bound = sounds
+            .iter()
+            .position(|sound| sound.keybind == Some(keybind))
+            .map(|idx| (idx, Playback::default()));
+        bound.or_else(|| {
+            sounds.iter().enumerate().find_map(|(idx, sound)| {
+                let playback = self
+                    .config
+                    .playback_modifiers
+                    .matching(keybind, sound.keybind?)?;
+                Some((idx, playback))
+            })
+        })
+    }
+
+    /// Stops the sound held down with `key`, if it is still playing.
+    pub fn release_key(&mut self, key: rdev::Key) {
+        let Some((held_key, id)) = self.held_sound else {
+            return;
+        };
+        if held_key != key {
+            return;
+        }
+        self.held_sound = None;
+        if self
+            .playing_sound
+            .as_ref()
+            .is_some_and(|playing| playing.id == id)
+        {
+            self.stop_sound();
+        }
+    }
+
+    pub fn stop_sound(&mut self) {
+        *self.decoder.lock().unwrap() = None;
+        if let Some(stopped) = self.playing_sound.take() {
+            // it may have ended before `update` got to report it
+            let event = if self.playhead.has_finished(stopped.id) {
+                scripting::Event::SoundFinished(stopped.sound.name)
+            } else {
+                scripting::Event::SoundStopped(stopped.sound.name)
+            };
+            self.notify_scripts(event);
+        }
+    }
+
+    pub fn run_macro(&mut self, idx: usize) {
+        let Some(sound_macro) = self.config.macros.get(idx) else {
+            return;
+        };
+
+        tracing::info!(name = sound_macro.name, "Running macro");
+        let run = macro_runner::start(
+            sound_macro.name.clone(),
+            sound_macro.steps.clone(),
+            self.input_sender.clone(),
+        );
+        self.running_macros.insert(sound_macro.name.clone(), run);
+    }
+
+    pub fn stop_macros(&mut self) {
+        self.running_macros.clear();
+    }
+
+    pub fn apply_preset(&mut self, idx: usize) {
+        let Some(preset) = self.config.filter_presets.get(idx) else {
+            return;
+        };
+
+        tracing::info!(preset = preset.name, "Applying filter preset");
+        let mut chain = self.filter_chain.lock().unwrap();
+        chain.sync(preset.enabled_filters());
+        self.active_preset = Some(idx);
+        self.notify_scripts(scripting::Event::PresetChanged(preset.name.clone()));
+    }
+
+    /// Filters every sound with the preset named `name`, `None` leaves them unfiltered.
+    pub fn set_sound_filter_preset(&mut self, name: Option<String>) {
+        let preset = name.as_deref().and_then(|name| {
+            let preset = self.config.filter_preset(name);
+            if preset.is_none() {
+                tracing::warn!(preset = name, "No such filter preset for the sounds");
+            }
+            preset
+        });
+        let filters: Vec<_> = preset
+            .map(|preset| preset.enabled_filters().collect())
+            .unwrap_or_default();
+        self.sound_filter_chain.lock().unwrap().sync(filters);
+        self.config.sound_filter_preset = name;
+    }
+
+    pub(super) fn notify_scripts(&self, event: scripting::Event) {
+        if let Some(scripts) = &self.scripts {
+            scripts.notify(event);
+        }
+    }
+
+    pub fn set_volumes(&mut self, volumes: Volumes) {
+        self.config.volumes = volumes;
+        self.mix.set_volumes(volumes);
+    }
+
+    /// Picks up what the audio threads reported since the last call.
+    pub fn update(&mut self) {
+        if self
+            .playing_sound
+            .as_ref()
+            .is_some_and(|sound| self.playhead.has_finished(sound.id))
+        {
+            let finished = self.playing_sound.take().unwrap();
+            self.notify_scripts(scripting::Event::SoundFinished(finished.sound.name));
+        }
+
+        if let Some(playing_sound) = &mut self.playing_sound
+            && let Some(scan) = playing_sound.duration_scan.take()
+        {
+            match scan.try_recv() {
+                Ok(duration) => playing_sound.duration = duration,
+                Err(TryRecvError::Empty) => playing_sound.duration_scan = Some(scan),
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+
+        self.publish_state();
+    }
+}
+
+/// Notes trigger actions when pressed, controls when they go up past the middle, like most pads
+/// and buttons sending control changes do when pressed. `previous` is the value the control had
+/// before, if known.
+fn midi_command(mapping: &MidiMapping, event: MidiEvent, previous: Option<u8>) -> Option<Command> {
+    let pressed = match event.control {
+        MidiControl::Note(_) => true,
+        MidiControl::Cc(_) => event.value >= 64 && previous.is_none_or(|previous| previous < 64),
+    };
+    if !mapping.action.is_continuous() && !pressed {
+        return None;
+    }
+
+    let value = mapping.scale(event.value);
+    Some(match &mapping.action {
+        MidiAction::Play { sound } => Command::Play(SoundRef::Name(sound.clone())),
+        MidiAction::Stop => Command::Stop,
+        MidiAction::TogglePause => Command::TogglePause,
+        MidiAction::Preset { preset } => Command::ActivatePreset(preset.clone()),
+        MidiAction::Volume { volume } => Command::SetVolumes {
+            mic: Some(value).filter(|_| *volume == VolumeKind::Mic),
+            sounds: Some(value).filter(|_| *volume == VolumeKind::Sounds),
+            monitor: Some(value).filter(|_| *volume == VolumeKind::Monitor),
+            mic_monitor: Some(value).filter(|_| *volume == VolumeKind::MicMonitor),
+        },
+        MidiAction::FilterProperty {
+            preset,
+            filter,
+            property,
+        } => Command::SetFilterParam {
+            preset: Some(preset.clone()),
+            filter: filter.clone(),
+            property: property.clone(),
+            value,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(
+        action: MidiAction,
+        control: MidiControl,
+        value: u8,
+        previous: Option<u8>,
+    ) -> Option<String> {
+        let mapping = MidiMapping {
+            control,
+            channel: None,
+            action,
+            range: Some((0.0, 2.0)),
+        };
+        let event = MidiEvent {
+            channel: 1,
+            control,
+            value,
+        };
+        midi_command(&mapping, event, previous).map(|command| format!("{command:?}"))
+    }
+
+    #[test]
+    fn maps_midi_to_commands() {
+        let stop = || MidiAction::Stop;
+        assert_eq!(
+            command(stop(), MidiControl::Note(36), 1, None),
+            Some("Stop".to_string())
+        );
+        assert_eq!(
+            command(stop(), MidiControl::Cc(20), 127, Some(0)),
+            Some("Stop".to_string())
+        );
+        // the release of a pad
+        assert_eq!(command(stop(), MidiControl::Cc(20), 0, Some(127)), None);
+        // a fader moving further up after crossing the middle
+        assert_eq!(command(stop(), MidiControl::Cc(20), 100, Some(70)), None);
+
+        let volume = MidiAction::Volume {
+            volume: VolumeKind::Sounds,
+        };
+        assert_eq!(
+            command(volume, MidiControl::Cc(7), 127, Some(120)),
+            Some(
+                "SetVolumes { mic: None, sounds: Some(2.0), monitor: None, mic_monitor: None }"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::app::{Selection, Sound};
+use std::collections::HashMap;
+
+/// Picks the file of sound groups, remembering what shuffled groups have left to play.
+#[derive(Default)]
+pub struct Variants {
+    shuffles: HashMap<String, Shuffle>,
+}
+
+#[derive(Default)]
+struct Shuffle {
+    /// Variants not played since the last refill, the next one at the end.
+    remaining: Vec<usize>,
+    last: Option<usize>,
+}
+
+/// What a single trigger of a sound plays.
+pub struct Variation {
+    /// A plain sound with the picked path and the varied volume.
+    pub sound: Sound,
+    /// How much faster, and so higher, the sound plays. `1.0` leaves it alone.
+    pub pitch: f32,
+}
+
+impl Variants {
+    /// Resolves `sound` for one trigger, `random` gives numbers from `0.0` up to but not
+    /// including `1.0`.
+    pub fn pick(&mut self, sound: &Sound, random: &mut impl FnMut() -> f64) -> Variation {
+        let mut picked = sound.clone();
+        picked.variants.clear();
+        if !sound.variants.is_empty() {
+            let idx = match sound.selection {
+                Selection::Uniform => Self::uniform(sound.variants.len(), random),
+                Selection::Weighted => {
+                    let weights: Vec<f64> = sound
+                        .variants
+                        .iter()
+                        .map(|variant| variant.weight.max(0.0) as f64)
+                        .collect();
+                    Self::weighted(&weights, random)
+                }
+                Selection::Shuffle => self
+                    .shuffles
+                    .entry(sound.name.clone())
+                    .or_default()
+                    .next(sound.variants.len(), random),
+            };
+            picked.path = sound.variants[idx].path.clone();
+        }
+
+        if sound.volume_variance > 0.0 {
+            let change = (random() * 2.0 - 1.0) as f32 * sound.volume_variance;
+            picked.volume = (picked.volume * (1.0 + change)).max(0.0);
+        }
+        let pitch = if sound.pitch_variance > 0.0 {
+            let semitones = (random() * 2.0 - 1.0) as f32 * sound.pitch_variance;
+            2f32.powf(semitones / 12.0)
+        } else {
+            1.0
+        };
+
+        Variation {
+            sound: picked,
+            pitch,
+        }
+    }
+
+    fn uniform(len: usize, random: &mut impl FnMut() -> f64) -> usize {
+        ((random() * len as f64) as usize).min(len - 1)
+    }
+
+    /// Falls back to uniform when no variant has any weight.
+    fn weighted(weights: &[f64], random: &mut impl FnMut() -> f64) -> usize {
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return Self::uniform(weights.len(), random);
+        }
+        let mut target = random() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                return i;
+            }
+            target -= weight;
+        }
+        weights
+            .iter()
+            .rposition(|weight| *weight > 0.0)
+            .unwrap_or(0)
+    }
+}
+
+impl Shuffle {
+    fn next(&mut self, len: usize, random: &mut impl FnMut() -> f64) -> usize {
+        // the group may have lost variants since the last trigger
+        self.remaining.retain(|idx| *idx < len);
+        if self.remaining.is_empty() {
+            self.remaining = (0..len).collect();
+            for i in (1..len).rev() {
+                let j = Variants::uniform(i + 1, random);
+                self.remaining.swap(i, j);
+            }
+            // a refill doesn't start with what the last one ended with
+            if len > 1 && self.remaining.last() == self.last.as_ref() {
+                self.remaining.swap(0, len - 1);
+            }
+        }
+        let idx = self.remaining.pop().unwrap_or(0);
+        self.last = Some(idx);
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Variant;
+
+    fn group(selection: Selection, weights: &[f32]) -> Sound {
+        let mut sound = Sound::new("Bonk".to_string(), String::new());
+        sound.selection = selection;
+        sound.variants = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| Variant {
+                path: format!("bonk{i}.ogg"),
+                weight: *weight,
+            })
+            .collect();
+        sound
+    }
+
+    /// Cycles through `values`, like a very predictable random number generator.
+    fn sequence(values: &[f64]) -> impl FnMut() -> f64 + '_ {
+        let mut values = values.iter().cycle();
+        move || *values.next().unwrap()
+    }
+
+    #[test]
+    fn weighted_follows_the_weights() {
+        let sound = group(Selection::Weighted, &[1.0, 0.0, 3.0]);
+        let mut variants = Variants::default();
+        let mut picks = |random: f64| variants.pick(&sound, &mut || random).sound.path;
+
+        assert_eq!(picks(0.0), "bonk0.ogg");
+        assert_eq!(picks(0.24), "bonk0.ogg");
+        assert_eq!(picks(0.25), "bonk2.ogg");
+        assert_eq!(picks(0.99), "bonk2.ogg");
+    }
+
+    #[test]
+    fn shuffle_plays_everything_before_repeating() {
+        let sound = group(Selection::Shuffle, &[1.0; 4]);
+        let mut variants = Variants::default();
+        let mut random = sequence(&[0.7, 0.1, 0.5, 0.3, 0.9]);
+
+        let mut previous = None;
+        for _ in 0..5 {
+            let mut round: Vec<String> = (0..4)
+                .map(|_| variants.pick(&sound, &mut random).sound.path)
+                .collect();
+            assert_ne!(previous.as_ref(), round.first());
+            previous = round.last().cloned();
+            round.sort();
+            assert_eq!(round, ["bonk0.ogg", "bonk1.ogg", "bonk2.ogg", "bonk3.ogg"]);
+        }
+    }
+
+    #[test]
+    fn variance_stays_within_bounds() {
+        let mut sound = Sound::new("Bonk".to_string(), "bonk.ogg".to_string());
+        sound.volume = 0.5;
+        sound.volume_variance = 0.2;
+        sound.pitch_variance = 12.0;
+        let mut variants = Variants::default();
+
+        let lowest = variants.pick(&sound, &mut || 0.0);
+        assert_eq!(lowest.sound.path, "bonk.ogg");
+        assert_eq!(lowest.sound.volume, 0.4);
+        assert_eq!(lowest.pitch, 0.5);
+        let middle = variants.pick(&sound, &mut || 0.5);
+        assert_eq!(middle.sound.volume, 0.5);
+        assert_eq!(middle.pitch, 1.0);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::app::{App, config::Config};
+use std::process::ExitCode;
+
+mod check;
+mod ctl;
+mod devices;
+mod headless;
+mod render;
+
+const USAGE: &str = "\
+Usage: keysounds [--headless | COMMAND]
+
+Starts the GUI when no command is given.
+
+Options:
+  --headless
+      Runs the soundboard without a window instead of the GUI. The stop sound and filter preset
+      keybinds keep working until the process gets SIGINT or SIGTERM.
+
+Commands:
+  render [--sample-rate <HZ>] [--tail <SECONDS>] <PRESET> <INPUT> <OUTPUT>
+      Applies the filter preset named PRESET to the INPUT audio file and writes the result to
+      OUTPUT as a 32-bit float WAV file. --tail keeps rendering up to 600 seconds of silence
+      after the input ends, so reverb tails aren't cut off.
+  devices
+      Lists the devices of every audio host with their ids, names and supported configs.
+  check
+      Checks the config, the sound files and the configured devices. Exits with 1 if there are
+      errors and with 2 if the config is missing or can't be parsed.
+  ctl <COMMAND>
+      Controls the running keysounds instance over its control socket:
+        list                          Lists the sounds
+        presets                       Lists the filter presets
+        play <NAME>                   Plays the sound named NAME
+        play --path <FILE>            Plays any audio file
+        stop
+        pause | resume | toggle-pause
+        seek <SECONDS>                Plays the playing sound from SECONDS
+        preset <NAME>                 Activates the filter preset named NAME
+        volume [--mic <V>] [--sounds <V>] [--monitor <V>] [--mic-monitor <V>]
+                                      Sets the mic volume, the volume of sounds on the virtual
+                                      output, the volume of sounds on the local output and the
+                                      volume of the mic on the local output
+        param <FILTER> <PROPERTY> <V> Sets a filter property in the active preset, like
+                                      `param reverb room_size 0.9`
+        state                         Prints what is playing, the active preset and volumes
+  help
+      Prints this message.
+";
+
+/// Runs the command given on the command line, or returns `None` if the GUI should start instead.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+    let (command, args) = args.split_first()?;
+
+    let result = match command.as_str() {
+        "--headless" => headless::run().map(|()| ExitCode::SUCCESS),
+        "render" => render::run(args).map(|()| ExitCode::SUCCESS),
+        "devices" => devices::run(args).map(|()| ExitCode::SUCCESS),
+        "check" => check::run(args),
+        "ctl" => ctl::run(args).map(|()| ExitCode::SUCCESS),
+        "help" | "-h" | "--help" => {
+            print!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(format!("Unknown command \"{command}\"\n\n{USAGE}")),
+    };
+
+    Some(match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
+    })
+}
+
+/// Reads the config without creating a default one like the GUI does.
+fn read_config() -> Result<Config, String> {
+    App::read_config()?.ok_or_else(|| {
+        format!(
+            "There is no config at {}, start keysounds once to create it",
+            App::config_file().display()
+        )
+    })
+}
+
+/// Parses the value following the option `name`.
+fn option_value<T: std::str::FromStr>(name: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{name} needs a value"))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {name}: \"{value}\""))
+}
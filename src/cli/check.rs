@@ -0,0 +1,517 @@
+use crate::app::{
+    App, Devices, Playback, Selection, TriggerMode,
+    audio::{AudioDecoder, MAX_SHIFT, MAX_SPEED, MIN_SPEED},
+    config::{
+        Config, Keybind, Latency,
+        filter::same_name,
+        macros::MacroStep,
+        midi::{MidiAction, MidiControl},
+    },
+    scripting,
+};
+use std::{fmt, path::Path, process::ExitCode};
+
+/// Exit code when the config has errors.
+const EXIT_PROBLEMS: u8 = 1;
+/// Exit code when there is no config or it can't be parsed.
+const EXIT_NO_CONFIG: u8 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// keysounds still works, but probably not the way it's meant to.
+    Warning,
+    /// keysounds fails to start or the affected part doesn't work.
+    Error,
+}
+
+struct Problem {
+    severity: Severity,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}", self.message)
+    }
+}
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn warn(&mut self, message: impl Into<String>) {
+        self.0.push(Problem {
+            severity: Severity::Warning,
+            message: message.into(),
+        });
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.0.push(Problem {
+            severity: Severity::Error,
+            message: message.into(),
+        });
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.0.iter().filter(|p| p.severity == severity).count()
+    }
+}
+
+/// Validates the config, its sounds and its devices, printing every problem found.
+pub(super) fn run(args: &[String]) -> Result<ExitCode, String> {
+    if let Some(arg) = args.first() {
+        return Err(format!("check doesn't take any arguments, got \"{arg}\""));
+    }
+
+    let path = App::config_file();
+    let config = match App::read_config() {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!(
+                "error: no config at {}, start keysounds once to create it",
+                path.display()
+            );
+            return Ok(ExitCode::from(EXIT_NO_CONFIG));
+        }
+        Err(err) => {
+            println!("error: {err}");
+            return Ok(ExitCode::from(EXIT_NO_CONFIG));
+        }
+    };
+    println!("Checking {}", path.display());
+
+    let mut problems = Problems::default();
+    check_settings(&config, &mut problems);
+    check_devices(&config, &mut problems);
+    check_keybinds(&config, &mut problems);
+    check_midi(&config, &mut problems);
+    check_macros(&config, &mut problems);
+    check_playback_modifiers(&config, &mut problems);
+    check_scripts(&config, &mut problems);
+    check_sounds(&config, &mut problems);
+
+    for problem in &problems.0 {
+        println!("{problem}");
+    }
+
+    let errors = problems.count(Severity::Error);
+    let warnings = problems.count(Severity::Warning);
+    println!("{errors} error(s), {warnings} warning(s)");
+
+    Ok(if errors > 0 {
+        ExitCode::from(EXIT_PROBLEMS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn check_settings(config: &Config, problems: &mut Problems) {
+    if !(config.gui_scale.is_finite() && config.gui_scale > 0.0) {
+        problems.error(format!(
+            "gui_scale must be a positive number, got {}",
+            config.gui_scale
+        ));
+    }
+    if !(config.sound_cache_max_duration.is_finite() && config.sound_cache_max_duration >= 0.0) {
+        problems.error(format!(
+            "sound_cache_max_duration can't be negative, got {}",
+            config.sound_cache_max_duration
+        ));
+    }
+    for (name, volume) in [
+        ("mic", config.volumes.mic),
+        ("sounds", config.volumes.sounds),
+        ("monitor", config.volumes.monitor),
+        ("mic_monitor", config.volumes.mic_monitor),
+    ] {
+        if !(volume.is_finite() && volume >= 0.0) {
+            problems.error(format!("volumes.{name} can't be negative, got {volume}"));
+        }
+    }
+    if let Latency::Frames(frames) = config.latency
+        && frames != config.latency.block_frames()
+    {
+        problems.warn(format!(
+            "latency of {frames} frames is out of range, {} frames are used instead",
+            config.latency.block_frames()
+        ));
+    }
+    if let Some(preset) = &config.sound_filter_preset
+        && config.filter_preset(preset).is_none()
+    {
+        problems.error(format!(
+            "sound_filter_preset is \"{preset}\", which doesn't exist"
+        ));
+    }
+}
+
+fn check_devices(config: &Config, problems: &mut Problems) {
+    let (input_devices, output_devices) = Devices::available();
+
+    for (kind, devices, name) in [
+        ("input_device", &input_devices, &config.input_device),
+        ("output_device", &output_devices, &config.output_device),
+    ] {
+        if devices.is_empty() {
+            problems.error(format!("there are no devices to use as the {kind}"));
+        } else if !Devices::is_default(name) && Devices::find(devices, name).is_none() {
+            problems.warn(format!(
+                "{kind} \"{name}\" not found, the system default is used instead"
+            ));
+        }
+    }
+
+    if Devices::find(&output_devices, &config.virtual_output_device).is_none() {
+        problems.error(format!(
+            "virtual_output_device \"{}\" not found, run `keysounds devices` to list them",
+            config.virtual_output_device
+        ));
+    }
+}
+
+fn check_keybinds(config: &Config, problems: &mut Problems) {
+    let mut keybinds: Vec<(Keybind, String)> = Vec::new();
+    let named_keybinds = [
+        (config.stop_sound_keybind, "stop_sound_keybind".to_string()),
+        (
+            config.search_and_play_keybind,
+            "search_and_play_keybind".to_string(),
+        ),
+    ]
+    .into_iter()
+    .chain(config.filter_presets.iter().map(|preset| {
+        (
+            Some(preset.keybind),
+            format!("filter preset \"{}\"", preset.name),
+        )
+    }))
+    .chain(config.macros.iter().map(|sound_macro| {
+        (
+            sound_macro.keybind,
+            format!("macro \"{}\"", sound_macro.name),
+        )
+    }))
+    .chain(
+        config
+            .sounds
+            .iter()
+            .map(|sound| (sound.keybind, format!("sound \"{}\"", sound.name))),
+    )
+    .chain(config.scripts.keybinds.iter().map(|binding| {
+        (
+            Some(binding.keybind),
+            format!("script \"{}\"", binding.script),
+        )
+    }));
+
+    for (keybind, owner) in named_keybinds {
+        let Some(keybind) = keybind else {
+            continue;
+        };
+        if let Some((_, first_owner)) = keybinds.iter().find(|(other, _)| *other == keybind) {
+            problems.warn(format!(
+                "{keybind} is bound to both {first_owner} and {owner}, only {first_owner} is used"
+            ));
+        } else {
+            keybinds.push((keybind, owner));
+        }
+    }
+
+    for (i, preset) in config.filter_presets.iter().enumerate() {
+        if config.filter_presets[..i]
+            .iter()
+            .any(|other| other.name == preset.name)
+        {
+            problems.warn(format!(
+                "there are multiple filter presets named \"{}\"",
+                preset.name
+            ));
+        }
+    }
+}
+
+fn check_midi(config: &Config, problems: &mut Problems) {
+    for mapping in &config.midi.mappings {
+        let (MidiControl::Note(number) | MidiControl::Cc(number)) = mapping.control;
+        if number > 127 {
+            problems.error(format!(
+                "MIDI mapping {mapping} is out of range, the last is 127"
+            ));
+        }
+        if mapping
+            .channel
+            .is_some_and(|channel| !(1..=16).contains(&channel))
+        {
+            problems.error(format!(
+                "MIDI mapping {mapping} has a channel outside of 1 to 16"
+            ));
+        }
+        if mapping
+            .range
+            .is_some_and(|(min, max)| !(min.is_finite() && max.is_finite()))
+        {
+            problems.error(format!("MIDI mapping {mapping} has an invalid range"));
+        }
+
+        let missing_preset = |name: &str| {
+            format!("MIDI mapping {mapping} uses filter preset \"{name}\", which doesn't exist")
+        };
+        let find_preset = |name: &str| {
+            config
+                .filter_presets
+                .iter()
+                .find(|preset| preset.name == name)
+        };
+        match &mapping.action {
+            MidiAction::Play { sound } => {
+                if !config
+                    .sounds
+                    .iter()
+                    .any(|other| other.name.eq_ignore_ascii_case(sound))
+                {
+                    problems.error(format!(
+                        "MIDI mapping {mapping} plays \"{sound}\", which isn't a sound"
+                    ));
+                }
+            }
+            MidiAction::Preset { preset } => {
+                if find_preset(preset).is_none() {
+                    problems.error(missing_preset(preset));
+                }
+            }
+            MidiAction::FilterProperty {
+                preset,
+                filter,
+                property,
+            } => {
+                let Some(preset) = find_preset(preset) else {
+                    problems.error(missing_preset(preset));
+                    continue;
+                };
+                let Some(audio_filter) = preset
+                    .filters
+                    .iter()
+                    .find(|audio_filter| same_name(audio_filter.name(), filter))
+                else {
+                    problems.error(format!(
+                        "MIDI mapping {mapping} uses filter {filter}, which \"{}\" doesn't have",
+                        preset.name
+                    ));
+                    continue;
+                };
+                if !audio_filter
+                    .filter_type
+                    .properties()
+                    .iter()
+                    .any(|prop| same_name(prop.name(), property))
+                {
+                    problems.error(format!(
+                        "MIDI mapping {mapping} uses property {property}, which {} doesn't have",
+                        audio_filter.name()
+                    ));
+                }
+            }
+            MidiAction::Stop | MidiAction::TogglePause | MidiAction::Volume { .. } => {}
+        }
+    }
+}
+
+fn check_macros(config: &Config, problems: &mut Problems) {
+    for sound_macro in &config.macros {
+        let name = &sound_macro.name;
+        let is_sound = |sound: &str| {
+            config
+                .sounds
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(sound))
+        };
+
+        let owner = format!("macro \"{name}\"");
+        for step in &sound_macro.steps {
+            let sounds = match step {
+                MacroStep::Play {
+                    sound, playback, ..
+                } => {
+                    check_playback(&owner, *playback, problems);
+                    vec![sound]
+                }
+                MacroStep::Layer { layers } => {
+                    for layer in layers {
+                        check_playback(&owner, layer.playback, problems);
+                    }
+                    layers.iter().map(|layer| &layer.sound).collect()
+                }
+                MacroStep::Preset { preset } => {
+                    if !config
+                        .filter_presets
+                        .iter()
+                        .any(|other| &other.name == preset)
+                    {
+                        problems.error(format!(
+                            "macro \"{name}\" uses filter preset \"{preset}\", which doesn't exist"
+                        ));
+                    }
+                    continue;
+                }
+                MacroStep::Wait { .. } | MacroStep::Stop => continue,
+            };
+            for sound in sounds {
+                if !is_sound(sound) {
+                    problems.error(format!(
+                        "macro \"{name}\" plays \"{sound}\", which isn't a sound"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_playback_modifiers(config: &Config, problems: &mut Problems) {
+    let modifiers = &config.playback_modifiers;
+    for (modifier, playback) in [
+        ("ctrl", modifiers.ctrl),
+        ("alt", modifiers.alt),
+        ("shift", modifiers.shift),
+    ] {
+        if let Some(playback) = playback {
+            check_playback(&format!("playback modifier {modifier}"), playback, problems);
+        }
+    }
+}
+
+/// Checks that the speed and pitch changes are within what playback supports.
+fn check_playback(owner: &str, playback: Playback, problems: &mut Problems) {
+    if let Some(speed) = playback.speed
+        && !(speed.is_finite() && (MIN_SPEED..=MAX_SPEED).contains(&speed))
+    {
+        problems.error(format!(
+            "{owner} has a speed of {speed}, it must be between {MIN_SPEED} and {MAX_SPEED}"
+        ));
+    }
+    if let Some(pitch) = playback.pitch
+        && !(pitch.is_finite() && pitch.abs() <= MAX_SHIFT)
+    {
+        problems.error(format!(
+            "{owner} shifts the pitch by {pitch} semitones, it must be between -{MAX_SHIFT} and {MAX_SHIFT}"
+        ));
+    }
+}
+
+fn check_scripts(config: &Config, problems: &mut Problems) {
+    if !config.scripts.enabled {
+        if !config.scripts.keybinds.is_empty() {
+            problems
+                .warn("scripts have keybinds but are disabled, set enabled = true under [scripts]");
+        }
+        return;
+    }
+
+    let scripts = scripting::compile_dir(&rhai::Engine::new(), &App::scripts_dir());
+    for (name, ast) in &scripts {
+        if let Err(err) = ast {
+            problems.error(format!("script \"{name}\" doesn't compile: {err}"));
+        }
+    }
+    for binding in &config.scripts.keybinds {
+        if !scripts.contains_key(&binding.script) {
+            problems.error(format!(
+                "{} is bound to script \"{}\", which isn't in {}",
+                binding.keybind,
+                binding.script,
+                App::scripts_dir().display()
+            ));
+        }
+    }
+}
+
+fn check_sounds(config: &Config, problems: &mut Problems) {
+    for sound in &config.sounds {
+        let name = &sound.name;
+        let playback = Playback {
+            speed: Some(sound.speed),
+            pitch: Some(sound.pitch),
+            reverse: None,
+        };
+        check_playback(&format!("sound \"{name}\""), playback, problems);
+        if !(sound.volume.is_finite() && sound.volume >= 0.0) {
+            problems.error(format!(
+                "sound \"{name}\" has an invalid volume of {}",
+                sound.volume
+            ));
+        }
+        if !(sound.volume_variance.is_finite() && (0.0..=1.0).contains(&sound.volume_variance)) {
+            problems.error(format!(
+                "sound \"{name}\" has a volume_variance of {}, it must be between 0 and 1",
+                sound.volume_variance
+            ));
+        }
+        if !(sound.pitch_variance.is_finite() && (0.0..=24.0).contains(&sound.pitch_variance)) {
+            problems.error(format!(
+                "sound \"{name}\" has a pitch_variance of {}, it must be between 0 and 24 semitones",
+                sound.pitch_variance
+            ));
+        }
+
+        if !(sound.cooldown.is_finite() && sound.cooldown >= 0.0) {
+            problems.error(format!(
+                "sound \"{name}\" has an invalid cooldown of {}",
+                sound.cooldown
+            ));
+        }
+        if sound.max_per_minute == Some(0) {
+            problems.warn(format!(
+                "sound \"{name}\" has a max_per_minute of 0, so it never plays"
+            ));
+        }
+
+        if let Some(preset) = &sound.filter_preset
+            && config.filter_preset(preset).is_none()
+        {
+            problems.error(format!(
+                "sound \"{name}\" uses filter preset \"{preset}\", which doesn't exist"
+            ));
+        }
+
+        if sound.trigger_mode == TriggerMode::Hold && sound.keybind.is_none() {
+            problems.warn(format!(
+                "sound \"{name}\" plays while its keybind is held, but has no keybind"
+            ));
+        }
+
+        if sound.path.is_empty() && sound.variants.is_empty() {
+            problems.error(format!("sound \"{name}\" has neither a path nor variants"));
+        } else if !sound.path.is_empty() && !sound.variants.is_empty() {
+            problems.warn(format!(
+                "sound \"{name}\" has both a path and variants, only the variants are played"
+            ));
+        }
+        for variant in &sound.variants {
+            if !(variant.weight.is_finite() && variant.weight >= 0.0) {
+                problems.error(format!(
+                    "sound \"{name}\": {} has an invalid weight of {}",
+                    variant.path, variant.weight
+                ));
+            }
+        }
+        if sound.selection == Selection::Weighted
+            && sound.variants.iter().all(|variant| variant.weight <= 0.0)
+        {
+            problems.warn(format!(
+                "sound \"{name}\" picks variants by weight, but none has a weight above 0"
+            ));
+        }
+
+        for path in sound.paths() {
+            if !Path::new(path).exists() {
+                problems.error(format!("sound \"{name}\": {path} doesn't exist"));
+            } else if let Err(err) = AudioDecoder::new(path, 48_000, 1.0) {
+                problems.error(format!("sound \"{name}\": couldn't open {path}: {err}"));
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    app::control::rpc,
+    cli::{USAGE, option_value},
+};
+use serde_json::{Map, Value, json};
+use std::path::Path;
+
+/// Forwards a command to the running instance and prints its result.
+pub(super) fn run(args: &[String]) -> Result<(), String> {
+    let (command, args) = args
+        .split_first()
+        .ok_or_else(|| format!("ctl needs a command\n\n{USAGE}"))?;
+
+    let (method, params) = match (command.as_str(), args) {
+        ("list", []) => ("list_sounds", Value::Null),
+        ("presets", []) => ("list_presets", Value::Null),
+        ("play", [flag, path]) if flag == "--path" => {
+            // the running instance has its own working directory
+            let path = std::path::absolute(Path::new(path))
+                .map_err(|err| format!("Invalid path {path}: {err}"))?;
+            ("play", json!({ "path": path }))
+        }
+        ("play", [name]) => ("play", json!({ "name": name })),
+        ("stop", []) => ("stop", Value::Null),
+        ("pause", []) => ("pause", Value::Null),
+        ("resume", []) => ("resume", Value::Null),
+        ("toggle-pause", []) => ("toggle_pause", Value::Null),
+        ("seek", [position]) => {
+            let position = position
+                .parse::<f64>()
+                .map_err(|err| format!("Invalid position {position}: {err}"))?;
+            ("seek", json!({ "position": position }))
+        }
+        ("preset", [name]) => ("activate_preset", json!({ "name": name })),
+        ("volume", args) if !args.is_empty() => ("set_volumes", volume_params(args)?),
+        ("param", [filter, property, value]) => {
+            let value = value
+                .parse::<f32>()
+                .map_err(|err| format!("Invalid value {value}: {err}"))?;
+            (
+                "set_filter_param",
+                json!({ "filter": filter, "property": property, "value": value }),
+            )
+        }
+        ("state", []) => ("state", Value::Null),
+        _ => {
+            return Err(format!(
+                "Unknown ctl command or wrong arguments \"{}\"\n\n{USAGE}",
+                [command.as_str()]
+                    .into_iter()
+                    .chain(args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+    };
+
+    let result = rpc::call(method, params)?;
+    if !result.is_null() {
+        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+    }
+    Ok(())
+}
+
+fn volume_params(args: &[String]) -> Result<Value, String> {
+    let mut params = Map::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let key = match arg.as_str() {
+            "--mic" => "mic",
+            "--sounds" => "sounds",
+            "--monitor" => "monitor",
+            "--mic-monitor" => "mic_monitor",
+            _ => return Err(format!("Unknown option {arg}")),
+        };
+        params.insert(
+            key.to_string(),
+            json!(option_value::<f32>(arg, args.next())?),
+        );
+    }
+    Ok(Value::Object(params))
+}
@@ -0,0 +1,165 @@
+use cpal::{
+    Device, SampleFormat, SupportedStreamConfigRange,
+    traits::{DeviceTrait, HostTrait},
+};
+
+/// Prints every device of every available host along with the configs it supports.
+pub(super) fn run(args: &[String]) -> Result<(), String> {
+    if let Some(arg) = args.first() {
+        return Err(format!("devices doesn't take any arguments, got \"{arg}\""));
+    }
+
+    let default_host = cpal::default_host().id();
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(err) => {
+                println!("{} (unavailable: {err})\n", host_id.name());
+                continue;
+            }
+        };
+
+        let default_marker = if host_id == default_host {
+            " (default host)"
+        } else {
+            ""
+        };
+        println!("{}{default_marker}", host_id.name());
+
+        let default_input = host.default_input_device();
+        println!("  Input devices:");
+        match host.input_devices() {
+            Ok(devices) => {
+                for device in devices {
+                    let is_default = default_input.as_ref() == Some(&device);
+                    print_device(
+                        &device,
+                        is_default,
+                        device.supported_input_configs().map(Iterator::collect),
+                    );
+                }
+            }
+            Err(err) => println!("    Couldn't list input devices: {err}"),
+        }
+
+        let default_output = host.default_output_device();
+        println!("  Output devices:");
+        match host.output_devices() {
+            Ok(devices) => {
+                for device in devices {
+                    let is_default = default_output.as_ref() == Some(&device);
+                    print_device(
+                        &device,
+                        is_default,
+                        device.supported_output_configs().map(Iterator::collect),
+                    );
+                }
+            }
+            Err(err) => println!("    Couldn't list output devices: {err}"),
+        }
+        println!();
+    }
+
+    println!(
+        "Use a device's id or name for input_device, output_device or virtual_output_device in \
+         the config."
+    );
+    Ok(())
+}
+
+fn print_device(
+    device: &Device,
+    is_default: bool,
+    configs: Result<Vec<SupportedStreamConfigRange>, impl std::fmt::Display>,
+) {
+    let name = device
+        .description()
+        .map(|desc| desc.name().to_string())
+        .unwrap_or_else(|_| device.to_string());
+    let default_marker = if is_default { " (default)" } else { "" };
+    println!("    {name}{default_marker}");
+
+    match device.id() {
+        Ok(id) => println!("      id: {}", id.id()),
+        Err(err) => println!("      id: unknown ({err})"),
+    }
+
+    match configs {
+        Ok(configs) if configs.is_empty() => println!("      no supported configs"),
+        Ok(configs) => {
+            for line in format_configs(&configs) {
+                println!("      {line}");
+            }
+        }
+        Err(err) => println!("      Couldn't query supported configs: {err}"),
+    }
+}
+
+/// One line per sample format and rate range, devices tend to repeat those for every channel
+/// count they support.
+fn format_configs(configs: &[SupportedStreamConfigRange]) -> Vec<String> {
+    let mut groups: Vec<(SampleFormat, u32, u32, Vec<u16>)> = Vec::new();
+    for config in configs {
+        let key = (
+            config.sample_format(),
+            config.min_sample_rate(),
+            config.max_sample_rate(),
+        );
+        match groups
+            .iter_mut()
+            .find(|(format, min, max, _)| (*format, *min, *max) == key)
+        {
+            Some((_, _, _, channels)) => channels.push(config.channels()),
+            None => groups.push((key.0, key.1, key.2, vec![config.channels()])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(format, min_rate, max_rate, mut channels)| {
+            let rate = if min_rate == max_rate {
+                format!("{min_rate} Hz")
+            } else {
+                format!("{min_rate}-{max_rate} Hz")
+            };
+            channels.sort_unstable();
+            channels.dedup();
+            format!("{format}, {rate}, {} channels", format_channels(&channels))
+        })
+        .collect()
+}
+
+/// Lists channel counts, collapsing consecutive counts into ranges like `1-8`.
+fn format_channels(channels: &[u16]) -> String {
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for &count in channels {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == count => *end = count,
+            _ => ranges.push((count, count)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_consecutive_channel_counts() {
+        assert_eq!(format_channels(&[2]), "2");
+        assert_eq!(format_channels(&[1, 2, 3, 4]), "1-4");
+        assert_eq!(format_channels(&[1, 2, 6, 8, 9]), "1-2, 6, 8-9");
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{
+    app::{Devices, Soundboard},
+    cli,
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+/// How long to wait for input before checking for shutdown and finished sounds again.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs the soundboard without a window until SIGINT or SIGTERM.
+pub(super) fn run() -> Result<(), String> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = Arc::clone(&running);
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::Relaxed))
+        .map_err(|err| format!("Couldn't set up the shutdown handler: {err}"))?;
+
+    let config = cli::read_config()?;
+    let devices = Devices::load(&config)?;
+    tracing::info!(
+        mic = %devices.mic,
+        output = %devices.out,
+        virtual_output = %devices.virtual_out,
+        "Starting headless"
+    );
+    let mut soundboard = Soundboard::new(
+        config,
+        &devices.mic.device,
+        &devices.out.device,
+        &devices.virtual_out.device,
+    );
+
+    while running.load(Ordering::Relaxed) {
+        if let Some(input) = soundboard.recv_input_timeout(POLL_INTERVAL) {
+            soundboard.handle_input(input);
+        }
+        soundboard.update();
+    }
+
+    tracing::info!("Shutting down");
+    // stops the streams before the process exits
+    drop(soundboard);
+    Ok(())
+}
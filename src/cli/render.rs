@@ -0,0 +1,227 @@
+use crate::{
+    app::{
+        audio::{AudioDecoder, CHANNELS, EngineConfig, FilterChain, OfflineEngine, WavWriter},
+        config::filter::FilterType,
+    },
+    cli::{self, USAGE, option_value},
+};
+use std::{
+    io, iter,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+const BLOCK_FRAMES: u32 = 1024;
+/// Seconds, longer tails are almost certainly a typo.
+const MAX_TAIL: f32 = 600.0;
+
+pub(super) fn run(args: &[String]) -> Result<(), String> {
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut tail = 0.0f32;
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sample-rate" => sample_rate = option_value(arg, args.next())?,
+            "--tail" => tail = option_value(arg, args.next())?,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let [preset_name, input, output] = positional[..] else {
+        return Err(format!(
+            "render needs a preset, an input and an output\n\n{USAGE}"
+        ));
+    };
+    if sample_rate == 0 || tail.is_nan() || tail < 0.0 {
+        return Err("The sample rate and tail must be positive".to_string());
+    }
+    if tail > MAX_TAIL {
+        return Err(format!("The tail can be at most {MAX_TAIL} seconds"));
+    }
+
+    let config = cli::read_config()?;
+    let preset = config
+        .filter_presets
+        .iter()
+        .find(|preset| preset.name == preset_name)
+        .ok_or_else(|| format!("No filter preset named \"{preset_name}\""))?;
+
+    let mut decoder = AudioDecoder::new(input, sample_rate, 1.0)
+        .map_err(|err| format!("Couldn't open {input}: {err}"))?;
+    let mut writer = WavWriter::create(Path::new(output), sample_rate, CHANNELS as u16)
+        .map_err(|err| format!("Couldn't create {output}: {err}"))?;
+
+    let tail_frames = (tail * sample_rate as f32) as usize;
+    render(
+        preset.enabled_filters(),
+        sample_rate,
+        iter::from_fn(|| decoder.next_sample()),
+        tail_frames,
+        |block| writer.write(block),
+    )
+    .and_then(|()| writer.finish())
+    .map_err(|err| format!("Couldn't write {output}: {err}"))?;
+
+    println!("Rendered {input} with \"{preset_name}\" to {output}");
+    Ok(())
+}
+
+/// Runs interleaved stereo `input` through `filters` block by block, followed by `tail_frames` of
+/// silence, and hands every rendered block to `output`.
+fn render(
+    filters: impl IntoIterator<Item = FilterType>,
+    sample_rate: u32,
+    input: impl Iterator<Item = f32>,
+    tail_frames: usize,
+    mut output: impl FnMut(&[f32]) -> io::Result<()>,
+) -> io::Result<()> {
+    let config = EngineConfig {
+        sample_rate,
+        block_frames: BLOCK_FRAMES,
+    };
+    let filter_chain = Arc::new(Mutex::new(FilterChain::new(sample_rate, CHANNELS)));
+    filter_chain.lock().unwrap().sync(filters);
+
+    let mut engine = OfflineEngine::new(
+        config,
+        Arc::default(),
+        Arc::default(),
+        filter_chain,
+        Arc::new(Mutex::new(FilterChain::new(sample_rate, CHANNELS))),
+        Arc::default(),
+    );
+
+    let mut input = input.chain(iter::repeat_n(0.0, tail_frames * CHANNELS));
+    let mut block = Vec::with_capacity(config.block_samples());
+    loop {
+        block.clear();
+        block.extend(input.by_ref().take(config.block_samples()));
+        if block.is_empty() {
+            return Ok(());
+        }
+
+        let (_, rendered) = engine.process(&block);
+        output(rendered)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, f32::consts::TAU, path::PathBuf};
+
+    const RATE: u32 = 48_000;
+    const FRAMES: usize = 4_800;
+    const TAIL_FRAMES: usize = 2_400;
+
+    /// A decaying mix of a low and a high sine, with the right channel phase shifted.
+    fn input() -> impl Iterator<Item = f32> {
+        (0..FRAMES).flat_map(|frame| {
+            let t = frame as f32 / RATE as f32;
+            let envelope = 1.0 - frame as f32 / FRAMES as f32;
+            [0.0, 0.25].map(|phase| {
+                let low = (TAU * (110.0 * t + phase)).sin();
+                let high = (TAU * (2_500.0 * t + phase)).sin();
+                envelope * (0.6 * low + 0.3 * high)
+            })
+        })
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{name}.wav"))
+    }
+
+    /// Compares the rendered filter against the checked in file, run with `KEYSOUNDS_BLESS=1` to
+    /// update the file after an intentional change.
+    fn assert_matches_golden(name: &str, filter: FilterType) {
+        let mut rendered = Vec::new();
+        render([filter], RATE, input(), TAIL_FRAMES, |block| {
+            rendered.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rendered.len(), (FRAMES + TAIL_FRAMES) * CHANNELS);
+
+        let path = golden_path(name);
+        if env::var_os("KEYSOUNDS_BLESS").is_some() {
+            let mut writer = WavWriter::create(&path, RATE, CHANNELS as u16).unwrap();
+            writer.write(&rendered).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let mut decoder = AudioDecoder::new(path.to_str().unwrap(), RATE, 1.0)
+            .unwrap_or_else(|err| panic!("Couldn't open {}: {err}", path.display()));
+        let golden = iter::from_fn(|| decoder.next_sample()).collect::<Vec<_>>();
+
+        assert_eq!(golden.len(), rendered.len(), "{name} length changed");
+        for (i, (rendered, golden)) in rendered.iter().zip(&golden).enumerate() {
+            assert!(
+                (rendered - golden).abs() < 1e-5,
+                "{name} differs at sample {i}: rendered {rendered}, golden {golden}"
+            );
+        }
+    }
+
+    #[test]
+    fn reverb_matches_golden() {
+        assert_matches_golden(
+            "reverb",
+            FilterType::Reverb {
+                room_size: 0.8,
+                damping: 0.2,
+                wet: 0.3,
+            },
+        );
+    }
+
+    #[test]
+    fn bass_boost_matches_golden() {
+        assert_matches_golden(
+            "bass_boost",
+            FilterType::BassBoost {
+                gain: 4.0,
+                cutoff: 200.0,
+            },
+        );
+    }
+
+    #[test]
+    fn shittify_matches_golden() {
+        assert_matches_golden(
+            "shittify",
+            FilterType::Shittify {
+                strength: 12.0,
+                cutoff: 0.65,
+            },
+        );
+    }
+
+    #[test]
+    fn renders_the_tail() {
+        let mut rendered = Vec::new();
+        render(
+            [FilterType::Reverb {
+                room_size: 0.8,
+                damping: 0.2,
+                wet: 0.3,
+            }],
+            RATE,
+            input(),
+            TAIL_FRAMES,
+            |block| {
+                rendered.extend_from_slice(block);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let tail = &rendered[FRAMES * CHANNELS..];
+        assert!(tail.iter().any(|&sample| sample.abs() > 1e-4));
+    }
+}
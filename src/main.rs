@@ -3,20 +3,44 @@
     gui::{Message, Theme},
 };
 
+use std::{cell::Cell, env, process::ExitCode};
+
 pub mod app;
+mod cli;
 
-fn main() -> iced::Result {
+fn main() -> ExitCode {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(tracing::Level::INFO)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    run_iced_app()
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        return code;
+    }
+
+    let app = match App::new() {
+        Ok(app) => app,
+        Err(err) => {
+            tracing::error!("{err}");
+            eprintln!("Error: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run_iced_app(app) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn run_iced_app() -> iced::Result {
-    iced::application::<App, Message, Theme, iced::Renderer>(App::new, App::update, App::view)
+fn run_iced_app(app: App) -> iced::Result {
+    let app = Cell::new(Some(app));
+    let boot = move || app.take().expect("the app boots once");
+    iced::application::<App, Message, Theme, iced::Renderer>(boot, App::update, App::view)
         .subscription(App::subscription)
         .title("keysounds")
         .window_size(iced::Size::new(420.0, 600.0))